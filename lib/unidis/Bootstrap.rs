@@ -0,0 +1,289 @@
+use crate::Distro;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use strum_macros::{EnumString, EnumVariantNames};
+
+/* PACMAN_REPOS are the repositories written to the pacman.conf(5) handed to
+pacstrap when mirrors are given. */
+const PACMAN_REPOS: [&str; 2] = ["core", "extra"];
+
+/* Method is a bootstrap tool unidis knows to drive. */
+#[derive(Copy, Clone, Debug, PartialEq, EnumString, EnumVariantNames, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Method
+{
+    // Arch Linux, from arch-install-scripts
+    Pacstrap,
+    // Fedora, RHEL and derivatives, through --installroot
+    Dnf,
+    // Debian, Ubuntu and derivatives
+    Debootstrap,
+    // Alpine Linux, through --root and --initdb
+    Apk,
+}
+
+/* Options is what the root directory is bootstrapped from. */
+#[derive(Clone, Debug, Default)]
+pub struct Options
+{
+    // Release to bootstrap, the suite of debootstrap or the releasever of dnf
+    pub release: Option<String>,
+    // Mirrors or local repositories used instead of the configured ones
+    pub mirrors: Vec<String>,
+    // Directory of cached packages, used and filled by the tool
+    pub cache: Option<PathBuf>,
+    // Packages installed on top of the base of the distribution
+    pub packages: Vec<String>,
+    // Whether to check the signatures of the packages
+    pub verify: bool,
+}
+
+/* Error is why a managed root cannot be bootstrapped. */
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "kebab-case")]
+pub enum Error
+{
+    // The name is not fit for a managed root, see `Distro::valid_name`
+    InvalidName
+    {
+        name: String
+    },
+    // A managed root of that name already exists
+    Exists
+    {
+        dir: PathBuf
+    },
+    // The bootstrap tool is missing from the root directory providing it
+    MissingTool
+    {
+        root: PathBuf,
+        method: Method,
+        command: String,
+    },
+    // The method cannot pick a release on its own
+    MissingRelease
+    {
+        method: Method
+    },
+}
+
+/* base() is the set of packages METHOD installs when none is given. */
+fn base(method: Method) -> &'static [&'static str]
+{
+    match method {
+        Method::Pacstrap => &["base"],
+        Method::Dnf => &["@core"],
+        // debootstrap installs the required and important packages itself
+        Method::Debootstrap => &[],
+        Method::Apk => &["alpine-base"],
+    }
+}
+
+/* url() turns MIRROR into a URL, local repositories being given as plain
+absolute paths. */
+fn url(mirror: &str) -> String
+{
+    match mirror.starts_with('/') {
+        true => format!("file://{}", mirror),
+        false => mirror.to_string(),
+    }
+}
+
+/* pacman_conf() is the pacman.conf(5) fetching the repositories of Arch
+Linux from the mirrors of OPTIONS, `$repo` and `$arch` being expanded by
+pacman. */
+fn pacman_conf(options: &Options) -> String
+{
+    let mut conf = String::from("[options]\nArchitecture = auto\n");
+    conf.push_str(match options.verify {
+        true => "SigLevel = Required DatabaseOptional\n",
+        false => "SigLevel = Never\n",
+    });
+    for repo in PACMAN_REPOS.iter() {
+        conf.push_str(&format!("\n[{}]\n", repo));
+        for mirror in &options.mirrors {
+            conf.push_str(&format!("Server = {}\n", url(mirror)));
+        }
+    }
+    conf
+}
+
+/* custom_pacman_conf() is whether OPTIONS call for a pacman.conf(5) of
+their own rather than that of the root directory providing pacstrap. */
+fn custom_pacman_conf(options: &Options) -> bool
+{
+    !options.mirrors.is_empty() || !options.verify
+}
+
+/* root() is the root directory of the managed root kept in DIR. */
+pub fn root(dir: &Path) -> PathBuf
+{
+    dir.join("root")
+}
+
+/* installed() is whether anything was installed to the managed root kept in
+DIR, besides what prepare() put there. */
+pub fn installed(dir: &Path) -> bool
+{
+    root(dir).join("usr").is_dir()
+}
+
+/* prepare() creates the managed root kept in DIR, which must not exist yet,
+along with the configuration METHOD, found in TOOL_ROOT, is handed by
+command(). */
+pub fn prepare(
+    tool_root: &Path,
+    method: Method,
+    dir: &Path,
+    options: &Options,
+) -> Result<(), io::Error>
+{
+    if dir.exists() {
+        return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+    }
+    fs::create_dir_all(root(dir))?;
+
+    match method {
+        // pacstrap only takes the repositories from a pacman.conf(5)
+        Method::Pacstrap if custom_pacman_conf(options) => {
+            fs::write(dir.join("pacman.conf"), pacman_conf(options))?;
+        }
+        // apk looks for its keys and repositories within the managed root,
+        // those of the root directory providing it are carried over
+        Method::Apk => {
+            let apk_dir = root(dir).join("etc").join("apk");
+            fs::create_dir_all(apk_dir.join("keys"))?;
+            if let Ok(keys) = fs::read_dir(tool_root.join("etc/apk/keys")) {
+                for key in keys.filter_map(Result::ok) {
+                    fs::copy(key.path(), apk_dir.join("keys").join(key.file_name()))?;
+                }
+            }
+            let repositories = tool_root.join("etc/apk/repositories");
+            if options.mirrors.is_empty() && repositories.is_file() {
+                fs::copy(repositories, apk_dir.join("repositories"))?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/* command() builds the command line bootstrapping the managed root kept in
+DIR with METHOD, which has to be found in the root directory TOOL_ROOT. */
+pub fn command(
+    tool_root: &Path,
+    method: Method,
+    dir: &Path,
+    options: &Options,
+) -> Result<Vec<String>, Error>
+{
+    let command = match method {
+        Method::Pacstrap => "pacstrap",
+        Method::Dnf => "dnf",
+        Method::Debootstrap => "debootstrap",
+        Method::Apk => "apk",
+    };
+    let release = options.release.as_deref();
+    if release.is_none() && (method == Method::Dnf || method == Method::Debootstrap) {
+        return Err(Error::MissingRelease { method });
+    }
+
+    let executable = match Distro::find_command(tool_root, command) {
+        Some(executable) => executable,
+        None => {
+            return Err(Error::MissingTool {
+                root: tool_root.to_path_buf(),
+                method,
+                command: command.to_string(),
+            })
+        }
+    };
+    let executable = Path::new("/").join(executable.strip_prefix(tool_root).unwrap());
+
+    let target = root(dir).to_string_lossy().into_owned();
+    let cache = options
+        .cache
+        .as_ref()
+        .map(|cache| cache.to_string_lossy().into_owned());
+    let packages = match options.packages.is_empty() {
+        true => base(method)
+            .iter()
+            .map(|package| package.to_string())
+            .collect(),
+        false => options.packages.clone(),
+    };
+
+    let mut argv = vec![executable.to_string_lossy().into_owned()];
+    match method {
+        Method::Pacstrap => {
+            if custom_pacman_conf(options) {
+                let conf = dir.join("pacman.conf").to_string_lossy().into_owned();
+                argv.extend(vec!["-C".to_string(), conf]);
+            }
+            // A keyring of its own is initialised within the managed root
+            argv.extend(vec!["-K".to_string(), target]);
+            argv.extend(packages);
+            // Whatever follows the packages is handed to pacman itself
+            if let Some(cache) = cache {
+                argv.extend(vec!["--cachedir".to_string(), cache]);
+            }
+        }
+        Method::Dnf => {
+            argv.push(format!("--installroot={}", target));
+            argv.push(format!("--releasever={}", release.unwrap()));
+            argv.push("-y".to_string());
+            if let Some(cache) = cache {
+                argv.push(format!("--setopt=cachedir={}", cache));
+                argv.push("--setopt=keepcache=True".to_string());
+            }
+            if !options.mirrors.is_empty() {
+                argv.push("--disablerepo=*".to_string());
+            }
+            for (index, mirror) in options.mirrors.iter().enumerate() {
+                argv.push(format!("--repofrompath=unidis-{},{}", index, url(mirror)));
+                argv.push(format!("--enablerepo=unidis-{}", index));
+            }
+            if !options.verify {
+                argv.push("--nogpgcheck".to_string());
+            }
+            argv.push("install".to_string());
+            argv.extend(packages);
+        }
+        Method::Debootstrap => {
+            if let Some(cache) = cache {
+                argv.push(format!("--cache-dir={}", cache));
+            }
+            if !packages.is_empty() {
+                argv.push(format!("--include={}", packages.join(",")));
+            }
+            if !options.verify {
+                argv.push("--no-check-gpg".to_string());
+            }
+            argv.extend(vec![release.unwrap().to_string(), target]);
+            // debootstrap fetches from a single mirror
+            argv.extend(options.mirrors.first().map(|mirror| url(mirror)));
+        }
+        Method::Apk => {
+            argv.extend(vec!["--root".to_string(), target, "--initdb".to_string()]);
+            argv.push("--update-cache".to_string());
+            if let Some(cache) = cache {
+                argv.extend(vec!["--cache-dir".to_string(), cache]);
+            }
+            for mirror in &options.mirrors {
+                argv.extend(vec!["--repository".to_string(), url(mirror)]);
+            }
+            if !options.verify {
+                argv.push("--allow-untrusted".to_string());
+            }
+            argv.push("add".to_string());
+            argv.extend(packages);
+        }
+    }
+
+    Ok(argv)
+}
@@ -0,0 +1,123 @@
+use crate::Libc::{self, Capability::cap_user_data, *};
+
+use libc::{
+    c_ulong, PR_CAPBSET_DROP, PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL, PR_CAP_AMBIENT_RAISE,
+    PR_SET_NO_NEW_PRIVS,
+};
+use std::fs;
+
+/* CAPABILITIES are the names of the capabilities known to us, indexed by
+their number, see capabilities(7). */
+pub const CAPABILITIES: &[&str] = &[
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+
+/* ALL is the set of every capability known to us. */
+pub const ALL: u64 = (1 << CAPABILITIES.len() as u64) - 1;
+
+/* parse() converts the capability NAME, with or without its `CAP_` prefix,
+to a set, `ALL` standing for every capability. */
+pub fn parse(name: &str) -> Option<u64>
+{
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("CAP_").unwrap_or(&name);
+    if name == "ALL" {
+        return Some(ALL);
+    }
+
+    CAPABILITIES
+        .iter()
+        .position(|capability| *capability == name)
+        .map(|cap| 1 << cap)
+}
+
+/* last_cap() retrieves the highest capability supported by the running
+kernel, which may be older or newer than us. */
+fn last_cap() -> u64
+{
+    fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|last_cap| last_cap.trim().parse().ok())
+        .unwrap_or(CAPABILITIES.len() as u64 - 1)
+}
+
+/* restrict() reduces the capabilities of the calling thread to KEEP for
+good: every other capability is dropped from the bounding set, KEEP is
+raised as ambient so that it survives `execve` as an unprivileged user, and
+no_new_privs is set so that nothing is regained through set-user-ID or
+file capabilities. */
+pub(crate) fn restrict(keep: u64) -> SyscallResult
+{
+    // Dropping from the bounding set requires CAP_SETPCAP, so it comes
+    // before the capabilities themselves
+    let last_cap = last_cap();
+    for cap in 0..=last_cap {
+        if cap >= 64 || keep & (1 << cap) == 0 {
+            Libc::prctl(PR_CAPBSET_DROP, cap as c_ulong, 0)?;
+        }
+    }
+    Libc::prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL as c_ulong, 0)?;
+
+    let mut data = [cap_user_data::default(); 2];
+    for (i, data) in data.iter_mut().enumerate() {
+        let set = (keep >> (32 * i)) as u32;
+        data.effective = set;
+        data.permitted = set;
+        data.inheritable = set;
+    }
+    Libc::capset(&data)?;
+
+    // Ambient capabilities must be both permitted and inheritable, which
+    // those unknown to the running kernel cannot be
+    for cap in 0..CAPABILITIES.len().min(last_cap as usize + 1) as u64 {
+        if keep & (1 << cap) != 0 {
+            Libc::prctl(
+                PR_CAP_AMBIENT,
+                PR_CAP_AMBIENT_RAISE as c_ulong,
+                cap as c_ulong,
+            )?;
+        }
+    }
+
+    Libc::prctl(PR_SET_NO_NEW_PRIVS, 1, 0)
+}
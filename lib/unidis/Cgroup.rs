@@ -0,0 +1,319 @@
+use std::{
+    fs::{self, File},
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/* SLICE is the cgroup grouping the sessions, beneath the delegated
+subtree. */
+const SLICE: &str = "unidis.slice";
+
+/* Limits are the resource limits of a cgroup, None leaves the limit
+untouched. */
+#[derive(Debug, Default)]
+pub struct Limits
+{
+    // MEMORY_MAX is in bytes
+    pub memory_max: Option<u64>,
+    // CPU_WEIGHT is in the range [1, 10000], the default being 100
+    pub cpu_weight: Option<u64>,
+    pub pids_max: Option<u64>,
+}
+
+impl Limits
+{
+    /* controllers() lists the controllers required to enforce the limits. */
+    fn controllers(&self) -> Vec<&'static str>
+    {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        if self.cpu_weight.is_some() {
+            controllers.push("cpu");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+
+        controllers
+    }
+}
+
+/* Cgroup is a leaf cgroup holding a single session. */
+pub struct Cgroup
+{
+    path: PathBuf,
+    // DIR is passed to `clone3` along CLONE_INTO_CGROUP
+    dir: File,
+}
+
+/* parse_mountinfo() finds where the cgroup v2 hierarchy is mounted in
+MOUNTINFO, see proc(5). */
+fn parse_mountinfo(mountinfo: &str) -> Option<PathBuf>
+{
+    // The filesystem type follows the optional fields, which are terminated
+    // by a single hyphen
+    mountinfo.lines().find_map(|line| {
+        let mut fields = line.split(' ');
+        let mountpoint = fields.nth(4)?;
+        let mut fields = fields.skip_while(|field| *field != "-").skip(1);
+        match fields.next() {
+            Some("cgroup2") => Some(PathBuf::from(mountpoint)),
+            _ => None,
+        }
+    })
+}
+
+/* mountpoint() finds where the cgroup v2 hierarchy is mounted, which is
+not necessarily /sys/fs/cgroup on hybrid systems. */
+fn mountpoint() -> Result<PathBuf, io::Error>
+{
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    parse_mountinfo(&mountinfo)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cgroup v2 is not mounted"))
+}
+
+/* parse_cgroup() retrieves the cgroup the sessions are created under from
+the cgroup(7) membership CGROUP of the process, relative to the root of the
+hierarchy.  A cgroup cannot hold processes and enable controllers for its
+children at once, so it is the parent of our own cgroup, which is in turn
+expected to be delegated to us, as with the user slice of systemd. */
+fn parse_cgroup(cgroup: &str) -> Option<PathBuf>
+{
+    let cgroup = cgroup.lines().find_map(|line| line.strip_prefix("0::"))?;
+
+    // The root cgroup is exempt from the rule above
+    let cgroup = Path::new(cgroup);
+    let cgroup = cgroup.parent().unwrap_or(cgroup);
+    Some(cgroup.strip_prefix("/").ok()?.to_path_buf())
+}
+
+/* delegated() retrieves the cgroup the sessions are created under, see
+`parse_cgroup`. */
+fn delegated() -> Result<PathBuf, io::Error>
+{
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+    let cgroup = parse_cgroup(&cgroup)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not in a cgroup v2 hierarchy"))?;
+
+    Ok(mountpoint()?.join(cgroup))
+}
+
+/* enable() enables CONTROLLERS for the children of the cgroup PATH. */
+fn enable(path: &Path, controllers: &[&str]) -> Result<(), io::Error>
+{
+    let available = fs::read_to_string(path.join("cgroup.controllers"))?;
+    for controller in controllers {
+        if !available
+            .split_whitespace()
+            .any(|available| available == *controller)
+        {
+            return Err(io::Error::other(format!(
+                "controller {} is not delegated to {:?}",
+                controller, path
+            )));
+        }
+    }
+
+    if controllers.is_empty() {
+        return Ok(());
+    }
+
+    let controllers = controllers
+        .iter()
+        .map(|controller| format!("+{}", controller))
+        .collect::<Vec<_>>()
+        .join(" ");
+    fs::write(path.join("cgroup.subtree_control"), controllers)
+}
+
+/* parse_key() parses KEY out of the flat keyed file CONTENTS, see
+cgroups(7). */
+fn parse_key(contents: &str, key: &str) -> Option<u64>
+{
+    contents.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some(name), Some(value)) if name == key => value.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/* read_key() reads KEY out of the flat keyed file PATH. */
+fn read_key(path: &Path, key: &str) -> Option<u64>
+{
+    parse_key(&fs::read_to_string(path).ok()?, key)
+}
+
+/* parse_value() parses the single value file CONTENTS, e.g. memory.peak,
+which is `max` when unlimited. */
+fn parse_value(contents: &str) -> Option<u64>
+{
+    contents.trim().parse().ok()
+}
+
+impl Cgroup
+{
+    /* create() creates the cgroup NAME enforcing LIMITS. */
+    pub fn create(name: &str, limits: &Limits) -> Result<Self, io::Error>
+    {
+        let delegated = delegated()?;
+        let controllers = limits.controllers();
+
+        let slice = delegated.join(SLICE);
+        match fs::create_dir(&slice) {
+            Err(error) if error.kind() != io::ErrorKind::AlreadyExists => return Err(error),
+            _ => {}
+        }
+        enable(&delegated, &controllers)?;
+        enable(&slice, &controllers)?;
+
+        let path = slice.join(name);
+        fs::create_dir(&path)?;
+        let cgroup = Cgroup {
+            dir: File::open(&path)?,
+            path,
+        };
+
+        let mut res = Ok(());
+        if let Some(memory_max) = limits.memory_max {
+            res =
+                res.and_then(|_| fs::write(cgroup.path.join("memory.max"), memory_max.to_string()));
+        }
+        if let Some(cpu_weight) = limits.cpu_weight {
+            res =
+                res.and_then(|_| fs::write(cgroup.path.join("cpu.weight"), cpu_weight.to_string()));
+        }
+        if let Some(pids_max) = limits.pids_max {
+            res = res.and_then(|_| fs::write(cgroup.path.join("pids.max"), pids_max.to_string()));
+        }
+        if let Err(error) = res {
+            let _ = cgroup.remove();
+            return Err(error);
+        }
+
+        Ok(cgroup)
+    }
+
+    /* path() retrieves the path of the cgroup. */
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    /* fd() retrieves the file descriptor of the cgroup directory. */
+    pub fn fd(&self) -> i32
+    {
+        self.dir.as_raw_fd()
+    }
+
+    /* report() prints the resources used by the cgroup over its lifetime. */
+    pub fn report(&self)
+    {
+        let peak = fs::read_to_string(self.path.join("memory.peak"));
+        if let Some(peak) = peak.ok().and_then(|peak| parse_value(&peak)) {
+            println!(
+                "Peak memory usage: {:.1} MiB",
+                peak as f64 / (1 << 20) as f64
+            );
+        }
+
+        let cpu_stat = self.path.join("cpu.stat");
+        if let Some(usage) = read_key(&cpu_stat, "usage_usec") {
+            println!(
+                "CPU usage: {:.3}s (user {:.3}s, system {:.3}s)",
+                usage as f64 / 1e6,
+                read_key(&cpu_stat, "user_usec").unwrap_or(0) as f64 / 1e6,
+                read_key(&cpu_stat, "system_usec").unwrap_or(0) as f64 / 1e6
+            );
+        }
+    }
+
+    /* remove() removes the cgroup, which must no longer hold any process.
+    The slice is removed along with it if no other session is left in it. */
+    pub fn remove(&self) -> Result<(), io::Error>
+    {
+        remove(&self.path)
+    }
+}
+
+/* remove() removes the cgroup of a session at PATH, see `Cgroup::remove`. */
+pub fn remove(path: &Path) -> Result<(), io::Error>
+{
+    match fs::remove_dir(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+
+    if let Some(slice) = path.parent() {
+        let _ = fs::remove_dir(slice);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn mountinfo()
+    {
+        // A hybrid hierarchy, with cgroup v1 controllers alongside
+        let mountinfo = "\
+22 1 0:21 / /sys rw,nosuid,nodev,noexec,relatime shared:7 - sysfs sysfs rw
+26 22 0:23 / /sys/fs/cgroup ro,nosuid,nodev,noexec shared:9 - tmpfs tmpfs ro,mode=755
+27 26 0:24 / /sys/fs/cgroup/unified rw,nosuid,relatime shared:10 - cgroup2 cgroup2 rw
+28 26 0:25 / /sys/fs/cgroup/memory rw,nosuid,relatime shared:11 - cgroup cgroup rw,memory
+";
+        assert_eq!(
+            parse_mountinfo(mountinfo),
+            Some(PathBuf::from("/sys/fs/cgroup/unified"))
+        );
+
+        // Mounts without optional fields
+        let mountinfo = "35 24 0:30 / /sys/fs/cgroup rw,nosuid - cgroup2 cgroup2 rw,nsdelegate\n";
+        assert_eq!(parse_mountinfo(mountinfo), Some(PathBuf::from("/sys/fs/cgroup")));
+
+        let mountinfo = "28 26 0:25 / /sys/fs/cgroup/memory rw - cgroup cgroup rw,memory\n";
+        assert_eq!(parse_mountinfo(mountinfo), None);
+    }
+
+    #[test]
+    fn cgroup()
+    {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/term.scope\n";
+        assert_eq!(
+            parse_cgroup(cgroup),
+            Some(PathBuf::from("user.slice/user-1000.slice/user@1000.service/app.slice"))
+        );
+
+        // The cgroup v1 hierarchies are skipped
+        let cgroup = "12:memory:/user.slice\n1:name=systemd:/user.slice\n0::/init.scope\n";
+        assert_eq!(parse_cgroup(cgroup), Some(PathBuf::new()));
+        assert_eq!(parse_cgroup("0::/\n"), Some(PathBuf::new()));
+        assert_eq!(parse_cgroup("1:name=systemd:/\n"), None);
+    }
+
+    #[test]
+    fn stat()
+    {
+        let cpu_stat = "\
+usage_usec 1503029
+user_usec 1002003
+system_usec 501026
+nr_periods 0
+";
+        assert_eq!(parse_key(cpu_stat, "usage_usec"), Some(1503029));
+        assert_eq!(parse_key(cpu_stat, "system_usec"), Some(501026));
+        assert_eq!(parse_key(cpu_stat, "usage"), None);
+        assert_eq!(parse_key("usage_usec\n", "usage_usec"), None);
+
+        assert_eq!(parse_value("104857600\n"), Some(104857600));
+        assert_eq!(parse_value("max\n"), None);
+    }
+}
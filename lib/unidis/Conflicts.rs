@@ -0,0 +1,272 @@
+use serde::Serialize;
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+
+/* FileKind is the type of a file as observed through `lstat`. */
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind
+{
+    Directory,
+    File,
+    Symlink,
+    Other,
+}
+
+impl From<&Metadata> for FileKind
+{
+    fn from(metadata: &Metadata) -> Self
+    {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            FileKind::Directory
+        } else if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_file() {
+            FileKind::File
+        } else {
+            FileKind::Other
+        }
+    }
+}
+
+/* Conflict describes a path of LEFT that is hidden by RIGHT in the union.
+PATH is always absolute with respect to the unioned root. */
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Conflict
+{
+    // Both sides provide the same non-directory path, so the file from
+    // RIGHT is the only one visible
+    Shadowed
+    {
+        path: PathBuf,
+        left: FileKind,
+        right: FileKind,
+    },
+    // Only one side is a directory, RIGHT wins and in the case LEFT is the
+    // directory, its entire subtree becomes unreachable
+    DirectoryFile
+    {
+        path: PathBuf,
+        left: FileKind,
+        right: FileKind,
+    },
+    // Both sides are symbolic links, but they point to different targets
+    Symlink
+    {
+        path: PathBuf,
+        left: PathBuf,
+        right: PathBuf,
+    },
+    // The path could not be compared, e.g. as a directory of the host is
+    // not readable, the walk carries on past it
+    Unreadable
+    {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+/* lstat() is `fs::symlink_metadata` where a missing file is not an error. */
+fn lstat(path: &Path) -> Result<Option<Metadata>, io::Error>
+{
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/* walk() compares the directory RELATIVE of LEFT against RIGHT, pushing
+every conflict found onto CONFLICTS.  Only failing to read RELATIVE itself
+is an error, whatever fails beneath it is reported as unreadable. */
+fn walk(
+    left: &Path,
+    right: &Path,
+    relative: &Path,
+    conflicts: &mut Vec<Conflict>,
+) -> Result<(), io::Error>
+{
+    let unreadable = |path: PathBuf, error: io::Error| Conflict::Unreadable {
+        path,
+        error: error.to_string(),
+    };
+
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(left.join(relative))? {
+        match entry {
+            Ok(entry) => entries.push(entry),
+            Err(error) => conflicts.push(unreadable(Path::new("/").join(relative), error)),
+        }
+    }
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let relative = relative.join(entry.file_name());
+        let path = Path::new("/").join(&relative);
+
+        // Anything missing from RIGHT falls through to LEFT untouched
+        let right_metadata = match lstat(&right.join(&relative)) {
+            Ok(None) => continue,
+            Ok(Some(metadata)) => metadata,
+            Err(error) => {
+                conflicts.push(unreadable(path, error));
+                continue;
+            }
+        };
+        let left_metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                conflicts.push(unreadable(path, error));
+                continue;
+            }
+        };
+
+        let left_kind = FileKind::from(&left_metadata);
+        let right_kind = FileKind::from(&right_metadata);
+        match (left_kind, right_kind) {
+            // Directories are merged, so only their contents can conflict
+            (FileKind::Directory, FileKind::Directory) => {
+                if let Err(error) = walk(left, right, &relative, conflicts) {
+                    conflicts.push(unreadable(path, error));
+                }
+            }
+            (FileKind::Directory, _) | (_, FileKind::Directory) => {
+                conflicts.push(Conflict::DirectoryFile {
+                    path,
+                    left: left_kind,
+                    right: right_kind,
+                });
+            }
+            (FileKind::Symlink, FileKind::Symlink) => {
+                let left_target = fs::read_link(left.join(&relative));
+                let right_target = fs::read_link(right.join(&relative));
+                match (left_target, right_target) {
+                    (Ok(left_target), Ok(right_target)) => {
+                        if left_target != right_target {
+                            conflicts.push(Conflict::Symlink {
+                                path,
+                                left: left_target,
+                                right: right_target,
+                            });
+                        }
+                    }
+                    (Err(error), _) | (_, Err(error)) => conflicts.push(unreadable(path, error)),
+                }
+            }
+            _ => {
+                conflicts.push(Conflict::Shadowed {
+                    path,
+                    left: left_kind,
+                    right: right_kind,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/* conflicts() walks LEFT and reports every path which RIGHT takes precedence
+over, resolving the union the same way the union filesystems do, RIGHT
+(read-write) is always looked up before LEFT (read-only). */
+pub fn conflicts(left: &Path, right: &Path) -> Result<Vec<Conflict>, io::Error>
+{
+    let mut conflicts = Vec::new();
+    walk(left, right, Path::new(""), &mut conflicts)?;
+
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::{ffi::CString, os::unix::fs::symlink};
+    use tempdir::TempDir;
+
+    /* deep() creates DEPTH nested directories named NAME beneath DIR, which
+    resolve to a path longer than PATH_MAX past some depth.  They are made
+    one at a time relative to their parent, as their paths cannot be used. */
+    fn deep(dir: &Path, name: &str, depth: usize)
+    {
+        let name = CString::new(name).unwrap();
+        let dir = CString::new(dir.to_str().unwrap()).unwrap();
+        let mut fd = unsafe { libc::open(dir.as_ptr(), libc::O_PATH | libc::O_DIRECTORY) };
+        for _ in 0..depth {
+            assert_eq!(unsafe { libc::mkdirat(fd, name.as_ptr(), 0o755) }, 0);
+            let next = unsafe { libc::openat(fd, name.as_ptr(), libc::O_PATH | libc::O_DIRECTORY) };
+            unsafe { libc::close(fd) };
+            assert!(next >= 0);
+            fd = next;
+        }
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn conflicts()
+    {
+        let left = TempDir::new("unidis-left").unwrap();
+        let right = TempDir::new("unidis-right").unwrap();
+        let (left, right) = (left.path(), right.path());
+
+        fs::create_dir_all(left.join("etc")).unwrap();
+        fs::create_dir_all(right.join("etc")).unwrap();
+        fs::write(left.join("etc/hostname"), "left").unwrap();
+        fs::write(right.join("etc/hostname"), "right").unwrap();
+        // Paths of LEFT alone are not conflicts
+        fs::write(left.join("etc/os-release"), "").unwrap();
+        fs::create_dir(left.join("opt")).unwrap();
+        fs::write(right.join("opt"), "").unwrap();
+        symlink("a", left.join("link")).unwrap();
+        symlink("b", right.join("link")).unwrap();
+        // Whoever runs the tests, a path past PATH_MAX cannot be looked up
+        let name = "d".repeat(200);
+        deep(left, &name, 24);
+        deep(right, &name, 24);
+
+        let conflicts = super::conflicts(left, right).unwrap();
+        assert_eq!(conflicts.len(), 4, "{:?}", conflicts);
+        match &conflicts[0] {
+            Conflict::Unreadable { path, .. } => {
+                assert!(path.starts_with(Path::new("/").join(&name)));
+                assert!(path.as_os_str().len() > libc::PATH_MAX as usize - left.as_os_str().len());
+            }
+            conflict => panic!("unexpected {:?}", conflict),
+        }
+        match &conflicts[1] {
+            Conflict::Shadowed { path, left, right } => {
+                assert_eq!(path, Path::new("/etc/hostname"));
+                assert!(matches!((left, right), (FileKind::File, FileKind::File)));
+            }
+            conflict => panic!("unexpected {:?}", conflict),
+        }
+        assert!(matches!(
+            &conflicts[2],
+            Conflict::Symlink { path, left, right }
+                if path == Path::new("/link") && left == Path::new("a") && right == Path::new("b")
+        ));
+        assert!(matches!(
+            &conflicts[3],
+            Conflict::DirectoryFile { path, left: FileKind::Directory, right: FileKind::File }
+                if path == Path::new("/opt")
+        ));
+
+        let json = serde_json::to_value(&conflicts).unwrap();
+        assert_eq!(json[0]["kind"], "unreadable");
+        assert_eq!(
+            json[1],
+            serde_json::json!({
+                "kind": "shadowed",
+                "path": "/etc/hostname",
+                "left": "file",
+                "right": "file",
+            })
+        );
+        assert_eq!(json[3]["kind"], "directory-file");
+    }
+}
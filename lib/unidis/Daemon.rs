@@ -0,0 +1,662 @@
+use crate::{
+    unidis, unidis_attrs,
+    Capabilities, Env, Landlock,
+    Libc::{self, *},
+    Ownership, Seccomp,
+    Session::{pidfd_wait, runtime_dir, Session},
+    Share,
+    UnionFS::SupportedUnionFS,
+    CLEAR_ENV, FAKE_OWNERSHIP, HOLD, PREPEND_PATH, UPDATE_PATH,
+};
+
+use libc::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::CString,
+    fs::{self, DirBuilder, Permissions},
+    io::{self, Read, Write},
+    iter, marker, mem,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, PermissionsExt},
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    ptr,
+    time::{Duration, Instant},
+};
+
+/* WARM_TIMEOUT is the time a warm session is given to be set up. */
+const WARM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/* REQUEST_TIMEOUT is the time a client is given to send its request, as it
+is read by the daemon itself. */
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/* Request is a command to run within the warm session matching the union
+it describes.  The standard file descriptors of the client are passed
+alongside it. */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request
+{
+    pub left: PathBuf,
+    pub right: PathBuf,
+    pub unionfs: SupportedUnionFS,
+    // FLAGS are the same as `unidis_attrs`, UPDATE_PATH, PREPEND_PATH,
+    // CLEAR_ENV and FAKE_OWNERSHIP are applied to the command rather than
+    // the session
+    pub flags: u64,
+    pub hostname: Option<String>,
+    pub time_offset: i64,
+    pub memory_max: u64,
+    pub cpu_weight: u64,
+    pub pids_max: u64,
+    pub masks: Option<String>,
+    pub host_files: Option<String>,
+    pub host_files_skip: Vec<PathBuf>,
+    // SHARE is bound into the session, while its variables are set for the
+    // command from ENV
+    pub share: Vec<String>,
+    // SECCOMP applies to the command rather than the session
+    pub seccomp: Option<String>,
+    // CAPS apply to the command rather than the session
+    pub caps: u64,
+    // LANDLOCK, LANDLOCK_READ and LANDLOCK_WRITE apply to the command
+    // rather than the session
+    pub landlock: Option<String>,
+    pub landlock_read: Vec<PathBuf>,
+    pub landlock_write: Vec<PathBuf>,
+    pub argv: Vec<String>,
+    pub cwd: PathBuf,
+    // ENV is the environment of the client, ENV_PROFILE derives the one of
+    // the command from it
+    pub env: Vec<(String, String)>,
+    pub env_profile: Env::Profile,
+}
+
+/* Response is the outcome of a request, either the wait status of the
+command or the reason it could not be run. */
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Response
+{
+    Status(c_int),
+    Error(String),
+}
+
+/* socket_path() retrieves the path the daemon listens on. */
+pub fn socket_path() -> PathBuf
+{
+    runtime_dir().with_file_name("daemon.sock")
+}
+
+/* session_flags() retrieves the flags of FLAGS which apply to the session
+rather than the command. */
+fn session_flags(flags: u64) -> u64
+{
+    flags & !(UPDATE_PATH | PREPEND_PATH | CLEAR_ENV | FAKE_OWNERSHIP)
+}
+
+/* warm_id() names the warm session backing REQUEST, requests describing
+the same union share the same session. */
+fn warm_id(request: &Request) -> String
+{
+    let key = format!(
+        "{:?}\0{:?}\0{:?}\0{}\0{:?}\0{}\0{}\0{}\0{}\0{:?}\0{:?}\0{:?}\0{:?}",
+        request.left,
+        request.right,
+        request.unionfs,
+        session_flags(request.flags),
+        request.hostname,
+        request.time_offset,
+        request.memory_max,
+        request.cpu_weight,
+        request.pids_max,
+        request.masks,
+        request.host_files,
+        request.host_files_skip,
+        request.share
+    );
+
+    // FNV-1a, the name only has to be stable across runs
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("warm-{:016x}", hash)
+}
+
+/* write_message() sends MESSAGE as JSON, prefixed by its length. */
+fn write_message<T: Serialize>(stream: &mut UnixStream, message: &T) -> Result<(), io::Error>
+{
+    let message = serde_json::to_vec(message).unwrap();
+    stream.write_all(&(message.len() as u32).to_le_bytes())?;
+    stream.write_all(&message)
+}
+
+/* read_body() reads a JSON message of LEN bytes. */
+fn read_body<T: DeserializeOwned>(stream: &mut UnixStream, len: u32) -> Result<T, io::Error>
+{
+    let mut message = vec![0u8; len as usize];
+    stream.read_exact(&mut message)?;
+    serde_json::from_slice(&message)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/* read_message() receives a message sent through `write_message`. */
+fn read_message<T: DeserializeOwned>(stream: &mut UnixStream) -> Result<T, io::Error>
+{
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    read_body(stream, u32::from_le_bytes(len))
+}
+
+/* request() runs REQUEST through the daemon with the standard file
+descriptors of the calling process, returning the wait status of the
+command. */
+pub fn request(request: &Request) -> Result<c_int, io::Error>
+{
+    let mut stream = UnixStream::connect(socket_path())?;
+
+    // The file descriptors ride along the length of the request, so that
+    // they are received before anything else
+    let body = serde_json::to_vec(request).unwrap();
+    let len = (body.len() as u32).to_le_bytes();
+    Libc::send_fds(stream.as_raw_fd(), &len, &[0, 1, 2]).map_err(io::Error::from_raw_os_error)?;
+    stream.write_all(&body)?;
+
+    match read_message(&mut stream)? {
+        Response::Status(status) => Ok(status),
+        Response::Error(error) => Err(io::Error::other(error)),
+    }
+}
+
+/* Daemon keeps warm sessions alive and spawns commands into them. */
+struct Daemon
+{
+    listener: UnixListener,
+    signalfd: c_int,
+    mask: sigset_t,
+    // WARM maps the warm sessions started by the daemon to their owner
+    warm: HashMap<String, pid_t>,
+}
+
+impl Daemon
+{
+    /* detach() releases the resources of the daemon inherited by a child
+    process. */
+    fn detach(&self)
+    {
+        unsafe {
+            close(self.listener.as_raw_fd());
+            close(self.signalfd);
+            sigprocmask(SIG_UNBLOCK, &self.mask, ptr::null_mut());
+        }
+    }
+
+    /* warm_session() retrieves the ID and owner of the warm session matching
+    REQUEST, starting it if it is not already running.  It does not wait for
+    the session to be set up, see `wait_session`.  CLIENT are the file
+    descriptors of the client, which the session must not keep open. */
+    fn warm_session(&mut self, request: &Request, client: &[c_int])
+        -> Result<(String, pid_t), io::Error>
+    {
+        let id = warm_id(request);
+        if let Some(owner) = self.warm.get(&id) {
+            return Ok((id, *owner));
+        }
+        if let Ok(session) = Session::load(&id) {
+            if session.is_alive() {
+                return Ok((id, session.owner));
+            }
+        }
+
+        let left = CString::new(request.left.as_os_str().as_bytes())?;
+        let right = CString::new(request.right.as_os_str().as_bytes())?;
+        let session = CString::new(id.as_bytes())?;
+        let hostname = match &request.hostname {
+            Some(hostname) => Some(CString::new(hostname.as_bytes())?),
+            None => None,
+        };
+        let masks = match &request.masks {
+            Some(masks) => Some(CString::new(masks.as_bytes())?),
+            None => None,
+        };
+        let host_files = match &request.host_files {
+            Some(host_files) => Some(CString::new(host_files.as_bytes())?),
+            None => None,
+        };
+        let host_files_skip = request
+            .host_files_skip
+            .iter()
+            .map(|path| CString::new(path.as_os_str().as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let host_files_skip = host_files_skip
+            .iter()
+            .map(|path| path.as_ptr())
+            .chain(iter::once(ptr::null()))
+            .collect::<Vec<_>>();
+        let share = request
+            .share
+            .iter()
+            .map(|preset| CString::new(preset.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let share = share
+            .iter()
+            .map(|preset| preset.as_ptr())
+            .chain(iter::once(ptr::null()))
+            .collect::<Vec<_>>();
+        let argv = [ptr::null()];
+
+        let owner = match Libc::fork().map_err(io::Error::from_raw_os_error)? {
+            0 => {
+                self.detach();
+
+                // The client waits for its standard file descriptors and
+                // the connection to be closed, which the session outlives
+                for fd in client {
+                    unsafe { close(*fd) };
+                }
+
+                // The session outlives the request, so it should not
+                // depend on the directory the daemon was started from
+                env::set_current_dir("/").unwrap();
+
+                // What is shared is looked up from the environment of the
+                // client rather than the one of the daemon
+                Env::apply(&request.env);
+
+                let unidis_attrs = unidis_attrs {
+                    _phantom: marker::PhantomData,
+                    left: left.as_ptr(),
+                    right: right.as_ptr(),
+                    argc: 0,
+                    argv: argv.as_ptr(),
+                    flags: session_flags(request.flags) | HOLD,
+                    unionfs: request.unionfs,
+                    session: session.as_ptr(),
+                    hostname: hostname.as_ref().map_or(ptr::null(), |hostname| hostname.as_ptr()),
+                    time_offset: request.time_offset,
+                    memory_max: request.memory_max,
+                    cpu_weight: request.cpu_weight,
+                    pids_max: request.pids_max,
+                    seccomp: ptr::null(),
+                    caps: 0,
+                    landlock: ptr::null(),
+                    landlock_read: ptr::null(),
+                    landlock_write: ptr::null(),
+                    masks: masks.as_ref().map_or(ptr::null(), |masks| masks.as_ptr()),
+                    env: ptr::null(),
+                    env_keep: ptr::null(),
+                    env_files: ptr::null(),
+                    env_unset: ptr::null(),
+                    env_set: ptr::null(),
+                    nix_env: ptr::null(),
+                    host_files: host_files
+                        .as_ref()
+                        .map_or(ptr::null(), |host_files| host_files.as_ptr()),
+                    host_files_skip: host_files_skip.as_ptr(),
+                    share: share.as_ptr(),
+                };
+                unsafe { _exit(unidis(&unidis_attrs) as c_int) }
+            }
+            owner => owner as pid_t,
+        };
+        self.warm.insert(id.clone(), owner);
+
+        Ok((id, owner))
+    }
+
+    /* accept() serves the client connected through STREAM, the session is
+    waited on and the command spawned and waited on by a child, so that the
+    daemon is never blocked by either. */
+    fn accept(&mut self, mut stream: UnixStream) -> Result<(), io::Error>
+    {
+        // A client stalling on its request must not hold up the others
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut len = [0u8; 4];
+        let (received, fds) = Libc::recv_fds(stream.as_raw_fd(), &mut len, 3)
+            .map_err(io::Error::from_raw_os_error)?;
+
+        let res = stream
+            .read_exact(&mut len[received..])
+            .and_then(|_| read_body::<Request>(&mut stream, u32::from_le_bytes(len)))
+            .and_then(|request| {
+                if fds.len() != 3 || request.argv.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "malformed request",
+                    ));
+                }
+                stream.set_read_timeout(None)?;
+                stream.set_write_timeout(None)?;
+                let client = fds
+                    .iter()
+                    .copied()
+                    .chain(iter::once(stream.as_raw_fd()))
+                    .collect::<Vec<_>>();
+                let (id, owner) = self.warm_session(&request, &client)?;
+
+                // The owner is pinned while it is still a child of the
+                // daemon, it cannot be reaped before this returns
+                let pidfd = Libc::pidfd_open(owner, 0).map_err(io::Error::from_raw_os_error)?;
+                let pidfd = pidfd as c_int;
+                let res = match Libc::fork().map_err(io::Error::from_raw_os_error) {
+                    Ok(0) => {
+                        self.detach();
+                        let response = match wait_session(&id, owner, pidfd) {
+                            Ok(session) => match spawn(&session, &request, &fds, &stream) {
+                                Ok(status) => Response::Status(status as c_int),
+                                Err(errno) => Response::Error(
+                                    io::Error::from_raw_os_error(errno).to_string(),
+                                ),
+                            },
+                            Err(error) => Response::Error(error.to_string()),
+                        };
+                        let _ = write_message(&mut stream, &response);
+                        unsafe { _exit(0) }
+                    }
+                    Ok(_) => Ok(()),
+                    Err(error) => Err(error),
+                };
+                unsafe { close(pidfd) };
+                res
+            });
+
+        for fd in fds {
+            unsafe { close(fd) };
+        }
+
+        if let Err(error) = &res {
+            let _ = write_message(&mut stream, &Response::Error(error.to_string()));
+        }
+        res
+    }
+
+    /* shutdown() stops the warm sessions started by the daemon. */
+    fn shutdown(&mut self)
+    {
+        for id in self.warm.keys() {
+            if let Ok(session) = Session::load(id) {
+                println!("Stopping session {}", id);
+                let _ = session.stop();
+            }
+        }
+        self.reap();
+    }
+
+    /* reap() collects the children of the daemon that exited. */
+    fn reap(&mut self)
+    {
+        loop {
+            let pid = unsafe { libc::waitpid(-1, ptr::null_mut(), WNOHANG) };
+            if pid <= 0 {
+                break;
+            }
+            self.warm.retain(|_, owner| *owner != pid);
+        }
+    }
+}
+
+/* wait_session() waits for the warm session ID, started by OWNER which
+PIDFD refers to, to be set up. */
+fn wait_session(id: &str, owner: pid_t, pidfd: c_int) -> Result<Session, io::Error>
+{
+    // The session is only recorded once it is set up
+    let deadline = Instant::now() + WARM_TIMEOUT;
+    loop {
+        if let Ok(session) = Session::load(id) {
+            if session.owner == owner {
+                return Ok(session);
+            }
+        }
+
+        if pidfd_wait(pidfd, 10) {
+            return Err(io::Error::other(format!("failed to start session {}", id)));
+        }
+
+        if Instant::now() > deadline {
+            unsafe { kill(owner, SIGTERM) };
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out starting session {}", id),
+            ));
+        }
+    }
+}
+
+/* spawn() runs the command of REQUEST within SESSION with the standard file
+descriptors FDS, returning its wait status.  The command is killed if the
+client hangs up on STREAM before it exits. */
+fn spawn(session: &Session, request: &Request, fds: &[c_int], stream: &UnixStream)
+    -> SyscallResult
+{
+    // The profile may be relative to the host, so it is loaded before
+    // entering the session
+    let filter = match &request.seccomp {
+        None => None,
+        Some(seccomp) => Seccomp::profile(seccomp)
+            .and_then(|profile| profile.map(|profile| Seccomp::compile(&profile)).transpose())
+            .map_err(|error| {
+                println!("Failed to load seccomp profile {}, got {:?}", seccomp, error);
+                error.raw_os_error().unwrap_or(EINVAL)
+            })?,
+    };
+
+    let landlock = match &request.landlock {
+        None => None,
+        Some(landlock) => Landlock::profile(landlock)
+            .map_err(|error| {
+                println!("Failed to load Landlock profile {}, got {:?}", landlock, error);
+                error.raw_os_error().unwrap_or(EINVAL)
+            })?
+            .map(|mut profile| {
+                profile.extend(&request.landlock_read, &request.landlock_write);
+                profile
+            }),
+    };
+
+    let share = Share::presets(&request.share)
+        .map(|presets| Share::discover(&presets, &request.env))
+        .map_err(|error| {
+            println!("Failed to share with the session, got {:?}", error);
+            EINVAL
+        })?;
+
+    let mut env_profile = request.env_profile.clone();
+    env_profile.apply_flags(request.flags);
+    env_profile.share(&share.vars);
+    let environment = Env::build(request.env.clone(), &env_profile, &request.left)
+        .map_err(|error| {
+            println!("Failed to set up the environment, got {:?}", error);
+            error.raw_os_error().unwrap_or(EINVAL)
+        })?;
+
+    session.enter()?;
+
+    // setns(CLONE_NEWPID) only applies to children
+    let pid = match Libc::fork()? {
+        0 => {
+            for (target, fd) in fds.iter().enumerate() {
+                unsafe { dup2(*fd, target as c_int) };
+            }
+
+            Env::apply(&environment);
+
+            if env::set_current_dir(&request.cwd).is_err() {
+                env::set_current_dir("/").unwrap();
+            }
+            // The supervisor of the ownership is left out of the
+            // confinement below, as in `init`
+            let ownership = match request.flags & FAKE_OWNERSHIP != 0 {
+                true => match Ownership::spawn() {
+                    Ok(ownership) => Some(ownership as c_int),
+                    Err(errno) => unsafe { _exit(errno) },
+                },
+                false => None,
+            };
+            if let Err(errno) = Capabilities::restrict(request.caps) {
+                unsafe { _exit(errno) }
+            }
+            if let Some(landlock) = &landlock {
+                if let Err(errno) = Landlock::apply(landlock) {
+                    unsafe { _exit(errno) }
+                }
+            }
+            if let Some(ownership) = ownership {
+                if let Err(errno) = Ownership::install(ownership) {
+                    unsafe { _exit(errno) }
+                }
+            }
+            if let Some(filter) = &filter {
+                if let Err(errno) = Seccomp::install(filter) {
+                    unsafe { _exit(errno) }
+                }
+            }
+
+            let argv = request
+                .argv
+                .iter()
+                .map(|arg| CString::new(arg.as_str()).unwrap())
+                .collect::<Vec<_>>();
+            let argv = argv
+                .iter()
+                .map(|cstr| cstr.as_ptr())
+                .chain(iter::once(ptr::null()))
+                .collect::<Vec<_>>();
+            let res = handle_syscall_result(Libc::execvp(argv[0], argv.as_ptr()));
+            unsafe { _exit(res.err().unwrap_or(EINVAL)) }
+        }
+        pid => pid as pid_t,
+    };
+
+    let pidfd = Libc::pidfd_open(pid, 0)? as c_int;
+    let mut pollfds = [
+        pollfd {
+            fd: pidfd,
+            events: POLLIN,
+            revents: 0,
+        },
+        pollfd {
+            fd: stream.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        if unsafe { poll(pollfds.as_mut_ptr(), 2, -1) } < 0 {
+            continue;
+        }
+        if pollfds[0].revents != 0 {
+            break;
+        }
+
+        // The client never sends anything after the request, so the
+        // connection only becomes readable once it is closed
+        if pollfds[1].revents != 0 {
+            let _ = Libc::pidfd_send_signal(pidfd, SIGKILL, 0);
+            pollfds[1].fd = -1;
+        }
+    }
+    unsafe { close(pidfd) };
+
+    Libc::waitpid(pid, 0)
+}
+
+/* serve() runs the daemon until it receives SIGINT or SIGTERM, at which
+point the warm sessions it started are stopped. */
+pub fn serve() -> Result<(), io::Error>
+{
+    let path = socket_path();
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path.parent().unwrap())?;
+
+    // A socket left behind by a daemon that died is replaced, but not one
+    // that is still being listened on
+    if UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a daemon is already listening on {}", path.display()),
+        ));
+    }
+    let _ = fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+
+    // Signals are handled synchronously along with the connections
+    let mut mask: sigset_t = unsafe { mem::zeroed() };
+    let signalfd = unsafe {
+        sigemptyset(&mut mask);
+        sigaddset(&mut mask, SIGINT);
+        sigaddset(&mut mask, SIGTERM);
+        sigaddset(&mut mask, SIGCHLD);
+        sigprocmask(SIG_BLOCK, &mask, ptr::null_mut());
+        signalfd(-1, &mask, SFD_CLOEXEC)
+    };
+    if signalfd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut daemon = Daemon {
+        listener,
+        signalfd,
+        mask,
+        warm: HashMap::new(),
+    };
+    println!("Listening on {}", path.display());
+
+    loop {
+        let mut pollfds = [
+            pollfd {
+                fd: daemon.listener.as_raw_fd(),
+                events: POLLIN,
+                revents: 0,
+            },
+            pollfd {
+                fd: daemon.signalfd,
+                events: POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { poll(pollfds.as_mut_ptr(), 2, -1) } < 0 {
+            continue;
+        }
+
+        if pollfds[1].revents != 0 {
+            let mut siginfo: signalfd_siginfo = unsafe { mem::zeroed() };
+            unsafe {
+                read(
+                    daemon.signalfd,
+                    &mut siginfo as *mut signalfd_siginfo as *mut c_void,
+                    mem::size_of::<signalfd_siginfo>(),
+                )
+            };
+            if siginfo.ssi_signo as c_int != SIGCHLD {
+                break;
+            }
+            daemon.reap();
+        }
+
+        if pollfds[0].revents != 0 {
+            match daemon.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(error) = daemon.accept(stream) {
+                        println!("Failed to serve request, got {:?}", error);
+                    }
+                }
+                Err(error) => println!("Failed to accept connection, got {:?}", error),
+            }
+        }
+    }
+
+    daemon.shutdown();
+    unsafe { close(daemon.signalfd) };
+    fs::remove_file(&path)
+}
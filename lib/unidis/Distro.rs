@@ -0,0 +1,79 @@
+use crate::{Env, Export};
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/* OS_RELEASE are the locations of os-release(5) within a root directory,
+in order of precedence. */
+const OS_RELEASE: [&str; 2] = ["etc/os-release", "usr/lib/os-release"];
+
+/* os_release() parses the os-release(5) file of the root directory ROOT,
+an empty map is returned if there is none. */
+pub fn os_release(root: &Path) -> HashMap<String, String>
+{
+    let os_release = OS_RELEASE
+        .iter()
+        .find_map(|path| fs::read_to_string(root.join(path)).ok())
+        .unwrap_or_default();
+
+    os_release
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim_matches(|c| c == '"' || c == '\'');
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/* name() names the distribution in the root directory ROOT after the ID of
+its os-release(5), falling back to the name of ROOT itself. */
+pub fn name(root: &Path) -> String
+{
+    os_release(root)
+        .remove("ID")
+        .filter(|id| !id.is_empty())
+        .or_else(|| root.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "unidis".to_string())
+}
+
+/* find_command() looks up COMMAND in the root directory ROOT, in its PATH
+if it is a bare name, None if it has no such file. */
+pub fn find_command(root: &Path, command: &str) -> Option<PathBuf>
+{
+    if command.contains('/') {
+        return Some(root.join(command.trim_start_matches('/'))).filter(|path| path.is_file());
+    }
+
+    Env::PATH_DIRS
+        .iter()
+        .map(|dir| root.join(dir.trim_start_matches('/')).join(command))
+        .find(|path| path.is_file())
+}
+
+/* store_dir() is the directory the managed roots are kept in, beneath
+$XDG_DATA_HOME. */
+pub fn store_dir() -> PathBuf
+{
+    Export::data_home().join("unidis").join("distros")
+}
+
+/* valid_name() is whether NAME names a single entry of the store directory,
+as a managed root is removed recursively if it fails to be bootstrapped. */
+pub fn valid_name(name: &str) -> bool
+{
+    crate::plain_name(name)
+}
+
+/* managed() is the directory of the managed root NAME, holding the root
+directory itself in `root` along with what its virtual machine needs, None
+if NAME is not valid. */
+pub fn managed(name: &str) -> Option<PathBuf>
+{
+    Some(store_dir().join(name)).filter(|_| valid_name(name))
+}
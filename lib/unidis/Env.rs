@@ -0,0 +1,460 @@
+use crate::{CLEAR_ENV, PREPEND_PATH, UPDATE_PATH};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/* KEEP are the variables kept from the host when the environment is
+cleared and the profile does not list any, those describing the terminal,
+the display and the user. */
+pub const KEEP: [&str; 16] = [
+    "COLORTERM",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "DISPLAY",
+    "HOME",
+    "LANG",
+    "LANGUAGE",
+    "LC_ALL",
+    "LOGNAME",
+    "SHELL",
+    "SSH_AUTH_SOCK",
+    "TERM",
+    "TZ",
+    "USER",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+];
+
+/* PATH_DIRS are the directories executables are installed to by the
+distributions, in the order they are looked up. */
+pub(crate) const PATH_DIRS: [&str; 6] = [
+    "/usr/local/sbin",
+    "/usr/local/bin",
+    "/usr/sbin",
+    "/usr/bin",
+    "/sbin",
+    "/bin",
+];
+
+/* NIX_IGNORED are the variables of a Nix dev shell left out of the
+environment, those describing the build sandbox rather than the shell, as
+`nix develop` does. */
+const NIX_IGNORED: [&str; 16] = [
+    "BASHOPTS",
+    "HOME",
+    "NIX_BUILD_TOP",
+    "NIX_ENFORCE_PURITY",
+    "NIX_LOG_FD",
+    "NIX_REMOTE",
+    "PPID",
+    "SHELL",
+    "SHELLOPTS",
+    "SSL_CERT_FILE",
+    "TEMP",
+    "TEMPDIR",
+    "TERM",
+    "TMP",
+    "TMPDIR",
+    "TZ",
+];
+
+/* PathMode describes how the directories of the distribution are merged
+into PATH. */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathMode
+{
+    // KEEP leaves PATH as is
+    #[default]
+    Keep,
+    // APPEND looks up the commands of the host first
+    Append,
+    // PREPEND looks up the commands of the distribution first
+    Prepend,
+}
+
+/* Profile describes the environment of the command, derived from the one
+of the caller in the order of the fields below. */
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Profile
+{
+    // CLEAR drops every variable but those in KEEP, see `self::KEEP`
+    pub clear: bool,
+    pub keep: Vec<String>,
+    // FILES hold KEY=VALUE lines, paths are on the host
+    pub files: Vec<PathBuf>,
+    // NIX is the output of `nix print-dev-env --json`, on the host
+    pub nix: Option<PathBuf>,
+    pub unset: Vec<String>,
+    // SET are KEY=VALUE pairs
+    pub set: Vec<String>,
+    pub path: PathMode,
+}
+
+impl Profile
+{
+    /* apply_flags() applies CLEAR_ENV, UPDATE_PATH and PREPEND_PATH of FLAGS
+    to the profile. */
+    pub fn apply_flags(&mut self, flags: u64)
+    {
+        if flags & CLEAR_ENV != 0 {
+            self.clear = true;
+        }
+        if flags & PREPEND_PATH != 0 {
+            self.path = PathMode::Prepend;
+        } else if flags & UPDATE_PATH != 0 {
+            self.path = PathMode::Append;
+        }
+    }
+
+    /* share() sets VARS ahead of the variables set by the profile, which
+    take precedence. */
+    pub fn share(&mut self, vars: &[(String, String)])
+    {
+        let vars = vars.iter().map(|(key, value)| format!("{}={}", key, value));
+        self.set.splice(0..0, vars);
+    }
+}
+
+/* profile() loads the environment profile NAME, which is either a built-in
+profile or the path to a JSON profile.  `host` passes the environment of the
+caller through while `clean` clears it. */
+pub fn profile(name: &str) -> Result<Profile, io::Error>
+{
+    match name {
+        "host" => Ok(Profile::default()),
+        "clean" => Ok(Profile {
+            clear: true,
+            ..Profile::default()
+        }),
+        path => {
+            let profile = fs::read(Path::new(path))?;
+            serde_json::from_slice(&profile)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        }
+    }
+}
+
+/* split() splits the KEY=VALUE pair VARIABLE. */
+fn split(variable: &str) -> Option<(String, String)>
+{
+    let mut variable = variable.splitn(2, '=');
+    match (variable.next(), variable.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => Some((key.to_string(), value.to_string())),
+        _ => None,
+    }
+}
+
+/* read_file() reads the variables of the file PATH, made of KEY=VALUE lines
+with an optional `export` prefix and quotes around VALUE.  Blank lines and
+lines starting with # are skipped. */
+fn read_file(path: &Path) -> Result<Vec<(String, String)>, io::Error>
+{
+    let file = fs::read_to_string(path)?;
+
+    let mut variables = Vec::new();
+    for (i, line) in file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = split(line).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?}:{}: expected KEY=VALUE", path, i + 1),
+            )
+        })?;
+        let value = match value.as_bytes() {
+            [b'"', .., b'"'] | [b'\'', .., b'\''] if value.len() >= 2 => {
+                value[1..value.len() - 1].to_string()
+            }
+            _ => value,
+        };
+        variables.push((key.trim_end().to_string(), value));
+    }
+
+    Ok(variables)
+}
+
+/* NixVariable is a shell variable of a Nix dev shell, only exported ones,
+which hold strings, end up in the environment. */
+#[derive(Deserialize)]
+struct NixVariable
+{
+    #[serde(rename = "type")]
+    kind: String,
+    value: serde_json::Value,
+}
+
+/* NixEnv is the output of `nix print-dev-env --json`. */
+#[derive(Deserialize)]
+struct NixEnv
+{
+    variables: BTreeMap<String, NixVariable>,
+}
+
+/* read_nix() reads the exported variables of the Nix dev shell PATH. */
+fn read_nix(path: &Path) -> Result<Vec<(String, String)>, io::Error>
+{
+    let nix = fs::read(path)?;
+    let nix: NixEnv = serde_json::from_slice(&nix)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    Ok(nix
+        .variables
+        .into_iter()
+        .filter(|(key, _)| !NIX_IGNORED.contains(&key.as_str()))
+        .filter(|(_, variable)| variable.kind == "exported")
+        .filter_map(|(key, variable)| match variable.value {
+            serde_json::Value::String(value) => Some((key, value)),
+            _ => None,
+        })
+        .collect())
+}
+
+/* merge_path() merges the directories of the distribution at ROOT into
+PATH following MODE, a missing PATH is made of them alone. */
+fn merge_path(path: Option<&str>, root: &Path, mode: PathMode) -> String
+{
+    // Symbolic links such as /bin -> usr/bin on merged /usr distributions
+    // would only repeat the directories they point to
+    let dirs = PATH_DIRS
+        .iter()
+        .filter(|dir| {
+            fs::symlink_metadata(root.join(dir.trim_start_matches('/')))
+                .is_ok_and(|metadata| metadata.is_dir())
+        })
+        .copied();
+
+    let path = match path {
+        None => return dirs.collect::<Vec<_>>().join(":"),
+        Some(path) => path,
+    };
+
+    let merged = match mode {
+        PathMode::Keep => return path.to_string(),
+        PathMode::Append => {
+            let dirs = dirs.filter(|dir| !path.split(':').any(|entry| entry == *dir));
+            path.split(':')
+                .filter(|entry| !entry.is_empty())
+                .chain(dirs)
+                .collect::<Vec<_>>()
+        }
+        PathMode::Prepend => {
+            let dirs = dirs.collect::<Vec<_>>();
+            let rest = path
+                .split(':')
+                .filter(|entry| !entry.is_empty() && !dirs.contains(entry));
+            dirs.iter().copied().chain(rest).collect::<Vec<_>>()
+        }
+    };
+
+    merged.join(":")
+}
+
+/* set() sets the variable KEY of VARS to VALUE. */
+fn set(vars: &mut Vec<(String, String)>, key: String, value: String)
+{
+    vars.retain(|(name, _)| *name != key);
+    vars.push((key, value));
+}
+
+/* build() derives the environment of the command from the environment VARS
+of the caller, following PROFILE, for the distribution at ROOT. */
+pub fn build<I>(vars: I, profile: &Profile, root: &Path) -> Result<Vec<(String, String)>, io::Error>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut vars = vars.into_iter().collect::<Vec<_>>();
+
+    if profile.clear {
+        let keep = if profile.keep.is_empty() {
+            KEEP.iter().map(|key| key.to_string()).collect()
+        } else {
+            profile.keep.clone()
+        };
+        vars.retain(|(key, _)| keep.contains(key));
+    }
+
+    for file in &profile.files {
+        for (key, value) in read_file(file)? {
+            set(&mut vars, key, value);
+        }
+    }
+
+    // The tools of the dev shell come first, the distribution ones are
+    // merged below like for any other PATH
+    if let Some(nix) = &profile.nix {
+        for (key, value) in read_nix(nix)? {
+            let value = match vars.iter().find(|(name, _)| key == "PATH" && *name == key) {
+                Some((_, path)) if !path.is_empty() => format!("{}:{}", value, path),
+                _ => value,
+            };
+            set(&mut vars, key, value);
+        }
+    }
+
+    vars.retain(|(key, _)| !profile.unset.contains(key));
+
+    for variable in &profile.set {
+        let (key, value) = split(variable).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?}: expected KEY=VALUE", variable),
+            )
+        })?;
+        set(&mut vars, key, value);
+    }
+
+    let path = vars
+        .iter()
+        .find(|(key, _)| key == "PATH")
+        .map(|(_, path)| path.as_str());
+    let path = merge_path(path, root, profile.path);
+    set(&mut vars, "PATH".to_string(), path);
+
+    Ok(vars)
+}
+
+/* apply() replaces the environment of the calling process with VARS. */
+pub fn apply(vars: &[(String, String)])
+{
+    for (key, _) in env::vars_os() {
+        env::remove_var(key);
+    }
+    for (key, value) in vars {
+        env::set_var(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempdir::TempDir;
+
+    /* fixture() creates a merged /usr root directory, where /bin and /sbin
+    link to their /usr counterparts. */
+    fn fixture() -> TempDir
+    {
+        let root = TempDir::new("unidis-env").unwrap();
+        fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        fs::create_dir_all(root.path().join("usr/sbin")).unwrap();
+        symlink("usr/bin", root.path().join("bin")).unwrap();
+        symlink("usr/sbin", root.path().join("sbin")).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn path()
+    {
+        let root = fixture();
+        let root = root.path();
+
+        assert_eq!(merge_path(None, root, PathMode::Append), "/usr/sbin:/usr/bin");
+        assert_eq!(
+            merge_path(Some("/opt/bin:/usr/bin"), root, PathMode::Keep),
+            "/opt/bin:/usr/bin"
+        );
+
+        // The directories already in PATH are neither repeated nor moved
+        assert_eq!(
+            merge_path(Some("/opt/bin:/usr/bin:"), root, PathMode::Append),
+            "/opt/bin:/usr/bin:/usr/sbin"
+        );
+
+        // Those of the distribution are moved ahead instead
+        assert_eq!(
+            merge_path(Some("/usr/bin:/opt/bin::/usr/sbin"), root, PathMode::Prepend),
+            "/usr/sbin:/usr/bin:/opt/bin"
+        );
+    }
+
+    #[test]
+    fn file()
+    {
+        let dir = TempDir::new("unidis-env").unwrap();
+        let path = dir.path().join("env");
+        fs::write(
+            &path,
+            "\
+# A comment
+   # An indented comment
+
+FOO=bar
+export QUOTED=\"a b\"
+SINGLE='c=d'
+KEY =value
+EMPTY=
+LONE=\"
+",
+        )
+        .unwrap();
+
+        let vars = read_file(&path).unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("FOO", "bar"),
+                ("QUOTED", "a b"),
+                ("SINGLE", "c=d"),
+                ("KEY", "value"),
+                ("EMPTY", ""),
+                ("LONE", "\""),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+
+        fs::write(&path, "FOO=bar\nno separator\n").unwrap();
+        let error = read_file(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().ends_with(":2: expected KEY=VALUE"));
+    }
+
+    #[test]
+    fn nix()
+    {
+        let dir = TempDir::new("unidis-env").unwrap();
+        let path = dir.path().join("nix.json");
+        fs::write(
+            &path,
+            r#"{
+                "bashFunctions": {},
+                "variables": {
+                    "PATH": { "type": "exported", "value": "/nix/store/a-gcc/bin" },
+                    "CC": { "type": "exported", "value": "gcc" },
+                    "NIX_BUILD_TOP": { "type": "exported", "value": "/tmp" },
+                    "name": { "type": "var", "value": "shell" },
+                    "outputs": { "type": "associative", "value": { "out": "/nix/store/b" } }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let vars = read_nix(&path).unwrap();
+        assert_eq!(
+            vars,
+            [("CC", "gcc"), ("PATH", "/nix/store/a-gcc/bin")]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+
+        // The tools of the dev shell come before those of the caller
+        let profile = Profile {
+            nix: Some(path),
+            path: PathMode::Keep,
+            ..Profile::default()
+        };
+        let vars = [("PATH".to_string(), "/usr/bin".to_string())];
+        let vars = build(vars, &profile, dir.path()).unwrap();
+        assert!(vars.contains(&("PATH".to_string(), "/nix/store/a-gcc/bin:/usr/bin".to_string())));
+    }
+}
@@ -0,0 +1,379 @@
+use crate::Distro;
+
+use serde::Serialize;
+use std::{
+    env, fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/* MARKER starts the comment of the wrappers, and the key of the desktop
+entries, recording what they export, so that only those are ever listed or
+removed. */
+const MARKER: &str = "unidis-export";
+
+/* DESKTOP_KEY is the key of the desktop entries recording the distribution
+they were exported from. */
+const DESKTOP_KEY: &str = "X-Unidis-Export";
+
+/* ICON_EXTENSIONS are the formats of icons looked up in the distribution,
+in order of preference. */
+const ICON_EXTENSIONS: [&str; 3] = ["svg", "png", "xpm"];
+
+/* Kind is what an export makes available on the host. */
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind
+{
+    // A wrapper executable in the bin directory
+    Command,
+    // A desktop entry in the applications directory
+    Desktop,
+}
+
+/* Export describes an exported command of DISTRO, found at PATH on the
+host. */
+#[derive(Debug, Serialize)]
+pub struct Export
+{
+    pub name: String,
+    pub kind: Kind,
+    pub distro: PathBuf,
+    pub command: String,
+    pub path: PathBuf,
+}
+
+/* home() is the home directory of the user. */
+fn home() -> PathBuf
+{
+    env::var_os("HOME").map_or_else(|| PathBuf::from("/"), PathBuf::from)
+}
+
+/* bin_dir() is the directory wrappers are written to, ~/.local/bin. */
+pub fn bin_dir() -> PathBuf
+{
+    home().join(".local").join("bin")
+}
+
+/* data_home() is $XDG_DATA_HOME, falling back on ~/.local/share. */
+pub(crate) fn data_home() -> PathBuf
+{
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|data_home| data_home.is_absolute())
+        .unwrap_or_else(|| home().join(".local").join("share"))
+}
+
+/* applications_dir() is the directory desktop entries are written to,
+beneath $XDG_DATA_HOME. */
+pub fn applications_dir() -> PathBuf
+{
+    data_home().join("applications")
+}
+
+/* quote() quotes ARG for sh(1). */
+pub(crate) fn quote(arg: &str) -> String
+{
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/* run_command() is the command line of `unidis run` within DISTRO with
+OPTIONS, up to the command itself. */
+fn run_command(distro: &Path, options: &[String]) -> Result<Vec<String>, io::Error>
+{
+    let unidis = env::current_exe()?;
+    let mut argv = vec![unidis.to_string_lossy().into_owned(), "run".to_string()];
+    argv.extend(options.iter().cloned());
+    argv.push(distro.to_string_lossy().into_owned());
+    argv.push("--".to_string());
+
+    Ok(argv)
+}
+
+/* valid_name() is whether NAME names a single entry of the bin or
+applications directory, as the exports are written there. */
+pub fn valid_name(name: &str) -> bool
+{
+    crate::plain_name(name)
+}
+
+/* invalid_name() is the error of exporting under NAME. */
+fn invalid_name(name: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid export name {:?}", name))
+}
+
+/* find_command() makes sure COMMAND is an executable of DISTRO, looked up
+in its PATH if it is a bare name. */
+fn find_command(distro: &Path, command: &str) -> Result<(), io::Error>
+{
+    match Distro::find_command(distro, command) {
+        Some(_) => Ok(()),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a command of {:?}", command, distro),
+        )),
+    }
+}
+
+/* export_command() writes the wrapper NAME to the bin directory, running
+COMMAND of DISTRO with the arguments and from the working directory it is
+given, OPTIONS are passed to `unidis run`. */
+pub fn export_command(
+    distro: &Path,
+    command: &str,
+    name: &str,
+    options: &[String],
+) -> Result<PathBuf, io::Error>
+{
+    if !valid_name(name) {
+        return Err(invalid_name(name));
+    }
+    find_command(distro, command)?;
+
+    let path = bin_dir().join(name);
+    if path.exists() && read_wrapper(&path).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} exists and was not exported", path),
+        ));
+    }
+
+    let argv = run_command(distro, options)?
+        .iter()
+        .chain(Some(&command.to_string()))
+        .map(|arg| quote(arg))
+        .collect::<Vec<_>>();
+    let wrapper = format!(
+        "#!/bin/sh\n# {}: {}\t{}\nexec {} \"$@\"\n",
+        MARKER,
+        distro.display(),
+        command,
+        argv.join(" ")
+    );
+
+    fs::create_dir_all(bin_dir())?;
+    fs::write(&path, wrapper)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+
+    Ok(path)
+}
+
+/* read_wrapper() reads the distribution and the command a wrapper at PATH
+exports, None if it is not one. */
+fn read_wrapper(path: &Path) -> Option<(PathBuf, String)>
+{
+    let wrapper = fs::read_to_string(path).ok()?;
+    let marker = wrapper.lines().nth(1)?.strip_prefix("# ")?;
+    let (distro, command) = marker
+        .strip_prefix(MARKER)?
+        .strip_prefix(": ")?
+        .split_once('\t')?;
+
+    Some((PathBuf::from(distro), command.to_string()))
+}
+
+/* desktop_exec() quotes ARG for the Exec key of a desktop entry. */
+fn desktop_exec(arg: &str) -> String
+{
+    if arg.chars().any(|c| " \t\"'\\><~|&;$*?#()`".contains(c)) {
+        let arg = arg
+            .replace('\\', r"\\")
+            .replace('"', "\\\"")
+            .replace('`', "\\`");
+        format!("\"{}\"", arg.replace('$', "\\$"))
+    } else {
+        arg.to_string()
+    }
+}
+
+/* find_icon() looks up the icon NAME in DISTRO, preferring scalable and
+larger ones, as the host has no idea of the icons of the distribution. */
+fn find_icon(distro: &Path, name: &str) -> Option<PathBuf>
+{
+    let hicolor = distro.join("usr/share/icons/hicolor");
+    let mut sizes = fs::read_dir(&hicolor)
+        .map(|sizes| {
+            sizes
+                .filter_map(Result::ok)
+                .map(|size| size.path())
+                .collect()
+        })
+        .unwrap_or_else(|_| Vec::new());
+    // "scalable" sorts after "NxN", larger sizes after smaller ones
+    sizes.sort_by_key(|size| {
+        let name = size
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let width = name.split('x').next().and_then(|width| width.parse().ok());
+        (name == "scalable", width.unwrap_or(0u32))
+    });
+
+    let dirs = sizes
+        .iter()
+        .rev()
+        .map(|size| size.join("apps"))
+        .chain(Some(distro.join("usr/share/pixmaps")));
+    for dir in dirs {
+        for extension in &ICON_EXTENSIONS {
+            let icon = dir.join(format!("{}.{}", name, extension));
+            if icon.is_file() {
+                return Some(icon);
+            }
+        }
+    }
+
+    None
+}
+
+/* export_desktop() writes the desktop entry APP of DISTRO, from its
+/usr/share/applications, to the applications directory with its commands
+run within DISTRO, OPTIONS are passed to `unidis run`. */
+pub fn export_desktop(distro: &Path, app: &str, options: &[String]) -> Result<PathBuf, io::Error>
+{
+    let app = app.trim_end_matches(".desktop");
+    if !valid_name(app) {
+        return Err(invalid_name(app));
+    }
+    let entry = distro
+        .join("usr/share/applications")
+        .join(format!("{}.desktop", app));
+    let entry = fs::read_to_string(&entry)
+        .map_err(|error| io::Error::new(error.kind(), format!("{:?}: {}", entry, error)))?;
+
+    let run = run_command(distro, options)?
+        .iter()
+        .map(|arg| desktop_exec(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let distro_name = Distro::name(distro);
+
+    let mut exported = String::new();
+    let mut in_entry = false;
+    for line in entry.lines() {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        }
+
+        // The executables are those of the distribution, which the host
+        // cannot look up
+        if line.starts_with("TryExec=") || line.starts_with("DBusActivatable=") {
+            continue;
+        }
+        if let Some(exec) = line.strip_prefix("Exec=") {
+            exported.push_str(&format!("Exec={} {}\n", run, exec));
+        } else if let (true, Some(name)) = (in_entry, line.strip_prefix("Name=")) {
+            exported.push_str(&format!("Name={} ({})\n", name, distro_name));
+        } else if let (true, Some(icon)) = (in_entry, line.strip_prefix("Icon=")) {
+            // Icons the distribution does not have are left for the host
+            // to look up
+            let found = match Path::new(icon).is_absolute() {
+                true => {
+                    Some(distro.join(icon.trim_start_matches('/'))).filter(|icon| icon.is_file())
+                }
+                false => find_icon(distro, icon),
+            };
+            match found {
+                Some(found) => exported.push_str(&format!("Icon={}\n", found.display())),
+                None => exported.push_str(&format!("Icon={}\n", icon)),
+            }
+        } else {
+            exported.push_str(line);
+            exported.push('\n');
+        }
+
+        if line == "[Desktop Entry]" {
+            exported.push_str(&format!("{}={}\n", DESKTOP_KEY, distro.display()));
+        }
+    }
+
+    let path = applications_dir().join(format!("unidis-{}.desktop", app));
+    fs::create_dir_all(applications_dir())?;
+    fs::write(&path, exported)?;
+
+    Ok(path)
+}
+
+/* read_desktop() reads the distribution a desktop entry at PATH was
+exported from, along with its first command, None if it is not one. */
+fn read_desktop(path: &Path) -> Option<(PathBuf, String)>
+{
+    let entry = fs::read_to_string(path).ok()?;
+    let distro = entry
+        .lines()
+        .find_map(|line| line.strip_prefix(DESKTOP_KEY)?.strip_prefix('='))?;
+    let command = entry
+        .lines()
+        .find_map(|line| line.strip_prefix("Exec="))
+        .and_then(|exec| exec.split(" -- ").nth(1))
+        .unwrap_or("");
+
+    Some((PathBuf::from(distro), command.to_string()))
+}
+
+/* list() lists the exports found in the bin and applications directories. */
+pub fn list() -> Vec<Export>
+{
+    let mut exports = Vec::new();
+
+    let entries = |dir: PathBuf| {
+        let mut entries = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .collect()
+            })
+            .unwrap_or_else(|_| Vec::new());
+        entries.sort();
+        entries
+    };
+
+    for path in entries(bin_dir()) {
+        if let Some((distro, command)) = read_wrapper(&path) {
+            exports.push(Export {
+                name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                kind: Kind::Command,
+                distro,
+                command,
+                path,
+            });
+        }
+    }
+    for path in entries(applications_dir()) {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let name = match name
+            .strip_prefix("unidis-")
+            .and_then(|name| name.strip_suffix(".desktop"))
+        {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if let Some((distro, command)) = read_desktop(&path) {
+            exports.push(Export {
+                name,
+                kind: Kind::Desktop,
+                distro,
+                command,
+                path,
+            });
+        }
+    }
+
+    exports
+}
+
+/* unexport() removes the wrapper and desktop entry NAME, the paths removed
+are returned, none meaning there was no such export. */
+pub fn unexport(name: &str) -> Result<Vec<PathBuf>, io::Error>
+{
+    let mut removed = Vec::new();
+    for export in list().into_iter().filter(|export| export.name == name) {
+        fs::remove_file(&export.path)?;
+        removed.push(export.path);
+    }
+
+    Ok(removed)
+}
@@ -0,0 +1,174 @@
+use crate::Libc::{self, *};
+
+use libc::{close, open, MS_BIND, MS_RDONLY, MS_REMOUNT, O_CLOEXEC, O_NOFOLLOW, O_PATH};
+use serde::Deserialize;
+use std::{
+    ffi::{CString, OsString},
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    ptr,
+};
+
+/* HOST_FILES are the files of the host passed through by the `default`
+profile, those describing the network and the locale of the machine rather
+than the distribution. */
+const HOST_FILES: [&str; 4] = [
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/localtime",
+    "/etc/nsswitch.conf",
+];
+
+/* MAX_SYMLINKS is the number of symbolic links followed while resolving a
+path within the distribution, as for the kernel. */
+const MAX_SYMLINKS: usize = 40;
+
+/* Profile lists the files of the host bound read-only over those of the
+distribution, at the same path. */
+#[derive(Debug, Default, Deserialize)]
+pub struct Profile
+{
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+}
+
+impl Profile
+{
+    /* exclude() opts out of passing FILES through. */
+    pub fn exclude<P: AsRef<Path>>(&mut self, files: &[P])
+    {
+        self.files
+            .retain(|file| !files.iter().any(|excluded| excluded.as_ref() == file));
+    }
+}
+
+/* default() builds the `default` profile. */
+fn default() -> Profile
+{
+    Profile {
+        files: HOST_FILES.iter().map(PathBuf::from).collect(),
+    }
+}
+
+/* profile() loads the profile NAME, which is either `default`, `none` or
+the path to a JSON profile, None meaning the files of the distribution are
+left as is. */
+pub fn profile(name: &str) -> Result<Option<Profile>, io::Error>
+{
+    match name {
+        "none" => Ok(None),
+        "default" => Ok(Some(default())),
+        path => {
+            let profile = fs::read(Path::new(path))?;
+            serde_json::from_slice(&profile)
+                .map(Some)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        }
+    }
+}
+
+/* resolve() resolves the absolute PATH within the distribution at the
+working directory, following symbolic links as if it were the root, None if
+it does not exist. */
+fn resolve(path: &Path) -> Option<PathBuf>
+{
+    let mut resolved = PathBuf::from(".");
+    let mut pending = vec![path.as_os_str().to_os_string()];
+    let mut symlinks = 0;
+
+    while let Some(path) = pending.pop() {
+        for (i, component) in Path::new(&path).components().enumerate() {
+            match component {
+                Component::RootDir => resolved = PathBuf::from("."),
+                Component::ParentDir if resolved != Path::new(".") => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => {
+                    let next = resolved.join(name);
+                    if !fs::symlink_metadata(&next).ok()?.file_type().is_symlink() {
+                        resolved = next;
+                        continue;
+                    }
+
+                    // The rest of the path is resolved from where the link
+                    // leads to
+                    symlinks += 1;
+                    if symlinks > MAX_SYMLINKS {
+                        return None;
+                    }
+                    let rest = Path::new(&path)
+                        .components()
+                        .skip(i + 1)
+                        .collect::<PathBuf>();
+                    if rest != Path::new("") {
+                        pending.push(OsString::from(rest));
+                    }
+                    pending.push(fs::read_link(&next).ok()?.into_os_string());
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Some(resolved)
+}
+
+/* bind() binds the file of the host SOURCE read-only over TARGET, without
+following TARGET if it is a symbolic link, which may well be dangling. */
+fn bind(source: &Path, target: &Path) -> SyscallResult
+{
+    let path = CString::new(target.as_os_str().as_bytes()).unwrap();
+    let fd = new_syscall_result::<i32>(
+        unsafe { open(path.as_ptr(), O_PATH | O_NOFOLLOW | O_CLOEXEC) },
+        None,
+    )?;
+
+    // The link itself is reached through its descriptor, once covered the
+    // path leads to the file bound over it
+    let target = target.to_string_lossy();
+    let res = Libc::mount(
+        &source.to_string_lossy(),
+        &format!("/proc/self/fd/{}", fd),
+        "",
+        MS_BIND,
+        ptr::null(),
+    )
+    .and_then(|_| {
+        Libc::mount(
+            "none",
+            &target,
+            "",
+            MS_REMOUNT | MS_BIND | MS_RDONLY,
+            ptr::null(),
+        )
+    });
+    unsafe { close(fd as i32) };
+
+    res
+}
+
+/* apply() binds the files of PROFILE read-only over those of the
+distribution at the working directory, before the host is out of reach.
+Links of the host are followed, so that links into /etc/static or
+/run/systemd are honoured, while those of the distribution are covered.
+Files missing from either side are skipped. */
+pub fn apply(profile: &Profile) -> SyscallResult
+{
+    for file in &profile.files {
+        let source = match fs::canonicalize(file) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let target = match (file.parent().and_then(resolve), file.file_name()) {
+            (Some(parent), Some(name)) => parent.join(name),
+            _ => continue,
+        };
+        if fs::symlink_metadata(&target).is_ok() {
+            bind(&source, &target)?;
+        }
+    }
+
+    new_syscall_result(0, None)
+}
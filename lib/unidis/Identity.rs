@@ -0,0 +1,301 @@
+use crate::Libc::{self, *};
+
+use libc::{getgrgid, getgroups, getpwuid, getuid, gid_t, uid_t, EINVAL, MS_BIND};
+use std::{
+    env,
+    ffi::CStr,
+    fs, io,
+    path::{Path, PathBuf},
+    ptr,
+};
+use tempdir::TempDir;
+
+/* FALLBACK_SHELL is the shell of the user when the distribution lacks the
+one of the host. */
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/* Identity is the invoking user as known to the host, along with the
+groups it belongs to, its primary group first. */
+#[derive(Debug)]
+pub struct Identity
+{
+    name: String,
+    uid: uid_t,
+    gid: gid_t,
+    gecos: String,
+    home: String,
+    shell: String,
+    groups: Vec<(String, gid_t)>,
+}
+
+/* string() copies the C string PTR, which may be NULL. */
+fn string(ptr: *const libc::c_char) -> String
+{
+    if ptr.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/* host() looks up the invoking user through the name services of the host,
+None if it has no name there either. */
+pub fn host() -> Option<Identity>
+{
+    let passwd = unsafe { getpwuid(getuid()).as_ref() }?;
+    let mut identity = Identity {
+        name: string(passwd.pw_name),
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+        gecos: string(passwd.pw_gecos),
+        home: string(passwd.pw_dir),
+        shell: string(passwd.pw_shell),
+        groups: Vec::new(),
+    };
+
+    let count = unsafe { getgroups(0, ptr::null_mut()) };
+    let mut gids = vec![0; count.max(0) as usize];
+    let count = unsafe { getgroups(gids.len() as i32, gids.as_mut_ptr()) };
+    gids.truncate(count.max(0) as usize);
+    gids.retain(|gid| *gid != identity.gid);
+    gids.insert(0, identity.gid);
+
+    // Groups without a name on the host could only be made up
+    for gid in gids {
+        if let Some(group) = unsafe { getgrgid(gid).as_ref() } {
+            identity.groups.push((string(group.gr_name), gid));
+        }
+    }
+
+    Some(identity)
+}
+
+/* merge_passwd() merges IDENTITY into PASSWD, the `passwd` of the
+distribution, replacing any entry with the same name or UID.  The shell of
+the host is kept if the distribution at ROOT has it. */
+fn merge_passwd(passwd: &str, identity: &Identity, root: &Path) -> String
+{
+    let mut merged = String::new();
+    for line in passwd.lines() {
+        let fields = line.split(':').collect::<Vec<_>>();
+        if fields.len() > 2 && (fields[0] == identity.name || fields[2] == identity.uid.to_string())
+        {
+            continue;
+        }
+        merged.push_str(line);
+        merged.push('\n');
+    }
+
+    let shell = match root.join(identity.shell.trim_start_matches('/')).exists() {
+        true if !identity.shell.is_empty() => &identity.shell,
+        _ => FALLBACK_SHELL,
+    };
+    merged.push_str(&format!(
+        "{}:x:{}:{}:{}:{}:{}\n",
+        identity.name, identity.uid, identity.gid, identity.gecos, identity.home, shell
+    ));
+
+    merged
+}
+
+/* merge_group() merges the groups of IDENTITY into GROUP, the `group` of the
+distribution.  The user is added as a member of the groups it already has,
+other groups are added unless their name is taken. */
+fn merge_group(group: &str, identity: &Identity) -> String
+{
+    let mut groups = group
+        .lines()
+        .map(|line| line.split(':').map(str::to_string).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    for (name, gid) in &identity.groups {
+        let gid = gid.to_string();
+        let taken = groups.iter().any(|fields| fields[0] == *name);
+        match groups
+            .iter_mut()
+            .find(|fields| fields.len() == 4 && fields[2] == gid)
+        {
+            Some(fields) => {
+                let mut members = fields[3]
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>();
+                if !members.contains(&identity.name) {
+                    members.push(identity.name.clone());
+                }
+                fields[3] = members.join(",");
+            }
+            None if taken => {}
+            None => groups.push(vec![
+                name.clone(),
+                "x".to_string(),
+                gid,
+                identity.name.clone(),
+            ]),
+        }
+    }
+
+    groups
+        .iter()
+        .map(|fields| fields.join(":") + "\n")
+        .collect()
+}
+
+/* overlay() binds a copy of PATH holding CONTENTS over PATH, the copy is
+written to SCRATCH on the host, which may be removed once bound. */
+fn overlay(path: &str, contents: &str, scratch: &Path) -> SyscallResult
+{
+    let copy = scratch.join(Path::new(path).file_name().unwrap());
+    if let Err(error) = fs::write(&copy, contents) {
+        return Err(error.raw_os_error().unwrap_or(EINVAL));
+    }
+
+    Libc::mount(copy.to_str().unwrap(), path, "", MS_BIND, ptr::null())
+}
+
+/* read() reads the database PATH of the distribution, None if it has none,
+as then there is nothing to bind over. */
+fn read(path: &str) -> Result<Option<String>, io::Error>
+{
+    match fs::read_to_string(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+        Ok(contents) => Ok(Some(contents)),
+    }
+}
+
+/* apply() binds the `passwd` and `group` of the distribution at the working
+directory, merged with IDENTITY, over the originals, which are left as is.
+The host has to be reachable still. */
+pub fn apply(identity: &Identity) -> SyscallResult
+{
+    let errno = |error: io::Error| error.raw_os_error().unwrap_or(EINVAL);
+
+    // Mounts outlive the files they were bound from, so the copies are
+    // gone from the host once the session is set up
+    let scratch = TempDir::new("unidis-identity").map_err(errno)?;
+
+    if let Some(passwd) = read("etc/passwd").map_err(errno)? {
+        let passwd = merge_passwd(&passwd, identity, Path::new("."));
+        overlay("etc/passwd", &passwd, scratch.path())?;
+    }
+    if let Some(group) = read("etc/group").map_err(errno)? {
+        overlay("etc/group", &merge_group(&group, identity), scratch.path())?;
+    }
+
+    new_syscall_result(0, None)
+}
+
+/* login_shell() looks up the shell of UID in the `passwd` of the root,
+falling back on $SHELL and then FALLBACK_SHELL, those the root lacks are
+skipped. */
+pub fn login_shell(uid: uid_t) -> PathBuf
+{
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    let shell = passwd.lines().find_map(|line| {
+        let fields = line.split(':').collect::<Vec<_>>();
+        match fields.len() == 7 && fields[2] == uid.to_string() {
+            true => Some(PathBuf::from(fields[6])),
+            false => None,
+        }
+    });
+
+    shell
+        .into_iter()
+        .chain(env::var_os("SHELL").map(PathBuf::from))
+        .find(|shell| shell.is_absolute() && shell.is_file())
+        .unwrap_or_else(|| PathBuf::from(FALLBACK_SHELL))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /* fixture() describes the user `alice`, 1000 on the host, who belongs to
+    `wheel` and `docker` on top of its primary group. */
+    fn fixture() -> Identity
+    {
+        Identity {
+            name: "alice".to_string(),
+            uid: 1000,
+            gid: 1000,
+            gecos: "Alice".to_string(),
+            home: "/home/alice".to_string(),
+            shell: "/bin/zsh".to_string(),
+            groups: vec![
+                ("alice".to_string(), 1000),
+                ("wheel".to_string(), 10),
+                ("docker".to_string(), 975),
+            ],
+        }
+    }
+
+    #[test]
+    fn passwd()
+    {
+        let identity = fixture();
+        let root = TempDir::new("unidis-identity").unwrap();
+
+        // The entry taking the UID of the user and the one taking its name
+        // are both replaced, the shell of the host is missing from the root
+        let passwd = "\
+root:x:0:0:root:/root:/bin/bash
+ubuntu:x:1000:1000:Ubuntu:/home/ubuntu:/bin/bash
+alice:x:1001:1001::/home/alice:/bin/sh
+";
+        assert_eq!(
+            merge_passwd(passwd, &identity, root.path()),
+            "\
+root:x:0:0:root:/root:/bin/bash
+alice:x:1000:1000:Alice:/home/alice:/bin/sh
+"
+        );
+
+        fs::create_dir(root.path().join("bin")).unwrap();
+        fs::write(root.path().join("bin/zsh"), "").unwrap();
+        assert_eq!(
+            merge_passwd("", &identity, root.path()),
+            "alice:x:1000:1000:Alice:/home/alice:/bin/zsh\n"
+        );
+    }
+
+    #[test]
+    fn group()
+    {
+        let identity = fixture();
+
+        // The user joins the groups with its GIDs, only once for `wheel`,
+        // and `docker` is left out as its name is taken by another GID
+        let group = "\
+root:x:0:
+wheel:x:10:alice
+users:x:100:bob
+docker:x:998:bob
+ubuntu:x:1000:
+";
+        assert_eq!(
+            merge_group(group, &identity),
+            "\
+root:x:0:
+wheel:x:10:alice
+users:x:100:bob
+docker:x:998:bob
+ubuntu:x:1000:alice
+"
+        );
+
+        let group = "root:x:0:\nadm:x:975:syslog\n";
+        assert_eq!(
+            merge_group(group, &identity),
+            "\
+root:x:0:
+adm:x:975:syslog,alice
+alice:x:1000:alice
+wheel:x:10:alice
+"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use crate::Libc::{self, Landlock::*, SyscallResult};
+
+use libc::{
+    close, fstat, open, stat, ENOENT, ENOSYS, EOPNOTSUPP, O_CLOEXEC, O_PATH, S_IFDIR, S_IFMT,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::CString,
+    fs, io, mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/* FILE_ACCESS are the accesses which apply to files, as opposed to
+directories, rules on files are restricted to them. */
+const FILE_ACCESS: u64 = LANDLOCK_ACCESS_FS_EXECUTE
+    | LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_READ_FILE
+    | LANDLOCK_ACCESS_FS_TRUNCATE;
+
+/* READ are the accesses needed to read a hierarchy. */
+const READ: u64 = LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR;
+
+/* WRITE are the accesses needed to modify a hierarchy, short of creating
+devices. */
+const WRITE: u64 = LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_REMOVE_DIR
+    | LANDLOCK_ACCESS_FS_REMOVE_FILE
+    | LANDLOCK_ACCESS_FS_MAKE_DIR
+    | LANDLOCK_ACCESS_FS_MAKE_REG
+    | LANDLOCK_ACCESS_FS_MAKE_SOCK
+    | LANDLOCK_ACCESS_FS_MAKE_FIFO
+    | LANDLOCK_ACCESS_FS_MAKE_SYM
+    | LANDLOCK_ACCESS_FS_REFER
+    | LANDLOCK_ACCESS_FS_TRUNCATE;
+
+/* Access is an action on the filesystem, `read` and `write` group the
+actions needed to read and modify a hierarchy. */
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access
+{
+    Execute,
+    WriteFile,
+    ReadFile,
+    ReadDir,
+    RemoveDir,
+    RemoveFile,
+    MakeChar,
+    MakeDir,
+    MakeReg,
+    MakeSock,
+    MakeFifo,
+    MakeBlock,
+    MakeSym,
+    Refer,
+    Truncate,
+    Read,
+    Write,
+}
+
+impl Access
+{
+    /* bits() converts the access to the LANDLOCK_ACCESS_FS_ bits. */
+    fn bits(self) -> u64
+    {
+        match self {
+            Access::Execute => LANDLOCK_ACCESS_FS_EXECUTE,
+            Access::WriteFile => LANDLOCK_ACCESS_FS_WRITE_FILE,
+            Access::ReadFile => LANDLOCK_ACCESS_FS_READ_FILE,
+            Access::ReadDir => LANDLOCK_ACCESS_FS_READ_DIR,
+            Access::RemoveDir => LANDLOCK_ACCESS_FS_REMOVE_DIR,
+            Access::RemoveFile => LANDLOCK_ACCESS_FS_REMOVE_FILE,
+            Access::MakeChar => LANDLOCK_ACCESS_FS_MAKE_CHAR,
+            Access::MakeDir => LANDLOCK_ACCESS_FS_MAKE_DIR,
+            Access::MakeReg => LANDLOCK_ACCESS_FS_MAKE_REG,
+            Access::MakeSock => LANDLOCK_ACCESS_FS_MAKE_SOCK,
+            Access::MakeFifo => LANDLOCK_ACCESS_FS_MAKE_FIFO,
+            Access::MakeBlock => LANDLOCK_ACCESS_FS_MAKE_BLOCK,
+            Access::MakeSym => LANDLOCK_ACCESS_FS_MAKE_SYM,
+            Access::Refer => LANDLOCK_ACCESS_FS_REFER,
+            Access::Truncate => LANDLOCK_ACCESS_FS_TRUNCATE,
+            Access::Read => READ,
+            Access::Write => WRITE,
+        }
+    }
+}
+
+/* Rule allows ACCESS beneath PATH, as seen from within the session, a
+relative PATH is relative to the working directory of the command. */
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Rule
+{
+    pub path: PathBuf,
+    pub access: Vec<Access>,
+}
+
+/* Profile is a Landlock ruleset, any access to the filesystem not allowed
+by one of its rules is denied. */
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Profile
+{
+    pub rules: Vec<Rule>,
+}
+
+impl Profile
+{
+    /* allow() adds a rule allowing ACCESS beneath PATH. */
+    pub fn allow(&mut self, path: &Path, access: &[Access])
+    {
+        self.rules.push(Rule {
+            path: path.to_path_buf(),
+            access: access.to_vec(),
+        });
+    }
+
+    /* extend() allows reading READ and modifying WRITE, along with
+    executing from both. */
+    pub fn extend<P: AsRef<Path>>(&mut self, read: &[P], write: &[P])
+    {
+        for path in read {
+            self.allow(path.as_ref(), &[Access::Read, Access::Execute]);
+        }
+        for path in write {
+            self.allow(
+                path.as_ref(),
+                &[Access::Read, Access::Write, Access::Execute],
+            );
+        }
+    }
+}
+
+/* SYSTEM_DIRS are the directories executables and libraries are installed
+to, the only ones the `untrusted` profile executes from. */
+const SYSTEM_DIRS: [&str; 9] = [
+    "/bin", "/lib", "/lib32", "/lib64", "/libx32", "/nix", "/opt", "/sbin", "/usr",
+];
+
+/* untrusted() builds the `untrusted` profile: read-only everywhere but the
+project directory the command is started from, no execution from /tmp. */
+fn untrusted() -> Profile
+{
+    // Rules only ever add up, so execution is allowed beneath the system
+    // directories rather than / as a whole
+    let mut profile = Profile::default();
+    profile.allow(Path::new("/"), &[Access::Read]);
+    for dir in &SYSTEM_DIRS {
+        profile.allow(Path::new(dir), &[Access::Read, Access::Execute]);
+    }
+    profile.allow(
+        Path::new("."),
+        &[Access::Read, Access::Write, Access::Execute],
+    );
+    profile.allow(Path::new("/tmp"), &[Access::Read, Access::Write]);
+    // Terminals and /dev/null are opened for writing by most tools
+    profile.allow(Path::new("/dev"), &[Access::Read, Access::WriteFile]);
+
+    profile
+}
+
+/* profile() loads the Landlock profile NAME, which is either a built-in
+profile or the path to a JSON profile, None meaning unrestricted. */
+pub fn profile(name: &str) -> Result<Option<Profile>, io::Error>
+{
+    match name {
+        "none" => Ok(None),
+        "untrusted" => Ok(Some(untrusted())),
+        path => {
+            let profile = fs::read(Path::new(path))?;
+            serde_json::from_slice(&profile)
+                .map(Some)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        }
+    }
+}
+
+/* handled() lists the accesses known to the Landlock ABI version ABI,
+later versions only ever add to them. */
+fn handled(abi: i64) -> u64
+{
+    let mut handled = (LANDLOCK_ACCESS_FS_MAKE_SYM << 1) - 1;
+    // Before ABI 2, files can never be renamed or linked across
+    // directories
+    if abi >= 2 {
+        handled |= LANDLOCK_ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        handled |= LANDLOCK_ACCESS_FS_TRUNCATE;
+    }
+
+    handled
+}
+
+/* add_rules() adds the rules of PROFILE to RULESET, given the accesses it
+HANDLED.  Rules on missing paths are skipped, as profiles are shared
+between distributions. */
+fn add_rules(ruleset: i32, handled: u64, profile: &Profile) -> SyscallResult
+{
+    for rule in &profile.rules {
+        let path = CString::new(rule.path.as_os_str().as_bytes()).unwrap();
+        let fd = unsafe { open(path.as_ptr(), O_PATH | O_CLOEXEC) };
+        if fd < 0 {
+            let errno = Libc::errno();
+            if errno == ENOENT {
+                continue;
+            }
+            return Err(errno);
+        }
+
+        let mut stat: stat = unsafe { mem::zeroed() };
+        let mut allowed = rule
+            .access
+            .iter()
+            .fold(0, |allowed, access| allowed | access.bits())
+            & handled;
+        if unsafe { fstat(fd, &mut stat) } == 0 && stat.st_mode & S_IFMT != S_IFDIR {
+            allowed &= FILE_ACCESS;
+        }
+
+        let res = Libc::landlock_add_rule(
+            ruleset,
+            &landlock_path_beneath_attr {
+                allowed_access: allowed,
+                parent_fd: fd,
+            },
+        );
+        unsafe { close(fd) };
+        res?;
+    }
+
+    Ok(0)
+}
+
+/* apply() confines the calling thread and its future children to PROFILE.
+On kernels without Landlock a warning is printed and nothing is enforced.
+no_new_privs has to be set beforehand. */
+pub fn apply(profile: &Profile) -> SyscallResult
+{
+    let abi = match Libc::landlock_create_ruleset(None, LANDLOCK_CREATE_RULESET_VERSION) {
+        Err(ENOSYS) | Err(EOPNOTSUPP) => {
+            println!("Landlock is not supported by the kernel, the profile is not enforced");
+            return Ok(0);
+        }
+        res => res?,
+    };
+
+    let handled = handled(abi);
+    let ruleset = Libc::landlock_create_ruleset(
+        Some(&landlock_ruleset_attr {
+            handled_access_fs: handled,
+        }),
+        0,
+    )? as i32;
+
+    let res =
+        add_rules(ruleset, handled, profile).and_then(|_| Libc::landlock_restrict_self(ruleset));
+    unsafe { close(ruleset) };
+
+    res
+}
@@ -1,4 +1,8 @@
+pub mod Capability;
 pub mod Clone;
+pub mod Ifreq;
+pub mod Landlock;
+pub mod Seccomp;
 
 use libc::*;
 use std::{
@@ -6,10 +10,11 @@ use std::{
     mem,
     os::unix::ffi::OsStrExt,
     path::Path,
+    ptr,
 };
 
 /* errno() returns the ERRNO value, typically of a syscall result. */
-fn errno() -> c_int
+pub fn errno() -> c_int
 {
     unsafe { *libc::__errno_location() }
 }
@@ -131,3 +136,271 @@ pub fn execvp(executable: *const c_char, argv: *const *const c_char) -> SyscallR
 {
     new_syscall_result::<i32>(unsafe { libc::execvp(executable, argv) }, None)
 }
+
+/* fork() is a wrapper against the syscall SYS_fork. */
+pub fn fork() -> SyscallResult
+{
+    new_syscall_result::<i32>(unsafe { libc::fork() }, None)
+}
+
+/* setns() is a wrapper against the syscall SYS_setns. */
+pub fn setns(fd: c_int, nstype: c_int) -> SyscallResult
+{
+    new_syscall_result::<i32>(unsafe { libc::setns(fd, nstype) }, None)
+}
+
+/* fchdir() is a wrapper against the syscall SYS_fchdir. */
+pub fn fchdir(fd: c_int) -> SyscallResult
+{
+    new_syscall_result::<i32>(unsafe { libc::fchdir(fd) }, None)
+}
+
+/* pidfd_open() is a wrapper against the syscall SYS_pidfd_open, returning
+a file descriptor referring to the process PID. */
+pub fn pidfd_open(pid: pid_t, flags: c_uint) -> SyscallResult
+{
+    new_syscall_result::<i64>(unsafe { libc::syscall(SYS_pidfd_open, pid, flags) }, None)
+}
+
+/* pidfd_send_signal() is a wrapper against the syscall
+SYS_pidfd_send_signal. */
+pub fn pidfd_send_signal(pidfd: c_int, sig: c_int, flags: c_uint) -> SyscallResult
+{
+    new_syscall_result::<i64>(
+        unsafe {
+            libc::syscall(
+                SYS_pidfd_send_signal,
+                pidfd,
+                sig,
+                ptr::null::<siginfo_t>(),
+                flags,
+            )
+        },
+        None,
+    )
+}
+
+/* pipe2() is a wrapper against the syscall SYS_pipe2, returning the read
+and write ends of the pipe. */
+pub fn pipe2(flags: c_int) -> Result<(c_int, c_int), c_int>
+{
+    let mut fds: [c_int; 2] = [-1, -1];
+    new_syscall_result::<i32>(unsafe { libc::pipe2(fds.as_mut_ptr(), flags) }, None)?;
+
+    Ok((fds[0], fds[1]))
+}
+
+/* socketpair() is a wrapper against the syscall SYS_socketpair, returning
+both ends of the connection. */
+pub fn socketpair(domain: c_int, ty: c_int) -> Result<(c_int, c_int), c_int>
+{
+    let mut fds: [c_int; 2] = [-1, -1];
+    new_syscall_result::<i32>(unsafe { libc::socketpair(domain, ty, 0, fds.as_mut_ptr()) }, None)?;
+
+    Ok((fds[0], fds[1]))
+}
+
+/* send_fds() sends DATA over the socket SOCKFD in a single message, passing
+FDS along through SCM_RIGHTS. */
+pub fn send_fds(sockfd: c_int, data: &[u8], fds: &[c_int]) -> SyscallResult
+{
+    let fds_size = mem::size_of_val(fds) as c_uint;
+    let mut control = vec![0u8; unsafe { CMSG_SPACE(fds_size) } as usize];
+
+    let mut iov = iovec {
+        iov_base: data.as_ptr() as *mut c_void,
+        iov_len: data.len(),
+    };
+    let mut msg: msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = control.len() as _;
+
+        unsafe {
+            let cmsg = CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = SOL_SOCKET;
+            (*cmsg).cmsg_type = SCM_RIGHTS;
+            (*cmsg).cmsg_len = CMSG_LEN(fds_size) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), CMSG_DATA(cmsg) as *mut c_int, fds.len());
+        }
+    }
+
+    new_syscall_result::<i64>(unsafe { libc::sendmsg(sockfd, &msg, 0) } as i64, None)
+}
+
+/* recv_fds() receives a message from the socket SOCKFD into BUF, along
+with any file descriptors passed through SCM_RIGHTS.  The number of bytes
+received is returned with the file descriptors. */
+pub fn recv_fds(sockfd: c_int, buf: &mut [u8], max_fds: usize) -> Result<(usize, Vec<c_int>), c_int>
+{
+    let fds_size = (max_fds * mem::size_of::<c_int>()) as c_uint;
+    let mut control = vec![0u8; unsafe { CMSG_SPACE(fds_size) } as usize];
+
+    let mut iov = iovec {
+        iov_base: buf.as_mut_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = control.len() as _;
+
+    let len = new_syscall_result::<i64>(
+        unsafe { libc::recvmsg(sockfd, &mut msg, MSG_CMSG_CLOEXEC) } as i64,
+        None,
+    )?;
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == SOL_SOCKET && (*cmsg).cmsg_type == SCM_RIGHTS {
+                let count = ((*cmsg).cmsg_len as usize - CMSG_LEN(0) as usize)
+                    / mem::size_of::<c_int>();
+                let data = CMSG_DATA(cmsg) as *const c_int;
+                fds.extend((0..count).map(|i| ptr::read_unaligned(data.add(i))));
+            }
+            cmsg = CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((len as usize, fds))
+}
+
+/* sethostname() is a wrapper against the syscall SYS_sethostname. */
+pub fn sethostname(name: &str) -> SyscallResult
+{
+    new_syscall_result::<i32>(
+        unsafe { libc::sethostname(name.as_ptr() as *const c_char, name.len()) },
+        None,
+    )
+}
+
+/* link_up() brings up the network interface NAME, in the manner of
+`ip link set NAME up`. */
+pub fn link_up(name: &str) -> SyscallResult
+{
+    let sockfd = new_syscall_result::<i32>(
+        unsafe { libc::socket(AF_INET, SOCK_DGRAM | SOCK_CLOEXEC, 0) },
+        None,
+    )? as c_int;
+
+    let mut ifreq: Ifreq::ifreq = unsafe { mem::zeroed() };
+    for (dst, src) in ifreq.ifr_name.iter_mut().zip(name.bytes().take(IFNAMSIZ - 1)) {
+        *dst = src as c_char;
+    }
+
+    let res = new_syscall_result::<i32>(unsafe { libc::ioctl(sockfd, SIOCGIFFLAGS, &mut ifreq) }, None)
+        .and_then(|_| {
+            ifreq.ifr_flags |= IFF_UP as c_short;
+            new_syscall_result::<i32>(unsafe { libc::ioctl(sockfd, SIOCSIFFLAGS, &ifreq) }, None)
+        });
+    unsafe { close(sockfd) };
+
+    res
+}
+
+/* seccomp() is a wrapper against the syscall SYS_seccomp, installing the
+filter PROG with SECCOMP_SET_MODE_FILTER. */
+pub fn seccomp(prog: &Seccomp::sock_fprog) -> SyscallResult
+{
+    new_syscall_result::<i64>(
+        unsafe { libc::syscall(SYS_seccomp, Seccomp::SECCOMP_SET_MODE_FILTER, 0, prog) },
+        None,
+    )
+}
+
+/* seccomp_listener() is seccomp() with SECCOMP_FILTER_FLAG_NEW_LISTENER,
+returning the file descriptor notified of the syscalls PROG defers to
+userspace. */
+pub fn seccomp_listener(prog: &Seccomp::sock_fprog) -> SyscallResult
+{
+    new_syscall_result::<i64>(
+        unsafe {
+            libc::syscall(
+                SYS_seccomp,
+                Seccomp::SECCOMP_SET_MODE_FILTER,
+                Seccomp::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+                prog,
+            )
+        },
+        None,
+    )
+}
+
+/* capset() is a wrapper against the syscall SYS_capset, setting the
+capabilities of the calling thread. */
+pub fn capset(data: &[Capability::cap_user_data; 2]) -> SyscallResult
+{
+    let mut header = Capability::cap_user_header {
+        version: Capability::_LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+
+    new_syscall_result::<i64>(
+        unsafe { libc::syscall(SYS_capset, &mut header, data.as_ptr()) },
+        None,
+    )
+}
+
+/* prctl() is a wrapper against the syscall SYS_prctl. */
+pub fn prctl(option: c_int, arg2: c_ulong, arg3: c_ulong) -> SyscallResult
+{
+    new_syscall_result::<i32>(unsafe { libc::prctl(option, arg2, arg3, 0, 0) }, None)
+}
+
+/* landlock_create_ruleset() is a wrapper against the syscall
+SYS_landlock_create_ruleset, with a NULL ATTR the ABI version is retrieved
+instead given LANDLOCK_CREATE_RULESET_VERSION. */
+pub fn landlock_create_ruleset(
+    attr: Option<&Landlock::landlock_ruleset_attr>,
+    flags: u32,
+) -> SyscallResult
+{
+    let (attr, size) = match attr {
+        None => (ptr::null(), 0),
+        Some(attr) => (
+            attr as *const Landlock::landlock_ruleset_attr,
+            mem::size_of::<Landlock::landlock_ruleset_attr>(),
+        ),
+    };
+
+    new_syscall_result::<i64>(
+        unsafe { libc::syscall(SYS_landlock_create_ruleset, attr, size, flags) },
+        None,
+    )
+}
+
+/* landlock_add_rule() is a wrapper against the syscall
+SYS_landlock_add_rule, adding a LANDLOCK_RULE_PATH_BENEATH rule. */
+pub fn landlock_add_rule(
+    ruleset: c_int,
+    attr: &Landlock::landlock_path_beneath_attr,
+) -> SyscallResult
+{
+    new_syscall_result::<i64>(
+        unsafe {
+            libc::syscall(
+                SYS_landlock_add_rule,
+                ruleset,
+                Landlock::LANDLOCK_RULE_PATH_BENEATH,
+                attr as *const Landlock::landlock_path_beneath_attr,
+                0,
+            )
+        },
+        None,
+    )
+}
+
+/* landlock_restrict_self() is a wrapper against the syscall
+SYS_landlock_restrict_self. */
+pub fn landlock_restrict_self(ruleset: c_int) -> SyscallResult
+{
+    new_syscall_result::<i64>(
+        unsafe { libc::syscall(SYS_landlock_restrict_self, ruleset, 0) },
+        None,
+    )
+}
@@ -0,0 +1,21 @@
+/* `struct __user_cap_header_struct` prototype ported, see
+linux/capability.h. */
+#[repr(C)]
+pub struct cap_user_header
+{
+    pub version: u32, /* _LINUX_CAPABILITY_VERSION_3 */
+    pub pid: i32,     /* 0 for the calling thread */
+}
+
+/* `struct __user_cap_data_struct` prototype ported, version 3 takes two
+of them for the lower and upper 32 capabilities. */
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct cap_user_data
+{
+    pub effective: u32,
+    pub permitted: u32,
+    pub inheritable: u32,
+}
+
+pub const _LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
@@ -22,3 +22,9 @@ pub struct clone_args {
     pub cgroup: __u64, /* File descriptor for target cgroup
                        of child (since Linux 5.7) */
 }
+
+/* CLONE_INTO_CGROUP does not fit the `int` flags of clone(2), so it is
+only available through clone3. */
+pub const CLONE_INTO_CGROUP: __u64 = 0x200000000; /* Place the child in the
+                                                  cgroup of `cgroup`
+                                                  (since Linux 5.7) */
@@ -0,0 +1,11 @@
+use libc::{c_char, c_short, IFNAMSIZ};
+
+/* `struct ifreq` prototype ported, restricted to the interface flags
+member of its union. */
+#[repr(C)]
+pub struct ifreq {
+    pub ifr_name: [c_char; IFNAMSIZ], /* Interface name */
+    pub ifr_flags: c_short,           /* Interface flags */
+    pub _pad: [u8; 22],               /* Remainder of the union
+                                      (struct sockaddr, struct ifmap) */
+}
@@ -0,0 +1,35 @@
+/* `struct landlock_ruleset_attr` prototype ported, restricted to its first
+member so that it is accepted by every version of Landlock, see
+linux/landlock.h. */
+#[repr(C)]
+pub struct landlock_ruleset_attr {
+    pub handled_access_fs: u64, /* Bitmask of handled actions */
+}
+
+/* `struct landlock_path_beneath_attr` prototype ported, the kernel expects
+it packed. */
+#[repr(C, packed)]
+pub struct landlock_path_beneath_attr {
+    pub allowed_access: u64, /* Bitmask of allowed actions */
+    pub parent_fd: i32,      /* File descriptor, preferably opened with
+                             O_PATH, of the hierarchy root */
+}
+
+pub const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+pub const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+pub const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+pub const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+pub const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+pub const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+pub const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+pub const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+pub const LANDLOCK_ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+pub const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+pub const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+pub const LANDLOCK_ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+pub const LANDLOCK_ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+pub const LANDLOCK_ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+pub const LANDLOCK_ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+pub const LANDLOCK_ACCESS_FS_REFER: u64 = 1 << 13; /* Since ABI 2 */
+pub const LANDLOCK_ACCESS_FS_TRUNCATE: u64 = 1 << 14; /* Since ABI 3 */
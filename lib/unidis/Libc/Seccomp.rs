@@ -0,0 +1,105 @@
+/* `struct sock_filter` prototype ported, see linux/filter.h. */
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct sock_filter
+{
+    pub code: u16, /* Actual filter code */
+    pub jt: u8,    /* Jump true */
+    pub jf: u8,    /* Jump false */
+    pub k: u32,    /* Generic multiuse field */
+}
+
+/* `struct sock_fprog` prototype ported, see linux/filter.h. */
+#[repr(C)]
+pub struct sock_fprog
+{
+    pub len: u16,                   /* Number of filter blocks */
+    pub filter: *const sock_filter, /* Filter blocks */
+}
+
+/* Classic BPF instruction classes and fields, see linux/bpf_common.h. */
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_ALU: u16 = 0x04;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+pub const BPF_W: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+pub const BPF_AND: u16 = 0x50;
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_JGT: u16 = 0x20;
+pub const BPF_JGE: u16 = 0x30;
+pub const BPF_K: u16 = 0x00;
+
+/* `struct seccomp_data` offsets, see linux/seccomp.h. */
+pub const SECCOMP_DATA_NR: u32 = 0; /* int nr */
+pub const SECCOMP_DATA_ARCH: u32 = 4; /* __u32 arch */
+pub const SECCOMP_DATA_ARGS: u32 = 16; /* __u64 args[6] */
+
+/* Filter return values, see linux/seccomp.h. */
+pub const SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000; /* Kill the process */
+pub const SECCOMP_RET_KILL_THREAD: u32 = 0x00000000; /* Kill the thread */
+pub const SECCOMP_RET_TRAP: u32 = 0x00030000; /* Disallow and force a SIGSYS */
+pub const SECCOMP_RET_ERRNO: u32 = 0x00050000; /* Return an errno */
+pub const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc00000; /* Notifies userspace */
+pub const SECCOMP_RET_TRACE: u32 = 0x7ff00000; /* Pass to a tracer or disallow */
+pub const SECCOMP_RET_LOG: u32 = 0x7ffc0000; /* Allow after logging */
+pub const SECCOMP_RET_ALLOW: u32 = 0x7fff0000; /* Allow */
+pub const SECCOMP_RET_DATA: u32 = 0x0000ffff; /* Mask for the return data */
+
+pub const SECCOMP_SET_MODE_FILTER: u32 = 1;
+
+/* Flags of SECCOMP_SET_MODE_FILTER, see linux/seccomp.h. */
+pub const SECCOMP_FILTER_FLAG_NEW_LISTENER: u32 = 1 << 3;
+
+/* `struct seccomp_data` prototype ported, see linux/seccomp.h. */
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct seccomp_data
+{
+    pub nr: i32,                  /* System call number */
+    pub arch: u32,                /* AUDIT_ARCH_* value */
+    pub instruction_pointer: u64, /* CPU instruction pointer */
+    pub args: [u64; 6],           /* Up to 6 system call arguments */
+}
+
+/* `struct seccomp_notif` prototype ported, see linux/seccomp.h. */
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct seccomp_notif
+{
+    pub id: u64,
+    pub pid: u32,
+    pub flags: u32,
+    pub data: seccomp_data,
+}
+
+/* `struct seccomp_notif_resp` prototype ported, see linux/seccomp.h. */
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct seccomp_notif_resp
+{
+    pub id: u64,
+    pub val: i64,
+    pub error: i32,
+    pub flags: u32,
+}
+
+/* Flags of `struct seccomp_notif_resp`, see linux/seccomp.h. */
+pub const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1 << 0;
+
+/* ioctl(2) requests of the notification listener, see linux/seccomp.h. */
+pub const SECCOMP_IOCTL_NOTIF_RECV: u64 = 0xc0502100; /* _IOWR('!', 0, struct seccomp_notif) */
+pub const SECCOMP_IOCTL_NOTIF_SEND: u64 = 0xc0182101; /* _IOWR('!', 1, struct seccomp_notif_resp) */
+pub const SECCOMP_IOCTL_NOTIF_ID_VALID: u64 = 0x40082102; /* _IOW('!', 2, __u64) */
+
+/* Audit architectures, see linux/audit.h. */
+#[cfg(target_arch = "x86_64")]
+pub const AUDIT_ARCH_X86_64: u32 = 0xc000003e;
+#[cfg(target_arch = "aarch64")]
+pub const AUDIT_ARCH_AARCH64: u32 = 0xc00000b7;
+#[cfg(target_arch = "riscv64")]
+pub const AUDIT_ARCH_RISCV64: u32 = 0xc00000f3;
+
+/* __X32_SYSCALL_BIT marks the x32 ABI syscalls on x86_64. */
+#[cfg(target_arch = "x86_64")]
+pub const X32_SYSCALL_BIT: u32 = 0x40000000;
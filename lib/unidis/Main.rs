@@ -1,8 +1,60 @@
-use unidis::{self, UnionFS};
+use unidis::{
+    self, Bootstrap, Capabilities, Conflicts, Daemon, Distro, Env, Export, Oci, Pkg, Session, Share,
+    UnionFS, Vm,
+};
 
-use std::{ffi::CString, fs, iter, marker, os::unix::ffi::OsStrExt, path, ptr};
-use structopt::{clap::AppSettings, StructOpt};
+use std::{
+    env,
+    ffi::CString,
+    fs, io, iter, marker,
+    os::unix::ffi::OsStrExt,
+    path, process, ptr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use structopt::{
+    clap::{AppSettings, ErrorKind},
+    StructOpt,
+};
 use strum::VariantNames;
+use strum_macros::{EnumString, EnumVariantNames};
+
+/* SECCOMP_PROFILES are the built-in seccomp profiles. */
+const SECCOMP_PROFILES: &[&str] = &["default", "strict", "none"];
+
+/* LANDLOCK_PROFILES are the built-in Landlock profiles. */
+const LANDLOCK_PROFILES: &[&str] = &["untrusted", "none"];
+
+/* ENV_PROFILES are the built-in environment profiles. */
+const ENV_PROFILES: &[&str] = &["host", "clean"];
+
+/* HOST_FILES_PROFILES are the built-in profiles of files passed through
+from the host. */
+const HOST_FILES_PROFILES: &[&str] = &["default", "none"];
+
+/* MASKS_PROFILES are the built-in profiles of masked and read-only paths. */
+const MASKS_PROFILES: &[&str] = &["default", "none"];
+
+/* Network is the network namespace to run the session in. */
+#[derive(Debug, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+enum Network
+{
+    // Share the network namespace of the host
+    Host,
+    // No network interface at all
+    None,
+    // Only the loopback interface
+    Loopback,
+}
+
+/* Ipc is the IPC namespace to run the session in. */
+#[derive(Debug, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+enum Ipc
+{
+    Host,
+    Private,
+}
 
 #[derive(StructOpt, Debug)]
 #[structopt(
@@ -22,32 +74,699 @@ struct Arguments
     /// Whether to remount /tmp
     #[structopt(long = "remount-tmp")]
     remount_tmp: bool,
-    /// Whether add /usr/bin and /usr/local/bin to the PATH env
+    /// Whether to append the executable directories of the distro to PATH
     #[structopt(long = "update-path")]
     update_path: bool,
+    /// Whether to prepend the executable directories of the distro to PATH
+    #[structopt(long = "prepend-path", conflicts_with = "update-path")]
+    prepend_path: bool,
+    /// Leave the passwd and group of the distro as is, rather than adding
+    /// the user and its groups to them
+    #[structopt(long = "no-sync-identity")]
+    no_sync_identity: bool,
+    /// Environment profile of the command, either host, clean or the path to
+    /// a JSON profile
+    #[structopt(long = "env-profile", default_value = "host")]
+    env_profile: String,
+    /// Clear the environment but for TERM, DISPLAY, HOME and the like
+    #[structopt(long = "clearenv")]
+    clearenv: bool,
+    /// Variable to keep with --clearenv, replacing the defaults
+    #[structopt(long = "keepenv", number_of_values = 1)]
+    keepenv: Vec<String>,
+    /// File of KEY=VALUE lines to load into the environment
+    #[structopt(long = "env-file", parse(from_os_str), number_of_values = 1)]
+    env_file: Vec<path::PathBuf>,
+    /// Output of `nix print-dev-env --json` to load the environment of a Nix
+    /// dev shell from
+    #[structopt(long = "nix-env", parse(from_os_str))]
+    nix_env: Option<path::PathBuf>,
+    /// Variable to remove from the environment
+    #[structopt(long = "unsetenv", number_of_values = 1)]
+    unsetenv: Vec<String>,
+    /// KEY=VALUE pair to set in the environment
+    #[structopt(long = "setenv", number_of_values = 1)]
+    setenv: Vec<String>,
+    /// Override the RIGHT (read-write) side of the union, defaults to / or
+    /// to the root directory itself with --as-root
+    #[structopt(long = "right", parse(from_os_str))]
+    right: Option<path::PathBuf>,
+    /// Stay root within the session, with every capability, so that the
+    /// package manager of the root directory can install into it
+    #[structopt(long = "as-root")]
+    as_root: bool,
+    /// Record the owners files are given in their user.rootlesscontainers
+    /// extended attribute, for owners the session cannot map
+    #[structopt(long = "fake-ownership")]
+    fake_ownership: bool,
+    /// Name to record the session under, defaults to its PID
+    #[structopt(long = "name", parse(try_from_str = parse_session_name))]
+    name: Option<String>,
+    /// Run the command within a warm session kept by the daemon
+    #[structopt(long = "warm", conflicts_with_all = &["name", "tty"])]
+    warm: bool,
+    /// Run the command on a pseudo-terminal of its own
+    #[structopt(long = "tty")]
+    tty: bool,
+    /// Network namespace to run in
+    #[structopt(
+        long = "net", default_value = "host",
+        possible_values = Network::VARIANTS,
+    )]
+    net: Network,
+    /// IPC namespace to run in
+    #[structopt(
+        long = "ipc", default_value = "host",
+        possible_values = Ipc::VARIANTS,
+    )]
+    ipc: Ipc,
+    /// Give the session a hostname of its own, defaults to the distro name
+    #[structopt(long = "hostname", min_values = 0, require_equals = true)]
+    hostname: Option<Option<String>>,
+    /// Offset the monotonic and boottime clocks, in seconds or suffixed by
+    /// one of s, m, h or d
+    #[structopt(long = "time-offset", parse(try_from_str = parse_time_offset), allow_hyphen_values = true)]
+    time_offset: Option<i64>,
+    /// Memory limit of the session, in bytes or suffixed by one of K, M, G or
+    /// T
+    #[structopt(long = "memory-max", parse(try_from_str = parse_size))]
+    memory_max: Option<u64>,
+    /// CPU weight of the session relative to others, from 1 to 10000
+    #[structopt(long = "cpu-weight", parse(try_from_str = parse_cpu_weight))]
+    cpu_weight: Option<u64>,
+    /// Maximum number of processes in the session
+    #[structopt(long = "pids-max")]
+    pids_max: Option<u64>,
+    /// Seccomp profile to confine the command with, either default, strict,
+    /// none or the path to an OCI seccomp profile
+    #[structopt(long = "seccomp", default_value = "default")]
+    seccomp: String,
+    /// Capabilities to leave to the command, none are left by default, ALL
+    /// stands for every capability
+    #[structopt(long = "cap-add", parse(try_from_str = parse_capability), number_of_values = 1)]
+    cap_add: Vec<u64>,
+    /// Capabilities to take from the command, applied after --cap-add
+    #[structopt(long = "cap-drop", parse(try_from_str = parse_capability), number_of_values = 1)]
+    cap_drop: Vec<u64>,
+    /// Paths to mask and make read-only within the session, either default,
+    /// none or the path to a profile listing maskedPaths and readonlyPaths
+    /// as in the runtime-spec of OCI
+    #[structopt(long = "masks", default_value = "default")]
+    masks: String,
+    /// Files of the host to bind over those of the distro, either default
+    /// for DNS, hosts and timezone, none or the path to a JSON profile
+    #[structopt(long = "host-files", default_value = "default")]
+    host_files: String,
+    /// File to leave out of the host files profile, e.g. /etc/hosts
+    #[structopt(long = "no-host-file", parse(from_os_str), number_of_values = 1)]
+    no_host_file: Vec<path::PathBuf>,
+    /// Services of the desktop to share with the session, through their
+    /// sockets and variables
+    #[structopt(
+        long = "share", use_delimiter = true,
+        possible_values = Share::Preset::VARIANTS,
+    )]
+    share: Vec<String>,
+    /// Landlock profile to confine the command with, either untrusted, none
+    /// or the path to a JSON profile
+    #[structopt(long = "landlock", default_value = "none")]
+    landlock: String,
+    /// Allow the command to read and execute beneath a path on top of the
+    /// Landlock profile
+    #[structopt(long = "landlock-read", parse(from_os_str), number_of_values = 1)]
+    landlock_read: Vec<path::PathBuf>,
+    /// Allow the command to modify beneath a path on top of the Landlock
+    /// profile
+    #[structopt(long = "landlock-write", parse(from_os_str), number_of_values = 1)]
+    landlock_write: Vec<path::PathBuf>,
+    /// Support root directory to merge
+    #[structopt(parse(from_os_str))]
+    left: path::PathBuf,
+    /// Command to run, defaults to the login shell of the user
+    #[structopt(use_delimiter(false))]
+    argv: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting = AppSettings::UnifiedHelpMessage)]
+struct ConflictsArguments
+{
     /// Override the RIGHT (read-write) side of the union
     #[structopt(long = "right", parse(from_os_str), default_value = "/")]
     right: path::PathBuf,
-    /// Support root directory to merge
+    /// Whether to print the conflicts as JSON
+    #[structopt(long = "json")]
+    json: bool,
+    /// Support root directory to check
     #[structopt(parse(from_os_str))]
     left: path::PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    setting = AppSettings::ArgRequiredElseHelp,
+    setting = AppSettings::TrailingVarArg,
+    setting = AppSettings::UnifiedHelpMessage,
+)]
+struct ExecArguments
+{
+    /// Session to attach to
+    session: String,
     /// Command to run
-    #[structopt(use_delimiter(false))]
+    #[structopt(use_delimiter(false), required = true)]
     argv: Vec<String>,
 }
 
-/* Wrapper routine to library. */
-pub fn main()
+#[derive(StructOpt, Debug)]
+#[structopt(
+    setting = AppSettings::SubcommandRequiredElseHelp,
+    setting = AppSettings::UnifiedHelpMessage,
+    setting = AppSettings::VersionlessSubcommands,
+)]
+enum Command
+{
+    /// Run a command within the union of a root directory and the host
+    Run(Box<Arguments>),
+    /// List the paths of a root directory shadowed by the host
+    Conflicts(ConflictsArguments),
+    /// Open a shell within a running session
+    Enter
+    {
+        /// Session to attach to
+        session: String,
+    },
+    /// Run a command within a running session
+    Exec(ExecArguments),
+    /// List the recorded sessions
+    Ps
+    {
+        /// Whether to print the sessions as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+    /// Stop a running session and tear down its union
+    Stop
+    {
+        /// Session to stop
+        session: String,
+    },
+    /// Tear down the sessions whose owner died
+    Gc,
+    /// Keep warm sessions alive to run commands started with --warm
+    Daemon,
+    /// Run bundles following the runtime-spec of OCI
+    Oci(OciCommand),
+    /// Export a command or desktop entry of a root directory to the host
+    Export(ExportArguments),
+    /// Remove an exported command or desktop entry
+    Unexport
+    {
+        /// Name of the export
+        name: String,
+    },
+    /// List the exported commands and desktop entries
+    ListExports
+    {
+        /// Whether to print the exports as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+    /// Manage the packages of a root directory with its own package manager
+    Pkg(PkgCommand),
+    /// Manage the root directories kept by unidis
+    Distro(DistroCommand),
+    /// Run managed roots within virtual machines
+    Vm(VmCommand),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
+enum VmCommand
+{
+    /// Boot a managed root within a virtual machine, sharing its root
+    /// directory through virtiofs
+    Run(VmArguments),
+}
+
+#[derive(StructOpt, Debug)]
+struct VmArguments
+{
+    /// Memory of the virtual machine
+    #[structopt(long = "memory", parse(try_from_str = parse_size), default_value = "4G")]
+    memory: u64,
+    /// Number of CPUs of the virtual machine
+    #[structopt(long = "cpus", default_value = "4")]
+    cpus: u32,
+    /// How to show the screen of the virtual machine
+    #[structopt(long = "display", possible_values = Vm::Display::VARIANTS, default_value = "gtk")]
+    display: Vm::Display,
+    /// Forward a port of the host to the guest, as [tcp|udp:]HOST:GUEST
+    #[structopt(long = "forward", parse(try_from_str = parse_forward), number_of_values = 1)]
+    forward: Vec<Vm::Forward>,
+    /// Installation ISO to boot from, defaults to the first one in the iso
+    /// directory of the managed root
+    #[structopt(long = "iso", parse(from_os_str))]
+    iso: Option<path::PathBuf>,
+    /// Directory holding OVMF_CODE.fd and OVMF_VARS.fd, looked up where
+    /// distributions install OVMF by default
+    #[structopt(long = "ovmf", parse(from_os_str))]
+    ovmf: Option<path::PathBuf>,
+    /// Path of virtiofsd, looked up in PATH and libexec by default
+    #[structopt(long = "virtiofsd", parse(from_os_str))]
+    virtiofsd: Option<path::PathBuf>,
+    /// Emulate the CPU rather than use KVM
+    #[structopt(long = "no-kvm")]
+    no_kvm: bool,
+    /// Print the command lines instead of running them
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// Whether to print errors as JSON
+    #[structopt(long = "json")]
+    json: bool,
+    /// Name of the managed root
+    #[structopt(parse(try_from_str = parse_distro_name))]
+    name: String,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
+enum DistroCommand
+{
+    /// Bootstrap a new managed root with the bootstrap tool of a distribution
+    Bootstrap(BootstrapArguments),
+}
+
+#[derive(StructOpt, Debug)]
+struct BootstrapArguments
+{
+    /// Bootstrap tool to use
+    #[structopt(long = "method", possible_values = Bootstrap::Method::VARIANTS)]
+    method: Bootstrap::Method,
+    /// Root directory providing the bootstrap tool, defaults to the host
+    #[structopt(long = "tool-root", parse(from_os_str), default_value = "/")]
+    tool_root: path::PathBuf,
+    /// Release to bootstrap, the suite of debootstrap or the releasever of dnf
+    #[structopt(long = "release")]
+    release: Option<String>,
+    /// Mirror, or path of a local repository, to fetch the packages from
+    /// instead of the configured ones
+    #[structopt(long = "mirror", number_of_values = 1)]
+    mirror: Vec<String>,
+    /// Directory of cached packages to use and fill
+    #[structopt(long = "cache", parse(from_os_str))]
+    cache: Option<path::PathBuf>,
+    /// Do not check the signatures of the packages, e.g. of an unsigned local
+    /// repository
+    #[structopt(long = "no-verify")]
+    no_verify: bool,
+    /// Whether to print errors as JSON
+    #[structopt(long = "json")]
+    json: bool,
+    /// Name of the managed root
+    #[structopt(parse(try_from_str = parse_distro_name))]
+    name: String,
+    /// Packages to install, defaults to the base of the distribution
+    packages: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
+enum PkgCommand
+{
+    /// Install packages into a root directory
+    Install(PkgArguments),
+    /// Remove packages from a root directory
+    Remove(PkgArguments),
+    /// Search the repositories of a root directory
+    Search(PkgArguments),
+}
+
+#[derive(StructOpt, Debug)]
+struct PkgArguments
+{
+    /// Package manager to use, detected from the os-release of the root
+    /// directory by default
+    #[structopt(long = "manager", possible_values = Pkg::Manager::VARIANTS)]
+    manager: Option<Pkg::Manager>,
+    /// Answer yes to the questions of the package manager
+    #[structopt(short = "y", long = "yes")]
+    yes: bool,
+    /// Whether to print errors as JSON
+    #[structopt(long = "json")]
+    json: bool,
+    /// Root directory to manage
+    #[structopt(parse(from_os_str))]
+    left: path::PathBuf,
+    /// Packages, or terms to search for
+    #[structopt(required = true)]
+    packages: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+struct ExportArguments
+{
+    /// Name of the wrapper, defaults to the name of the command
+    #[structopt(long = "name", parse(try_from_str = parse_export_name))]
+    name: Option<String>,
+    /// Export the desktop entry of that name from /usr/share/applications
+    /// rather than a command
+    #[structopt(long = "desktop")]
+    desktop: bool,
+    /// Run the command within a warm session kept by the daemon
+    #[structopt(long = "warm")]
+    warm: bool,
+    /// Option to pass to `unidis run`, e.g. --run-option=--share=wayland
+    #[structopt(long = "run-option", number_of_values = 1, allow_hyphen_values = true)]
+    run_option: Vec<String>,
+    /// Root directory the command belongs to
+    #[structopt(parse(from_os_str))]
+    left: path::PathBuf,
+    /// Command to export, either a name looked up in the PATH of the root
+    /// directory or an absolute path within it
+    command: String,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
+enum OciCommand
+{
+    /// Run the process of a bundle within a session
+    Run
+    {
+        /// Union filesystem to use
+        #[structopt(
+            short = "t", long = "unionfs", default_value = "fuseunionfs",
+            possible_values = UnionFS::SupportedUnionFS::VARIANTS,
+        )]
+        unionfs: UnionFS::SupportedUnionFS,
+        /// Name to record the session under, defaults to its PID
+        #[structopt(long = "name")]
+        name: Option<String>,
+        /// Directory holding the config.json and root of the bundle
+        #[structopt(parse(from_os_str))]
+        bundle: path::PathBuf,
+    },
+}
+
+/* parse_time_offset() parses OFFSET into seconds, a negative offset moves
+the clocks back. */
+fn parse_time_offset(offset: &str) -> Result<i64, String>
+{
+    let (value, unit) = match offset.char_indices().last() {
+        Some((i, 's')) => (&offset[..i], 1),
+        Some((i, 'm')) => (&offset[..i], 60),
+        Some((i, 'h')) => (&offset[..i], 60 * 60),
+        Some((i, 'd')) => (&offset[..i], 24 * 60 * 60),
+        _ => (offset, 1),
+    };
+
+    value
+        .parse::<i64>()
+        .ok()
+        .and_then(|value| value.checked_mul(unit))
+        .ok_or_else(|| format!("invalid time offset {:?}", offset))
+}
+
+/* parse_session_name() accepts NAME if it is fit to record a session
+under. */
+fn parse_session_name(name: &str) -> Result<String, String>
+{
+    match Session::valid_id(name) {
+        true => Ok(name.to_string()),
+        false => Err(format!("invalid session name {:?}", name)),
+    }
+}
+
+/* parse_distro_name() accepts NAME if it is fit to name a managed root. */
+fn parse_distro_name(name: &str) -> Result<String, String>
+{
+    match Distro::valid_name(name) {
+        true => Ok(name.to_string()),
+        false => Err(format!("invalid managed root name {:?}", name)),
+    }
+}
+
+/* parse_export_name() accepts NAME if it is fit to name a wrapper. */
+fn parse_export_name(name: &str) -> Result<String, String>
+{
+    match Export::valid_name(name) {
+        true => Ok(name.to_string()),
+        false => Err(format!("invalid export name {:?}", name)),
+    }
+}
+
+/* parse_size() parses SIZE into bytes. */
+fn parse_size(size: &str) -> Result<u64, String>
+{
+    let (value, shift) = match size.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 30),
+        Some((i, 'T')) | Some((i, 't')) => (&size[..i], 40),
+        _ => (size, 0),
+    };
+
+    value
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(1 << shift))
+        .filter(|value| *value > 0)
+        .ok_or_else(|| format!("invalid size {:?}", size))
+}
+
+/* parse_forward() parses the port forward SPEC. */
+fn parse_forward(spec: &str) -> Result<Vm::Forward, String>
+{
+    Vm::Forward::parse(spec).ok_or_else(|| format!("invalid port forward {:?}", spec))
+}
+
+/* parse_cpu_weight() parses WEIGHT, restricted to the range of cpu.weight. */
+fn parse_cpu_weight(weight: &str) -> Result<u64, String>
+{
+    weight
+        .parse::<u64>()
+        .ok()
+        .filter(|weight| (1..=10000).contains(weight))
+        .ok_or_else(|| format!("invalid CPU weight {:?}", weight))
+}
+
+/* parse_capability() parses the capability NAME into a set. */
+fn parse_capability(name: &str) -> Result<u64, String>
+{
+    Capabilities::parse(name).ok_or_else(|| format!("invalid capability {:?}", name))
+}
+
+/* caps() computes the capabilities left to the command of ARGS. */
+fn caps(args: &Arguments) -> u64
+{
+    let all = if args.as_root { Capabilities::ALL } else { 0 };
+    let add = args.cap_add.iter().fold(all, |caps, cap| caps | cap);
+    let drop = args.cap_drop.iter().fold(0, |caps, cap| caps | cap);
+
+    add & !drop
+}
+
+/* resolve_profile() resolves the profile PROFILE, which is passed along as
+an absolute path unless it is one of the BUILTIN profiles. */
+fn resolve_profile(profile: &str, builtin: &[&str]) -> String
+{
+    if builtin.contains(&profile) {
+        return profile.to_string();
+    }
+
+    fs::canonicalize(profile)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| profile.to_string())
+}
+
+/* flags() translates ARGS into the bit flags of `unidis_attrs`. */
+fn flags(args: &Arguments) -> u64
+{
+    let mut flags: u64 = 0;
+    if args.remount_tmp {
+        flags |= unidis::REMOUNT_TMP;
+    }
+    if args.update_path {
+        flags |= unidis::UPDATE_PATH;
+    }
+    if args.as_root {
+        flags |= unidis::AS_ROOT;
+    }
+    if args.fake_ownership {
+        flags |= unidis::FAKE_OWNERSHIP;
+    }
+    if !args.no_sync_identity {
+        flags |= unidis::SYNC_IDENTITY;
+    }
+    if args.prepend_path {
+        flags |= unidis::PREPEND_PATH;
+    }
+    if args.clearenv {
+        flags |= unidis::CLEAR_ENV;
+    }
+    if args.tty {
+        flags |= unidis::TTY;
+    }
+    match args.net {
+        Network::Host => {}
+        Network::None => flags |= unidis::NET_NONE,
+        Network::Loopback => flags |= unidis::NET_LOOPBACK,
+    }
+    if let Ipc::Private = args.ipc {
+        flags |= unidis::PRIVATE_IPC;
+    }
+    if args.hostname.is_some() {
+        flags |= unidis::PRIVATE_UTS;
+    }
+    if args.time_offset.is_some() {
+        flags |= unidis::PRIVATE_TIME;
+    }
+    if args.memory_max.is_some() || args.cpu_weight.is_some() || args.pids_max.is_some() {
+        flags |= unidis::CGROUP;
+    }
+
+    flags
+}
+
+/* env_files() resolves the environment files of ARGS, which are read from
+wherever the session is set up. */
+fn env_files(args: &Arguments) -> Vec<path::PathBuf>
+{
+    args.env_file
+        .iter()
+        .map(|file| fs::canonicalize(file).unwrap_or_else(|_| file.clone()))
+        .collect()
+}
+
+/* nix_env() resolves the Nix dev shell of ARGS, like env_files(). */
+fn nix_env(args: &Arguments) -> Option<path::PathBuf>
+{
+    args.nix_env
+        .as_ref()
+        .map(|file| fs::canonicalize(file).unwrap_or_else(|_| file.clone()))
+}
+
+/* right() resolves the RIGHT side of the union of ARGS, a session as root
+writes to the root directory rather than to the host. */
+fn right(args: &Arguments) -> path::PathBuf
+{
+    let right = match (&args.right, args.as_root) {
+        (Some(right), _) => right,
+        (None, true) => &args.left,
+        (None, false) => path::Path::new("/"),
+    };
+
+    fs::canonicalize(right).unwrap()
+}
+
+/* cstrings() converts STRINGS for the library. */
+fn cstrings<I, S>(strings: I) -> Vec<CString>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<Vec<u8>>,
+{
+    strings
+        .into_iter()
+        .map(|string| CString::new(string).unwrap())
+        .collect()
+}
+
+/* cpaths() converts PATHS for the library. */
+fn cpaths(paths: &[path::PathBuf]) -> Vec<CString>
+{
+    cstrings(paths.iter().map(|path| path.as_os_str().as_bytes()))
+}
+
+/* nul_terminated() lists the pointers to CSTRINGS, followed by NULL. */
+fn nul_terminated(cstrings: &[CString]) -> Vec<*const libc::c_char>
+{
+    cstrings
+        .iter()
+        .map(|cstr| cstr.as_ptr())
+        .chain(iter::once(ptr::null()))
+        .collect()
+}
+
+/* run_warm() runs the command of ARGS through the daemon, returning None
+if the daemon could not be reached. */
+fn run_warm(args: &Arguments) -> Option<i32>
+{
+    let env_profile = resolve_profile(&args.env_profile, ENV_PROFILES);
+    let mut env_profile = match Env::profile(&env_profile) {
+        Ok(profile) => profile,
+        Err(error) => {
+            println!(
+                "Failed to load environment profile {}, got {:?}",
+                env_profile, error
+            );
+            return Some(1);
+        }
+    };
+    env_profile.keep.extend(args.keepenv.iter().cloned());
+    env_profile.files.extend(env_files(args));
+    env_profile.nix = nix_env(args).or(env_profile.nix);
+    env_profile.unset.extend(args.unsetenv.iter().cloned());
+    env_profile.set.extend(args.setenv.iter().cloned());
+
+    let request = Daemon::Request {
+        left: fs::canonicalize(&args.left).unwrap(),
+        right: right(args),
+        unionfs: args.unionfs,
+        flags: flags(args),
+        hostname: args.hostname.clone().flatten(),
+        time_offset: args.time_offset.unwrap_or(0),
+        memory_max: args.memory_max.unwrap_or(0),
+        cpu_weight: args.cpu_weight.unwrap_or(0),
+        pids_max: args.pids_max.unwrap_or(0),
+        masks: Some(resolve_profile(&args.masks, MASKS_PROFILES)),
+        host_files: Some(resolve_profile(&args.host_files, HOST_FILES_PROFILES)),
+        host_files_skip: args.no_host_file.clone(),
+        share: args.share.clone(),
+        seccomp: Some(resolve_profile(&args.seccomp, SECCOMP_PROFILES)),
+        caps: caps(args),
+        landlock: Some(resolve_profile(&args.landlock, LANDLOCK_PROFILES)),
+        landlock_read: args.landlock_read.clone(),
+        landlock_write: args.landlock_write.clone(),
+        argv: args.argv.clone(),
+        cwd: env::current_dir().unwrap(),
+        env: env::vars().collect(),
+        env_profile,
+    };
+
+    match Daemon::request(&request) {
+        Ok(status) if libc::WIFSIGNALED(status) => Some(128 + libc::WTERMSIG(status)),
+        Ok(status) => Some(libc::WEXITSTATUS(status)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound
+            || error.kind() == io::ErrorKind::ConnectionRefused =>
+        {
+            println!("Daemon is not running, starting a cold session instead");
+            None
+        }
+        Err(error) => {
+            println!("Failed to run through the daemon, got {:?}", error);
+            Some(1)
+        }
+    }
+}
+
+/* run() sets up and runs a command through the library, returning the exit
+status of the command, or non-zero if it failed to run it. */
+fn run(args: Arguments) -> i64
 {
-    let args = Arguments::from_args();
     println!("{:?}", &args);
 
+    if args.warm && !args.argv.is_empty() {
+        if let Some(code) = run_warm(&args) {
+            process::exit(code);
+        }
+    }
+
     // right -> char *
-    let right_osstr = fs::canonicalize(args.right).unwrap();
+    let right_osstr = right(&args);
     let right = CString::new(right_osstr.as_os_str().as_bytes()).unwrap();
 
     // left -> char *
-    let left_osstr = fs::canonicalize(args.left).unwrap();
+    let left_osstr = fs::canonicalize(&args.left).unwrap();
     let left = CString::new(left_osstr.as_os_str().as_bytes()).unwrap();
 
     // argv -> char ** + NUL-terminated
@@ -64,15 +783,35 @@ pub fn main()
         .collect::<Vec<_>>();
 
     // flags -> bit flags
-    let mut flags: u64 = 0;
-    if args.remount_tmp {
-        flags = flags | unidis::REMOUNT_TMP;
-    }
-    if args.update_path {
-        flags = flags | unidis::UPDATE_PATH;
-    }
-
-    let flags = flags;
+    let flags = flags(&args);
+    let caps = caps(&args);
+    let env_files = cpaths(&env_files(&args));
+    let nix_env = cpaths(&nix_env(&args).into_iter().collect::<Vec<_>>()).pop();
+    let hostname = args
+        .hostname
+        .flatten()
+        .map(|hostname| CString::new(hostname).unwrap());
+    let seccomp = CString::new(resolve_profile(&args.seccomp, SECCOMP_PROFILES)).unwrap();
+    let masks = CString::new(resolve_profile(&args.masks, MASKS_PROFILES)).unwrap();
+    let host_files = CString::new(resolve_profile(&args.host_files, HOST_FILES_PROFILES)).unwrap();
+    let host_files_skip = cpaths(&args.no_host_file);
+    let share = cstrings(args.share.iter().map(String::as_str));
+    let landlock = CString::new(resolve_profile(&args.landlock, LANDLOCK_PROFILES)).unwrap();
+    let landlock_read = cpaths(&args.landlock_read);
+    let landlock_write = cpaths(&args.landlock_write);
+    let env_profile = CString::new(resolve_profile(&args.env_profile, ENV_PROFILES)).unwrap();
+    let env_keep = cstrings(args.keepenv.iter().map(String::as_str));
+    let env_unset = cstrings(args.unsetenv.iter().map(String::as_str));
+    let env_set = cstrings(args.setenv.iter().map(String::as_str));
+    let host_files_skip = nul_terminated(&host_files_skip);
+    let share = nul_terminated(&share);
+    let landlock_read = nul_terminated(&landlock_read);
+    let landlock_write = nul_terminated(&landlock_write);
+    let env_keep = nul_terminated(&env_keep);
+    let env_files = nul_terminated(&env_files);
+    let env_unset = nul_terminated(&env_unset);
+    let env_set = nul_terminated(&env_set);
+    let name = args.name.map(|name| CString::new(name).unwrap());
     let unidis_attrs = &unidis::unidis_attrs {
         _phantom: marker::PhantomData,
         left: left.as_ptr(),
@@ -81,7 +820,450 @@ pub fn main()
         argv: argv.as_ptr(),
         flags,
         unionfs: args.unionfs,
+        session: name.as_ref().map_or(ptr::null(), |name| name.as_ptr()),
+        hostname: hostname.as_ref().map_or(ptr::null(), |hostname| hostname.as_ptr()),
+        time_offset: args.time_offset.unwrap_or(0),
+        memory_max: args.memory_max.unwrap_or(0),
+        cpu_weight: args.cpu_weight.unwrap_or(0),
+        pids_max: args.pids_max.unwrap_or(0),
+        seccomp: seccomp.as_ptr(),
+        caps,
+        landlock: landlock.as_ptr(),
+        landlock_read: landlock_read.as_ptr(),
+        landlock_write: landlock_write.as_ptr(),
+        masks: masks.as_ptr(),
+        env: env_profile.as_ptr(),
+        env_keep: env_keep.as_ptr(),
+        env_files: env_files.as_ptr(),
+        env_unset: env_unset.as_ptr(),
+        env_set: env_set.as_ptr(),
+        nix_env: nix_env.as_ref().map_or(ptr::null(), |nix_env| nix_env.as_ptr()),
+        host_files: host_files.as_ptr(),
+        host_files_skip: host_files_skip.as_ptr(),
+        share: share.as_ptr(),
+    };
+
+    // The child of the session only returns if it failed to run the command,
+    // it must not carry on as the caller would
+    let pid = process::id();
+    let res = unidis::unidis(unidis_attrs);
+    if process::id() != pid {
+        process::exit(res as i32);
+    }
+    res
+}
+
+/* conflicts() reports the paths of LEFT that RIGHT takes precedence over. */
+fn conflicts(args: ConflictsArguments)
+{
+    let left = fs::canonicalize(args.left).unwrap();
+    let right = fs::canonicalize(args.right).unwrap();
+
+    let conflicts = match Conflicts::conflicts(&left, &right) {
+        Ok(conflicts) => conflicts,
+        Err(error) => {
+            println!("Failed to compare {:?} against {:?}, got {:?}", left, right, error);
+            process::exit(1);
+        }
     };
 
-    unidis::unidis(unidis_attrs);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&conflicts).unwrap());
+        return;
+    }
+
+    for conflict in &conflicts {
+        match conflict {
+            Conflicts::Conflict::Shadowed { path, .. } => {
+                println!("shadowed   {}", path.display());
+            }
+            Conflicts::Conflict::DirectoryFile { path, left, right } => {
+                println!(
+                    "directory  {} ({:?} <- {:?})",
+                    path.display(),
+                    left,
+                    right
+                );
+            }
+            Conflicts::Conflict::Symlink { path, left, right } => {
+                println!(
+                    "symlink    {} ({} <- {})",
+                    path.display(),
+                    left.display(),
+                    right.display()
+                );
+            }
+            Conflicts::Conflict::Unreadable { path, error } => {
+                println!("unreadable {} ({})", path.display(), error);
+            }
+        }
+    }
+}
+
+/* attach() runs ARGV within the running session SESSION_ID. */
+fn attach(session_id: &str, argv: Vec<String>)
+{
+    let session = load_session(session_id);
+
+    let argv = argv
+        .into_iter()
+        .map(|arg| CString::new(arg).unwrap())
+        .collect::<Vec<_>>();
+
+    match session.attach(&argv) {
+        Err(_) => process::exit(1),
+        Ok(status) if libc::WIFSIGNALED(status as i32) => {
+            process::exit(128 + libc::WTERMSIG(status as i32))
+        }
+        Ok(status) => process::exit(libc::WEXITSTATUS(status as i32)),
+    }
+}
+
+/* load_session() reads the session SESSION_ID, exiting if it does not
+exist. */
+fn load_session(session_id: &str) -> Session::Session
+{
+    match Session::Session::load(session_id) {
+        Ok(session) => session,
+        Err(error) => {
+            println!("Failed to find session {}, got {:?}", session_id, error);
+            process::exit(1);
+        }
+    }
+}
+
+/* list_sessions() reads every recorded session, exiting on failure. */
+fn list_sessions() -> Vec<Session::Session>
+{
+    match Session::Session::list() {
+        Ok(sessions) => sessions,
+        Err(error) => {
+            println!("Failed to list sessions, got {:?}", error);
+            process::exit(1);
+        }
+    }
+}
+
+/* ps() prints the recorded sessions. */
+fn ps(json: bool)
+{
+    let sessions = list_sessions();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&sessions).unwrap());
+        return;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    println!(
+        "{:<16} {:>8} {:>8} {:<12} {:>10} {:<8} {:<32} MOUNTPOINT",
+        "ID", "PID", "OWNER", "UNIONFS", "UPTIME", "STATE", "DISTRO"
+    );
+    for session in sessions {
+        let uptime = now.saturating_sub(session.started);
+        let state = if !session.is_alive() {
+            "dead"
+        } else if session.is_orphaned() {
+            "orphaned"
+        } else {
+            "running"
+        };
+
+        println!(
+            "{:<16} {:>8} {:>8} {:<12} {:>10} {:<8} {:<32} {}",
+            session.id,
+            session.pid,
+            session.owner,
+            format!("{:?}", session.unionfs).to_lowercase(),
+            format!("{}:{:02}:{:02}", uptime / 3600, uptime / 60 % 60, uptime % 60),
+            state,
+            session.distro.display(),
+            session.mountpoint.display()
+        );
+    }
+}
+
+/* export() exports a command or desktop entry following ARGS. */
+fn export(args: ExportArguments)
+{
+    let left = fs::canonicalize(&args.left).unwrap();
+    let mut options = args.run_option.clone();
+    if args.warm {
+        options.insert(0, "--warm".to_string());
+    }
+
+    let res = if args.desktop {
+        Export::export_desktop(&left, &args.command, &options)
+    } else {
+        let name = args.name.as_deref().unwrap_or_else(|| {
+            path::Path::new(&args.command)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(&args.command)
+        });
+        Export::export_command(&left, &args.command, name, &options)
+    };
+    match res {
+        Ok(path) => println!("Exported {} to {}", args.command, path.display()),
+        Err(error) => {
+            println!("Failed to export {}, got {:?}", args.command, error);
+            process::exit(1);
+        }
+    }
+}
+
+/* pkg() carries out ACTION with the package manager of the root directory
+of ARGS, within a session as root writing to the root directory itself. */
+fn pkg(action: Pkg::Action, args: PkgArguments)
+{
+    let left = fs::canonicalize(&args.left).unwrap();
+    let argv = args
+        .manager
+        .map_or_else(|| Pkg::detect(&left), Ok)
+        .and_then(|manager| Pkg::command(&left, manager, action, &args.packages, args.yes));
+    let argv = match argv {
+        Ok(argv) => argv,
+        Err(error) if args.json => {
+            println!("{}", serde_json::to_string_pretty(&error).unwrap());
+            process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to manage the packages of {:?}, got {:?}", left, error);
+            process::exit(1);
+        }
+    };
+
+    let run_args = ["unidis", "--as-root", "--fake-ownership"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain(iter::once(left.to_string_lossy().into_owned()))
+        .chain(iter::once("--".to_string()))
+        .chain(argv);
+    process::exit(run(Arguments::from_iter(run_args)) as i32);
+}
+
+/* bootstrap() bootstraps the managed root of ARGS, running the bootstrap
+tool within a session as root over the host, so that the managed root along
+with local mirrors and caches are reachable at their own paths. */
+fn bootstrap(args: BootstrapArguments)
+{
+    let tool_root = fs::canonicalize(&args.tool_root).unwrap();
+    let options = Bootstrap::Options {
+        release: args.release,
+        mirrors: args
+            .mirror
+            .iter()
+            .map(|mirror| match path::Path::new(mirror).exists() {
+                true => fs::canonicalize(mirror).unwrap().to_string_lossy().into_owned(),
+                false => mirror.clone(),
+            })
+            .collect(),
+        cache: args.cache.map(|cache| {
+            fs::create_dir_all(&cache).unwrap();
+            fs::canonicalize(cache).unwrap()
+        }),
+        packages: args.packages,
+        verify: !args.no_verify,
+    };
+
+    let argv = match Distro::managed(&args.name) {
+        None => Err(Bootstrap::Error::InvalidName { name: args.name.clone() }),
+        Some(dir) if dir.exists() => Err(Bootstrap::Error::Exists { dir }),
+        Some(dir) => Bootstrap::command(&tool_root, args.method, &dir, &options)
+            .map(|argv| (dir, argv)),
+    };
+    let (dir, argv) = match argv {
+        Ok(argv) => argv,
+        Err(error) if args.json => {
+            println!("{}", serde_json::to_string_pretty(&error).unwrap());
+            process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to bootstrap {}, got {:?}", args.name, error);
+            process::exit(1);
+        }
+    };
+    if let Err(error) = Bootstrap::prepare(&tool_root, args.method, &dir, &options) {
+        println!("Failed to create {:?}, got {:?}", dir, error);
+        // The directory is only ours if it did not exist beforehand
+        if error.kind() != io::ErrorKind::AlreadyExists {
+            let _ = fs::remove_dir_all(&dir);
+        }
+        process::exit(1);
+    }
+
+    let tool = argv[0].clone();
+    let run_args = ["unidis", "--as-root", "--fake-ownership", "--right", "/"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain(iter::once(tool_root.to_string_lossy().into_owned()))
+        .chain(iter::once("--".to_string()))
+        .chain(argv);
+    let status = run(Arguments::from_iter(run_args));
+
+    // A failed bootstrap is cleared away for it to be run again
+    let root = Bootstrap::root(&dir);
+    if status != 0 || !Bootstrap::installed(&dir) {
+        match status {
+            0 => println!("Failed to bootstrap {}, nothing was installed to {:?}", args.name, root),
+            status => {
+                println!("Failed to bootstrap {}, {} exited with {}", args.name, tool, status)
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+        process::exit(match status {
+            0 => 1,
+            status => status as i32,
+        });
+    }
+    println!("Bootstrapped {} to {}", args.name, root.display());
+}
+
+/* vm() runs the virtual machine of the managed root of ARGS, or prints the
+command lines it would run. */
+fn vm(args: VmArguments)
+{
+    let options = Vm::Options {
+        memory: args.memory >> 20,
+        cpus: args.cpus,
+        display: args.display,
+        forwards: args.forward,
+        iso: args.iso.map(|iso| fs::canonicalize(iso).unwrap()),
+        ovmf: args.ovmf.map(|ovmf| fs::canonicalize(ovmf).unwrap()),
+        virtiofsd: args.virtiofsd.map(|virtiofsd| fs::canonicalize(virtiofsd).unwrap()),
+        kvm: !args.no_kvm,
+    };
+    let vm = match Vm::Vm::new(&args.name, options) {
+        Ok(vm) => vm,
+        Err(error) if args.json => {
+            println!("{}", serde_json::to_string_pretty(&error).unwrap());
+            process::exit(1);
+        }
+        Err(error) => {
+            println!("Failed to run the virtual machine of {}, got {:?}", args.name, error);
+            process::exit(1);
+        }
+    };
+
+    if args.dry_run {
+        for command in vm.dry_run() {
+            println!("{}", command);
+        }
+        return;
+    }
+
+    match vm.run() {
+        Ok(status) if status.success() => (),
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(error) => {
+            println!("Failed to run the virtual machine of {}, got {:?}", args.name, error);
+            process::exit(1);
+        }
+    }
+}
+
+/* list_exports() prints the exported commands and desktop entries. */
+fn list_exports(json: bool)
+{
+    let exports = Export::list();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&exports).unwrap());
+        return;
+    }
+
+    println!("{:<16} {:<8} {:<32} {:<24} PATH", "NAME", "KIND", "DISTRO", "COMMAND");
+    for export in exports {
+        println!(
+            "{:<16} {:<8} {:<32} {:<24} {}",
+            export.name,
+            format!("{:?}", export.kind).to_lowercase(),
+            export.distro.display(),
+            export.command,
+            export.path.display()
+        );
+    }
+}
+
+/* gc() tears down the sessions whose owner died. */
+fn gc()
+{
+    for session in list_sessions() {
+        if !session.is_orphaned() {
+            continue;
+        }
+
+        match session.stop() {
+            Ok(_) => println!("Collected session {}", session.id),
+            Err(_) => println!("Failed to collect session {}", session.id),
+        }
+    }
+}
+
+/* Wrapper routine to library. */
+pub fn main()
+{
+    // `unidis [OPTIONS] <left> [argv]...` is kept as a shorthand for
+    // `unidis run [OPTIONS] <left> [argv]...`
+    let command = Command::from_iter_safe(env::args_os()).unwrap_or_else(|error| match error.kind {
+        ErrorKind::UnknownArgument | ErrorKind::UnrecognizedSubcommand => {
+            let mut args = env::args_os().collect::<Vec<_>>();
+            args.insert(1, "run".into());
+            Command::from_iter(args)
+        }
+        _ => error.exit(),
+    });
+
+    match command {
+        Command::Run(args) => process::exit(run(*args) as i32),
+        Command::Conflicts(args) => conflicts(args),
+        Command::Enter { session } => attach(&session, Vec::new()),
+        Command::Exec(args) => attach(&args.session, args.argv),
+        Command::Ps { json } => ps(json),
+        Command::Stop { session } => {
+            if load_session(&session).stop().is_err() {
+                process::exit(1);
+            }
+        }
+        Command::Gc => gc(),
+        Command::Daemon => {
+            if let Err(error) = Daemon::serve() {
+                println!("Failed to run the daemon, got {:?}", error);
+                process::exit(1);
+            }
+        }
+        Command::Oci(OciCommand::Run {
+            unionfs,
+            name,
+            bundle,
+        }) => {
+            // The child of the session only returns if it failed to run the
+            // process, exiting right away keeps it from carrying on as the
+            // caller, see `run`
+            process::exit(Oci::run(&bundle, name.as_deref(), unionfs) as i32)
+        }
+        Command::Export(args) => export(args),
+        Command::Unexport { name } => match Export::unexport(&name) {
+            Ok(removed) if removed.is_empty() => {
+                println!("No export named {}", name);
+                process::exit(1);
+            }
+            Ok(removed) => {
+                for path in removed {
+                    println!("Removed {}", path.display());
+                }
+            }
+            Err(error) => {
+                println!("Failed to unexport {}, got {:?}", name, error);
+                process::exit(1);
+            }
+        },
+        Command::ListExports { json } => list_exports(json),
+        Command::Pkg(PkgCommand::Install(args)) => pkg(Pkg::Action::Install, args),
+        Command::Pkg(PkgCommand::Remove(args)) => pkg(Pkg::Action::Remove, args),
+        Command::Pkg(PkgCommand::Search(args)) => pkg(Pkg::Action::Search, args),
+        Command::Distro(DistroCommand::Bootstrap(args)) => bootstrap(args),
+        Command::Vm(VmCommand::Run(args)) => vm(args),
+    }
 }
@@ -0,0 +1,116 @@
+use crate::Libc::{self, *};
+
+use libc::{EINVAL, MS_BIND, MS_NODEV, MS_NOEXEC, MS_NOSUID, MS_RDONLY};
+use serde::Deserialize;
+use std::{ffi::CString, fs, io, os::unix::ffi::OsStrExt, path::Path, ptr};
+
+/* MASKED_PATHS are the paths masked by the `default` profile, those of the
+runtime-spec of OCI, exposing the kernel or the hardware of the host. */
+const MASKED_PATHS: [&str; 11] = [
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/sys/devices/virtual/powercap",
+    "/sys/firmware",
+];
+
+/* READONLY_PATHS are the paths made read-only by the `default` profile,
+those of the runtime-spec of OCI, tuning the kernel of the host. */
+const READONLY_PATHS: [&str; 5] = [
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/* Profile lists the paths hidden from the session and those it may only
+read, as seen from within the session, in the format of the `linux` section
+of the runtime-spec of OCI. */
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile
+{
+    #[serde(default)]
+    pub masked_paths: Vec<String>,
+    #[serde(default)]
+    pub readonly_paths: Vec<String>,
+}
+
+/* default() builds the `default` profile. */
+fn default() -> Profile
+{
+    Profile {
+        masked_paths: MASKED_PATHS.iter().map(|path| path.to_string()).collect(),
+        readonly_paths: READONLY_PATHS.iter().map(|path| path.to_string()).collect(),
+    }
+}
+
+/* profile() loads the profile NAME, which is either `default`, `none` or
+the path to a JSON profile, None meaning nothing is masked. */
+pub fn profile(name: &str) -> Result<Option<Profile>, io::Error>
+{
+    match name {
+        "none" => Ok(None),
+        "default" => Ok(Some(default())),
+        path => {
+            let profile = fs::read(Path::new(path))?;
+            serde_json::from_slice(&profile)
+                .map(Some)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        }
+    }
+}
+
+/* mask() hides PATH, a directory is covered with an empty read-only tmpfs
+and anything else with /dev/null. */
+fn mask(path: &str) -> SyscallResult
+{
+    match fs::metadata(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => new_syscall_result(0, None),
+        Err(error) => Err(error.raw_os_error().unwrap_or(EINVAL)),
+        Ok(metadata) if metadata.is_dir() => Libc::mount(
+            "tmpfs",
+            path,
+            "tmpfs",
+            MS_RDONLY | MS_NOSUID | MS_NODEV | MS_NOEXEC,
+            ptr::null(),
+        ),
+        Ok(_) => Libc::mount("/dev/null", path, "", MS_BIND, ptr::null()),
+    }
+}
+
+/* readonly() binds PATH over itself read-only, along with its submounts. */
+fn readonly(path: &str) -> SyscallResult
+{
+    if !Path::new(path).exists() {
+        return new_syscall_result(0, None);
+    }
+
+    let path = CString::new(Path::new(path).as_os_str().as_bytes()).unwrap();
+    match rorbind::rormount(path.as_ptr(), path.as_ptr()) {
+        0 => new_syscall_result(0, None),
+        errno => Err(errno),
+    }
+}
+
+/* apply() masks and makes read-only the paths of PROFILE, which are absolute
+within the new root.  Missing paths are skipped, as they depend on the
+kernel and hardware of the host. */
+pub fn apply(profile: &Profile) -> SyscallResult
+{
+    for path in &profile.masked_paths {
+        mask(path)?;
+    }
+    for path in &profile.readonly_paths {
+        readonly(path)?;
+    }
+
+    new_syscall_result(0, None)
+}
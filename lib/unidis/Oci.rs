@@ -0,0 +1,639 @@
+use crate::{
+    launch, unidis_attrs, Capabilities,
+    Libc::{self, *},
+    Masks, Share,
+    UnionFS::SupportedUnionFS,
+    NET_LOOPBACK, PRIVATE_IPC, PRIVATE_TIME, PRIVATE_UTS, TTY,
+};
+
+use libc::{
+    __rlimit_resource_t, getegid, geteuid, rlimit, setrlimit, EINVAL, EPERM, MS_BIND, MS_DIRSYNC,
+    MS_MANDLOCK, MS_NOATIME, MS_NODEV, MS_NODIRATIME, MS_NOEXEC, MS_NOSUID, MS_RDONLY, MS_REC,
+    MS_RELATIME, MS_REMOUNT, MS_STRICTATIME, MS_SYNCHRONOUS, RLIMIT_AS, RLIMIT_CORE, RLIMIT_CPU,
+    RLIMIT_DATA, RLIMIT_FSIZE, RLIMIT_LOCKS, RLIMIT_MEMLOCK, RLIMIT_MSGQUEUE, RLIMIT_NICE,
+    RLIMIT_NOFILE, RLIMIT_NPROC, RLIMIT_RSS, RLIMIT_RTPRIO, RLIMIT_RTTIME, RLIMIT_SIGPENDING,
+    RLIMIT_STACK,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    ffi::CString,
+    fs,
+    io, iter, marker,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+/* RLIMITS are the resource limits a configuration can refer to by name. */
+const RLIMITS: [(&str, __rlimit_resource_t); 16] = [
+    ("RLIMIT_AS", RLIMIT_AS),
+    ("RLIMIT_CORE", RLIMIT_CORE),
+    ("RLIMIT_CPU", RLIMIT_CPU),
+    ("RLIMIT_DATA", RLIMIT_DATA),
+    ("RLIMIT_FSIZE", RLIMIT_FSIZE),
+    ("RLIMIT_LOCKS", RLIMIT_LOCKS),
+    ("RLIMIT_MEMLOCK", RLIMIT_MEMLOCK),
+    ("RLIMIT_MSGQUEUE", RLIMIT_MSGQUEUE),
+    ("RLIMIT_NICE", RLIMIT_NICE),
+    ("RLIMIT_NOFILE", RLIMIT_NOFILE),
+    ("RLIMIT_NPROC", RLIMIT_NPROC),
+    ("RLIMIT_RSS", RLIMIT_RSS),
+    ("RLIMIT_RTPRIO", RLIMIT_RTPRIO),
+    ("RLIMIT_RTTIME", RLIMIT_RTTIME),
+    ("RLIMIT_SIGPENDING", RLIMIT_SIGPENDING),
+    ("RLIMIT_STACK", RLIMIT_STACK),
+];
+
+/* Config is the subset of the `config.json` of a bundle, as described by
+the runtime-spec of OCI, that maps onto a session. */
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config
+{
+    pub oci_version: String,
+    pub root: Root,
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    pub process: Process,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub linux: Linux,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Root
+{
+    // PATH is relative to the bundle unless absolute
+    pub path: PathBuf,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Mount
+{
+    pub destination: PathBuf,
+    #[serde(rename = "type", default)]
+    pub fstype: Option<String>,
+    // SOURCE is relative to the bundle unless absolute, for bind mounts
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Process
+{
+    #[serde(default)]
+    pub terminal: bool,
+    pub user: User,
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    pub cwd: PathBuf,
+    #[serde(default)]
+    pub capabilities: Option<ProcessCapabilities>,
+    #[serde(default)]
+    pub rlimits: Vec<Rlimit>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User
+{
+    pub uid: u32,
+    pub gid: u32,
+    #[serde(default)]
+    pub additional_gids: Vec<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ProcessCapabilities
+{
+    #[serde(default)]
+    pub bounding: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rlimit
+{
+    #[serde(rename = "type")]
+    pub resource: String,
+    pub hard: u64,
+    pub soft: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Linux
+{
+    #[serde(default)]
+    pub namespaces: Vec<Namespace>,
+    #[serde(default)]
+    pub uid_mappings: Vec<IDMapping>,
+    #[serde(default)]
+    pub gid_mappings: Vec<IDMapping>,
+    #[serde(default)]
+    pub time_offsets: HashMap<String, TimeOffset>,
+    // MASKS are the `maskedPaths` and `readonlyPaths` of the section
+    #[serde(flatten)]
+    pub masks: Masks::Profile,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Namespace
+{
+    #[serde(rename = "type")]
+    pub ns: String,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IDMapping
+{
+    #[serde(rename = "containerID")]
+    pub container_id: u32,
+    #[serde(rename = "hostID")]
+    pub host_id: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeOffset
+{
+    pub secs: i64,
+    #[serde(default)]
+    pub nanosecs: u32,
+}
+
+/* invalid() builds the error reported for a configuration unidis cannot
+honor. */
+fn invalid(message: String) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/* check_mapping() checks that the ID of the process is the one MAPPINGS
+map HOST_ID to, as only our own ID can be mapped without privileges. */
+fn check_mapping(kind: &str, id: u32, host_id: u32, mappings: &[IDMapping])
+    -> Result<(), io::Error>
+{
+    if mappings.is_empty() {
+        return Ok(());
+    }
+
+    let mapped = mappings.iter().find_map(|mapping| {
+        let offset = host_id.checked_sub(mapping.host_id)?;
+        if offset < mapping.size {
+            Some(mapping.container_id + offset)
+        } else {
+            None
+        }
+    });
+    match mapped {
+        Some(mapped) if mapped == id => Ok(()),
+        Some(mapped) => Err(invalid(format!(
+            "{} {} of the process is not mapped, only {} is",
+            kind, id, mapped
+        ))),
+        None => Err(invalid(format!(
+            "{} {} of the host is not mapped",
+            kind, host_id
+        ))),
+    }
+}
+
+/* load() reads the configuration of BUNDLE, paths on the host are resolved
+against the bundle. */
+pub fn load(bundle: &Path) -> Result<Config, io::Error>
+{
+    let config = fs::read(bundle.join("config.json"))?;
+    let mut config: Config =
+        serde_json::from_slice(&config).map_err(|error| invalid(error.to_string()))?;
+
+    config.root.path = fs::canonicalize(bundle.join(&config.root.path))?;
+    for mount in &mut config.mounts {
+        if let Some(source) = &mount.source {
+            if source.is_relative() && mount.is_bind() {
+                mount.source = Some(bundle.join(source));
+            }
+        }
+    }
+
+    if config.process.args.is_empty() {
+        return Err(invalid("process.args is empty".to_string()));
+    }
+    if let Some(namespace) = config.linux.namespaces.iter().find(|ns| ns.path.is_some()) {
+        return Err(invalid(format!(
+            "joining the {} namespace at {:?} is not supported",
+            namespace.ns,
+            namespace.path.as_ref().unwrap()
+        )));
+    }
+    if !config.process.user.additional_gids.is_empty() {
+        println!("Ignoring process.user.additionalGids, setgroups is denied");
+    }
+    check_mapping(
+        "UID",
+        config.process.user.uid,
+        unsafe { geteuid() },
+        &config.linux.uid_mappings,
+    )?;
+    check_mapping(
+        "GID",
+        config.process.user.gid,
+        unsafe { getegid() },
+        &config.linux.gid_mappings,
+    )?;
+
+    Ok(config)
+}
+
+impl Mount
+{
+    /* is_bind() checks whether the mount binds a path of the host. */
+    fn is_bind(&self) -> bool
+    {
+        self.fstype.as_deref() == Some("bind")
+            || self
+                .options
+                .iter()
+                .any(|option| option == "bind" || option == "rbind")
+    }
+}
+
+/* Options are the options of a mount, split between the flags of `mount`,
+its data and the recursive read-only option of the `rro` proposal. */
+#[derive(Default)]
+struct Options
+{
+    flags: u64,
+    data: Vec<String>,
+    rro: bool,
+}
+
+/* parse_options() splits OPTIONS, see mount(8). */
+fn parse_options(options: &[String]) -> Options
+{
+    let mut parsed = Options::default();
+    for option in options {
+        let (set, clear) = match option.as_str() {
+            "ro" => (MS_RDONLY, 0),
+            "rw" => (0, MS_RDONLY),
+            "nosuid" => (MS_NOSUID, 0),
+            "suid" => (0, MS_NOSUID),
+            "nodev" => (MS_NODEV, 0),
+            "dev" => (0, MS_NODEV),
+            "noexec" => (MS_NOEXEC, 0),
+            "exec" => (0, MS_NOEXEC),
+            "sync" => (MS_SYNCHRONOUS, 0),
+            "async" => (0, MS_SYNCHRONOUS),
+            "dirsync" => (MS_DIRSYNC, 0),
+            "mand" => (MS_MANDLOCK, 0),
+            "nomand" => (0, MS_MANDLOCK),
+            "noatime" => (MS_NOATIME, 0),
+            "atime" => (0, MS_NOATIME),
+            "nodiratime" => (MS_NODIRATIME, 0),
+            "diratime" => (0, MS_NODIRATIME),
+            "relatime" => (MS_RELATIME, 0),
+            "norelatime" => (0, MS_RELATIME),
+            "strictatime" => (MS_STRICTATIME, 0),
+            "bind" => (MS_BIND, 0),
+            "rbind" => (MS_BIND | MS_REC, 0),
+            "rro" => {
+                parsed.rro = true;
+                (MS_RDONLY, 0)
+            }
+            // The session is a slave of the host regardless
+            "private" | "rprivate" | "shared" | "rshared" | "slave" | "rslave" | "unbindable"
+            | "runbindable" | "remount" => (0, 0),
+            // Only our own IDs are mapped, so ownership options would fail
+            // the mount altogether
+            data if data.starts_with("uid=") || data.starts_with("gid=") => (0, 0),
+            data => {
+                parsed.data.push(data.to_string());
+                (0, 0)
+            }
+        };
+        parsed.flags = (parsed.flags | set) & !clear;
+    }
+
+    parsed
+}
+
+/* bind_ro() binds the path of the host SOURCE read-only at TARGET, along
+with its submounts. */
+fn bind_ro(source: &Path, target: &Path) -> SyscallResult
+{
+    // `rormount` does not resolve relative paths against the working
+    // directory throughout
+    let target = match env::current_dir() {
+        Ok(cwd) => cwd.join(target),
+        Err(error) => return Err(error.raw_os_error().unwrap_or(EINVAL)),
+    };
+
+    let source = CString::new(source.as_os_str().as_bytes()).unwrap();
+    let target = CString::new(target.as_os_str().as_bytes()).unwrap();
+    match rorbind::rormount(source.as_ptr(), target.as_ptr()) {
+        0 => new_syscall_result(0, None),
+        errno => Err(errno),
+    }
+}
+
+/* mount() performs MOUNT relative to the current working directory, which
+is the new root, the mountpoints it creates are appended to CREATED. */
+fn mount(mount: &Mount, created: &mut Vec<PathBuf>) -> SyscallResult
+{
+    let target = match mount.destination.strip_prefix("/") {
+        Ok(target) => target,
+        Err(_) => return Err(EINVAL),
+    };
+
+    // /proc and /dev are set up by unidis, devices cannot be created
+    // without privileges anyway
+    if target == Path::new("proc") || target == Path::new("dev") {
+        println!(
+            "Skipping mount of {:?}, it is provided by unidis",
+            mount.destination
+        );
+        return new_syscall_result(0, None);
+    }
+
+    let options = parse_options(&mount.options);
+    let bind = mount.is_bind();
+    let fstype = mount.fstype.as_deref().unwrap_or("none");
+    let source = match &mount.source {
+        Some(source) => source.clone(),
+        None => PathBuf::from(fstype),
+    };
+
+    // The target is created within the union, files are bound over files
+    let dir = !bind || source.is_dir();
+    if let Err(error) = Share::placeholder(Path::new("."), target, dir, created) {
+        println!("Failed to create {:?}, got {:?}", mount.destination, error);
+        return Err(error.raw_os_error().unwrap_or(EINVAL));
+    }
+
+    if bind && options.rro {
+        return bind_ro(&source, target);
+    }
+
+    let data = CString::new(options.data.join(",")).unwrap();
+    let data = if options.data.is_empty() {
+        ptr::null()
+    } else {
+        data.as_ptr() as *const usize
+    };
+    let source = source.to_str().unwrap();
+    let target_str = target.to_str().unwrap();
+    if bind {
+        Libc::mount(
+            source,
+            target_str,
+            "",
+            options.flags & (MS_BIND | MS_REC),
+            ptr::null(),
+        )?;
+
+        // The other flags of a bind mount only apply once it is remounted
+        let flags = options.flags & !(MS_BIND | MS_REC);
+        if flags != 0 {
+            Libc::mount(
+                source,
+                target_str,
+                "",
+                MS_REMOUNT | MS_BIND | flags,
+                ptr::null(),
+            )?;
+        }
+
+        return new_syscall_result(0, None);
+    }
+
+    // Filesystems tied to namespaces we do not own, such as sysfs outside
+    // of a network namespace of our own, are bound from the host instead
+    match Libc::mount(source, target_str, fstype, options.flags, data) {
+        Err(EPERM) if mount.destination.exists() => {
+            println!(
+                "Binding {:?} from the host, {} cannot be mounted",
+                mount.destination, fstype
+            );
+            bind_ro(&mount.destination, target)
+        }
+        res => res,
+    }
+}
+
+/* setup_mounts() performs the mounts of CONFIG, from the new root before
+`pivot_root`, so that the sources remain reachable. */
+pub fn setup_mounts(config: &Config, created: &mut Vec<PathBuf>) -> SyscallResult
+{
+    for mnt in &config.mounts {
+        mount(mnt, created)?;
+    }
+
+    new_syscall_result(0, None)
+}
+
+/* remount_root() makes the root read-only if CONFIG asks for it, the mounts
+beneath it are left untouched. */
+pub fn remount_root(config: &Config) -> SyscallResult
+{
+    if !config.root.readonly {
+        return new_syscall_result(0, None);
+    }
+
+    Libc::mount(
+        "none",
+        "/",
+        "",
+        MS_REMOUNT | MS_BIND | MS_RDONLY,
+        ptr::null(),
+    )
+}
+
+/* setup_process() applies the environment and resource limits of the
+process of CONFIG to the calling process. */
+pub fn setup_process(config: &Config) -> SyscallResult
+{
+    for (key, _) in env::vars_os() {
+        env::remove_var(key);
+    }
+    for variable in &config.process.env {
+        let mut variable = variable.splitn(2, '=');
+        if let (Some(key), value) = (variable.next(), variable.next()) {
+            env::set_var(key, value.unwrap_or(""));
+        }
+    }
+
+    for limit in &config.process.rlimits {
+        let resource = match RLIMITS.iter().find(|(name, _)| *name == limit.resource) {
+            Some((_, resource)) => *resource,
+            None => {
+                println!("Unknown resource limit {}", limit.resource);
+                return Err(EINVAL);
+            }
+        };
+        let limit = rlimit {
+            rlim_cur: limit.soft,
+            rlim_max: limit.hard,
+        };
+        new_syscall_result::<i32>(unsafe { setrlimit(resource, &limit) }, None)?;
+    }
+
+    new_syscall_result(0, None)
+}
+
+impl Config
+{
+    /* revuidmap() retrieves the UID mapping to the process of the
+    configuration, reverting the one to `root`. */
+    pub fn revuidmap(&self) -> String
+    {
+        format!("{} 0 1", self.process.user.uid)
+    }
+
+    /* revgidmap() retrieves the GID mapping to the process of the
+    configuration, reverting the one to `root`. */
+    pub fn revgidmap(&self) -> String
+    {
+        format!("{} 0 1", self.process.user.gid)
+    }
+
+    /* has_namespace() checks whether the configuration asks for the
+    namespace NS. */
+    fn has_namespace(&self, ns: &str) -> bool
+    {
+        self.linux
+            .namespaces
+            .iter()
+            .any(|namespace| namespace.ns == ns)
+    }
+
+    /* flags() translates the configuration into the bit flags of
+    `unidis_attrs`.  The user, mount and PID namespaces are always unshared,
+    the cgroup namespace is ignored. */
+    fn flags(&self) -> u64
+    {
+        let mut flags = 0;
+        if self.process.terminal {
+            flags |= TTY;
+        }
+        if self.has_namespace("network") {
+            flags |= NET_LOOPBACK;
+        }
+        if self.has_namespace("ipc") {
+            flags |= PRIVATE_IPC;
+        }
+        if self.has_namespace("uts") {
+            flags |= PRIVATE_UTS;
+        }
+        if self.has_namespace("time") {
+            flags |= PRIVATE_TIME;
+        }
+
+        flags
+    }
+
+    /* time_offset() retrieves the offset of the clocks, unidis offsets the
+    monotonic and boottime clocks alike. */
+    fn time_offset(&self) -> i64
+    {
+        self.linux
+            .time_offsets
+            .get("monotonic")
+            .or_else(|| self.linux.time_offsets.get("boottime"))
+            .map_or(0, |offset| offset.secs)
+    }
+
+    /* caps() retrieves the capabilities left to the process. */
+    fn caps(&self) -> u64
+    {
+        let bounding = match &self.process.capabilities {
+            None => return 0,
+            Some(capabilities) => &capabilities.bounding,
+        };
+
+        bounding
+            .iter()
+            .fold(0, |caps, name| match Capabilities::parse(name) {
+                Some(cap) => caps | cap,
+                None => {
+                    println!("Ignoring unknown capability {}", name);
+                    caps
+                }
+            })
+    }
+}
+
+/* run() runs the process of the bundle BUNDLE in a session recorded under
+ID, with its root as both sides of the union. */
+pub fn run(bundle: &Path, id: Option<&str>, unionfs: SupportedUnionFS) -> i64
+{
+    let config = match load(bundle) {
+        Err(error) => {
+            println!("Failed to load OCI bundle {:?}, got {:?}", bundle, error);
+            return error.raw_os_error().unwrap_or(EINVAL).into();
+        }
+        Ok(config) => config,
+    };
+
+    let root = CString::new(config.root.path.as_os_str().as_bytes()).unwrap();
+    let argv = config
+        .process
+        .args
+        .iter()
+        .map(|arg| CString::new(arg.as_str()).unwrap())
+        .collect::<Vec<_>>();
+    let argv = argv
+        .iter()
+        .map(|cstr| cstr.as_ptr())
+        .chain(iter::once(ptr::null()))
+        .collect::<Vec<_>>();
+    let hostname = config
+        .hostname
+        .as_ref()
+        .map(|hostname| CString::new(hostname.as_str()).unwrap());
+    let id = id.map(|id| CString::new(id).unwrap());
+
+    let unidis_attrs = unidis_attrs {
+        _phantom: marker::PhantomData,
+        left: root.as_ptr(),
+        right: root.as_ptr(),
+        argc: argv.len(),
+        argv: argv.as_ptr(),
+        flags: config.flags(),
+        unionfs,
+        session: id.as_ref().map_or(ptr::null(), |id| id.as_ptr()),
+        hostname: hostname
+            .as_ref()
+            .map_or(ptr::null(), |hostname| hostname.as_ptr()),
+        time_offset: config.time_offset(),
+        memory_max: 0,
+        cpu_weight: 0,
+        pids_max: 0,
+        seccomp: ptr::null(),
+        caps: config.caps(),
+        landlock: ptr::null(),
+        landlock_read: ptr::null(),
+        landlock_write: ptr::null(),
+        masks: ptr::null(),
+        env: ptr::null(),
+        env_keep: ptr::null(),
+        env_files: ptr::null(),
+        env_unset: ptr::null(),
+        env_set: ptr::null(),
+        nix_env: ptr::null(),
+        host_files: ptr::null(),
+        host_files_skip: ptr::null(),
+        share: ptr::null(),
+    };
+
+    launch(&unidis_attrs, Some(&config))
+}
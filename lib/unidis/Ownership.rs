@@ -0,0 +1,702 @@
+use crate::{
+    Libc::{self, Seccomp::*, *},
+    Seccomp,
+};
+
+use libc::{
+    _exit, c_char, c_int, c_long, c_void, close, fstatat, getxattr, ioctl, mode_t, poll, pollfd,
+    removexattr, setsid, setxattr, stat, statx, SYS_fchown, SYS_fchownat, SYS_fstat,
+    SYS_newfstatat, SYS_statx, AF_UNIX, AT_EMPTY_PATH, AT_FDCWD, AT_STATX_SYNC_TYPE,
+    AT_SYMLINK_NOFOLLOW, EFAULT, EINTR, EINVAL, ENAMETOOLONG, ENODATA, ENOENT, ESRCH, O_CLOEXEC,
+    O_NOFOLLOW, O_PATH, PATH_MAX, POLLERR, POLLHUP, POLLIN, SOCK_CLOEXEC, SOCK_STREAM, S_IFDIR,
+    S_IFMT, S_IFREG,
+};
+#[cfg(target_arch = "x86_64")]
+use libc::{SYS_chown, SYS_lchown, SYS_lstat, SYS_stat};
+use std::{
+    ffi::{CStr, CString},
+    fs::{File, OpenOptions},
+    io, mem,
+    os::unix::fs::FileExt,
+    slice,
+};
+
+/* XATTR is the extended attribute recording the owner of a file as intended
+by the session, in the format of rootless containers, see
+https://github.com/rootless-containers/proto. */
+const XATTR: &str = "user.rootlesscontainers";
+
+/* NOOP_ID stands for the actual owner or group of a file in XATTR, it is
+also the ID chown(2) leaves unchanged. */
+const NOOP_ID: u32 = u32::MAX;
+
+/* SYSCALLS are the syscalls deferred to the supervisor, those changing and
+reporting the owner of files.  Those unknown to the native architecture are
+skipped. */
+const SYSCALLS: [&str; 9] = [
+    "chown",
+    "lchown",
+    "fchown",
+    "fchownat",
+    "stat",
+    "lstat",
+    "fstat",
+    "newfstatat",
+    "statx",
+];
+
+/* Resource is the owner of a file as recorded in XATTR. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Resource
+{
+    uid: u32,
+    gid: u32,
+}
+
+/* varint() reads a varint of protobuf off the front of BUF. */
+fn varint(buf: &mut &[u8]) -> Option<u64>
+{
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/* push_varint() appends VALUE as a varint of protobuf to BUF. */
+fn push_varint(buf: &mut Vec<u8>, mut value: u64)
+{
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+impl Resource
+{
+    /* decode() parses the `Resource` message BUF, fields left out are 0 as
+    per proto3 and unknown ones are skipped. */
+    fn decode(mut buf: &[u8]) -> Option<Resource>
+    {
+        let mut resource = Resource { uid: 0, gid: 0 };
+        while !buf.is_empty() {
+            let key = varint(&mut buf)?;
+            let skip = match (key >> 3, key & 0x7) {
+                (1, 0) => {
+                    resource.uid = varint(&mut buf)? as u32;
+                    0
+                }
+                (2, 0) => {
+                    resource.gid = varint(&mut buf)? as u32;
+                    0
+                }
+                (_, 0) => varint(&mut buf).map(|_| 0)?,
+                (_, 1) => 8,
+                (_, 2) => varint(&mut buf)? as usize,
+                (_, 5) => 4,
+                _ => return None,
+            };
+            buf = buf.get(skip..)?;
+        }
+
+        Some(resource)
+    }
+
+    /* encode() serializes the `Resource` message. */
+    fn encode(&self) -> Vec<u8>
+    {
+        let mut buf = Vec::new();
+        if self.uid != 0 {
+            buf.push(1 << 3);
+            push_varint(&mut buf, self.uid as u64);
+        }
+        if self.gid != 0 {
+            buf.push(2 << 3);
+            push_varint(&mut buf, self.gid as u64);
+        }
+
+        buf
+    }
+}
+
+/* Fd closes the file descriptor it holds once dropped. */
+struct Fd(c_int);
+
+impl Drop for Fd
+{
+    fn drop(&mut self)
+    {
+        unsafe { close(self.0) };
+    }
+}
+
+impl Fd
+{
+    /* path() is the path reaching the file of the descriptor, extended
+    attributes cannot be accessed through O_PATH descriptors directly. */
+    fn path(&self) -> CString
+    {
+        CString::new(format!("/proc/self/fd/{}", self.0)).unwrap()
+    }
+}
+
+/* openat() opens PATH beneath DIRFD as an O_PATH descriptor. */
+fn openat(dirfd: c_int, path: &CStr, flags: c_int) -> Result<Fd, c_int>
+{
+    let fd = new_syscall_result::<i32>(
+        unsafe { libc::openat(dirfd, path.as_ptr(), O_PATH | O_CLOEXEC | flags) },
+        None,
+    )?;
+
+    Ok(Fd(fd as c_int))
+}
+
+/* Tracee is the process whose syscall is handled, reached through its
+entries in /proc, which are those of the session as the supervisor shares
+its mount and PID namespaces. */
+struct Tracee
+{
+    listener: c_int,
+    id: u64,
+    pid: u32,
+    mem: File,
+}
+
+impl Tracee
+{
+    /* open() opens the memory of the process behind NOTIF. */
+    fn open(listener: c_int, notif: &seccomp_notif) -> Result<Tracee, c_int>
+    {
+        let mem = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/proc/{}/mem", notif.pid))
+            .map_err(|error| error.raw_os_error().unwrap_or(ESRCH))?;
+
+        Ok(Tracee {
+            listener,
+            id: notif.id,
+            pid: notif.pid,
+            mem,
+        })
+    }
+
+    /* valid() makes sure the syscall is still pending, as the process may
+    have been replaced by another one with the same PID in the meantime, in
+    which case what was read from its memory is meaningless. */
+    fn valid(&self) -> Result<(), c_int>
+    {
+        let res = unsafe { ioctl(self.listener, SECCOMP_IOCTL_NOTIF_ID_VALID as _, &self.id) };
+        new_syscall_result::<i32>(res, None).map(|_| ())
+    }
+
+    /* path() reads the path at ADDR, NULL being the empty path if EMPTY_PATH
+    allows for it. */
+    fn path(&self, addr: u64, empty_path: bool) -> Result<CString, c_int>
+    {
+        if addr == 0 {
+            return match empty_path {
+                true => Ok(CString::default()),
+                false => Err(EFAULT),
+            };
+        }
+
+        // Reads stop at page boundaries, as the next page may well be
+        // unmapped
+        let mut path = Vec::new();
+        while path.len() < PATH_MAX as usize {
+            let addr = addr + path.len() as u64;
+            let mut page = vec![0u8; 4096 - (addr % 4096) as usize];
+            let len = self.mem.read_at(&mut page, addr).map_err(|_| EFAULT)?;
+            if len == 0 {
+                return Err(EFAULT);
+            }
+            if let Some(end) = page[..len].iter().position(|byte| *byte == 0) {
+                path.extend_from_slice(&page[..end]);
+                self.valid()?;
+                return Ok(CString::new(path).unwrap());
+            }
+            path.extend_from_slice(&page[..len]);
+        }
+
+        Err(ENAMETOOLONG)
+    }
+
+    /* write() writes VALUE to ADDR. */
+    fn write<T>(&self, addr: u64, value: &T) -> Result<(), c_int>
+    {
+        let buf =
+            unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) };
+        match self.mem.write_at(buf, addr) {
+            Ok(len) if len == buf.len() => Ok(()),
+            _ => Err(EFAULT),
+        }
+    }
+
+    /* resolve() opens the file the syscall refers to through DIRFD and
+    PATH, with the *at() FLAGS AT_SYMLINK_NOFOLLOW and AT_EMPTY_PATH. */
+    fn resolve(&self, dirfd: c_int, path: &CStr, flags: c_int) -> Result<Fd, c_int>
+    {
+        let base = match dirfd {
+            AT_FDCWD => format!("/proc/{}/cwd", self.pid),
+            dirfd => format!("/proc/{}/fd/{}", self.pid, dirfd),
+        };
+        let base = CString::new(base).unwrap();
+
+        if path.to_bytes().is_empty() {
+            return match flags & AT_EMPTY_PATH != 0 {
+                true => openat(AT_FDCWD, &base, 0),
+                false => Err(ENOENT),
+            };
+        }
+
+        // Absolute paths start from the root of the process, which may
+        // have changed its own
+        let (base, path) = match path.to_bytes().strip_prefix(b"/") {
+            Some(path) => {
+                let root = CString::new(format!("/proc/{}/root", self.pid)).unwrap();
+                (openat(AT_FDCWD, &root, 0)?, CString::new(path).unwrap())
+            }
+            None => (openat(AT_FDCWD, &base, 0)?, path.to_owned()),
+        };
+        let path = match path.to_bytes().is_empty() {
+            true => CString::new(".").unwrap(),
+            false => path,
+        };
+        let nofollow = match flags & AT_SYMLINK_NOFOLLOW != 0 {
+            true => O_NOFOLLOW,
+            false => 0,
+        };
+
+        openat(base.0, &path, nofollow)
+    }
+}
+
+/* fstat() retrieves the status of the file FD. */
+fn fstat(fd: &Fd) -> Result<stat, c_int>
+{
+    let mut stat: stat = unsafe { mem::zeroed() };
+    let res = unsafe {
+        fstatat(
+            fd.0,
+            b"\0".as_ptr() as *const c_char,
+            &mut stat,
+            AT_EMPTY_PATH,
+        )
+    };
+    new_syscall_result::<i32>(res, None)?;
+
+    Ok(stat)
+}
+
+/* has_xattrs() is whether a file of MODE may carry XATTR, the user
+namespace of extended attributes is restricted to regular files and
+directories. */
+fn has_xattrs(mode: mode_t) -> bool
+{
+    mode & S_IFMT == S_IFREG || mode & S_IFMT == S_IFDIR
+}
+
+/* owner() reads the owner of the file FD as intended by the session, its
+actual owner is ACTUAL. */
+fn owner(fd: &Fd, mode: mode_t, actual: Resource) -> Resource
+{
+    if !has_xattrs(mode) {
+        return actual;
+    }
+
+    let name = CString::new(XATTR).unwrap();
+    let mut buf = [0u8; 64];
+    let len = unsafe {
+        getxattr(
+            fd.path().as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr() as *mut c_void,
+            buf.len(),
+        )
+    };
+    let resource = match len {
+        len if len < 0 => None,
+        len => Resource::decode(&buf[..len as usize]),
+    };
+
+    match resource {
+        None => actual,
+        Some(resource) => Resource {
+            uid: if resource.uid == NOOP_ID {
+                actual.uid
+            } else {
+                resource.uid
+            },
+            gid: if resource.gid == NOOP_ID {
+                actual.gid
+            } else {
+                resource.gid
+            },
+        },
+    }
+}
+
+/* set_owner() records OWNER as the owner of the file FD, which is removed
+if it matches ACTUAL. */
+fn set_owner(fd: &Fd, owner: Resource, actual: Resource) -> Result<(), c_int>
+{
+    let name = CString::new(XATTR).unwrap();
+    if owner == actual {
+        let res = unsafe { removexattr(fd.path().as_ptr(), name.as_ptr()) };
+        return match new_syscall_result::<i32>(res, None) {
+            Err(ENODATA) | Ok(_) => Ok(()),
+            Err(errno) => Err(errno),
+        };
+    }
+
+    let resource = Resource {
+        uid: if owner.uid == actual.uid {
+            NOOP_ID
+        } else {
+            owner.uid
+        },
+        gid: if owner.gid == actual.gid {
+            NOOP_ID
+        } else {
+            owner.gid
+        },
+    }
+    .encode();
+    let res = unsafe {
+        setxattr(
+            fd.path().as_ptr(),
+            name.as_ptr(),
+            resource.as_ptr() as *const c_void,
+            resource.len(),
+            0,
+        )
+    };
+
+    new_syscall_result::<i32>(res, None).map(|_| ())
+}
+
+/* chown() records the owner UID and GID of the file DIRFD and PATH refer to.
+The kernel is never left to change it, as it would fail for the IDs the
+session cannot map, so failing to record it is reported instead. */
+fn chown(tracee: &Tracee, dirfd: c_int, path: u64, uid: u32, gid: u32, flags: c_int)
+    -> Result<i64, c_int>
+{
+    let path = tracee.path(path, flags & AT_EMPTY_PATH != 0)?;
+    let fd = tracee.resolve(dirfd, &path, flags)?;
+    let stat = fstat(&fd)?;
+
+    // Links and special files keep the actual owner, as the package
+    // managers mostly care about the files they install
+    if !has_xattrs(stat.st_mode) {
+        return Ok(0);
+    }
+
+    let actual = Resource {
+        uid: stat.st_uid,
+        gid: stat.st_gid,
+    };
+    let current = owner(&fd, stat.st_mode, actual);
+    let owner = Resource {
+        uid: if uid == NOOP_ID { current.uid } else { uid },
+        gid: if gid == NOOP_ID { current.gid } else { gid },
+    };
+
+    set_owner(&fd, owner, actual)?;
+
+    Ok(0)
+}
+
+/* stat_file() reports the status of the file DIRFD and PATH refer to at BUF,
+along with its owner as intended by the session. */
+fn stat_file(tracee: &Tracee, dirfd: c_int, path: u64, buf: u64, flags: c_int)
+    -> Result<i64, c_int>
+{
+    let path = tracee.path(path, flags & AT_EMPTY_PATH != 0)?;
+    let fd = tracee.resolve(dirfd, &path, flags)?;
+
+    let mut stat = fstat(&fd)?;
+    let owner = owner(
+        &fd,
+        stat.st_mode,
+        Resource {
+            uid: stat.st_uid,
+            gid: stat.st_gid,
+        },
+    );
+    stat.st_uid = owner.uid;
+    stat.st_gid = owner.gid;
+    tracee.write(buf, &stat)?;
+
+    Ok(0)
+}
+
+/* statx_file() is stat_file() for statx(2), with its MASK and FLAGS. */
+fn statx_file(
+    tracee: &Tracee,
+    dirfd: c_int,
+    path: u64,
+    flags: c_int,
+    mask: u32,
+    buf: u64,
+) -> Result<i64, c_int>
+{
+    let path = tracee.path(path, flags & AT_EMPTY_PATH != 0)?;
+    let fd = tracee.resolve(dirfd, &path, flags)?;
+
+    let mut statx: statx = unsafe { mem::zeroed() };
+    let flags = AT_EMPTY_PATH | (flags & AT_STATX_SYNC_TYPE);
+    let res = unsafe {
+        libc::statx(
+            fd.0,
+            b"\0".as_ptr() as *const c_char,
+            flags,
+            mask,
+            &mut statx,
+        )
+    };
+    new_syscall_result::<i32>(res, None)?;
+
+    let owner = owner(
+        &fd,
+        statx.stx_mode as mode_t,
+        Resource {
+            uid: statx.stx_uid,
+            gid: statx.stx_gid,
+        },
+    );
+    statx.stx_uid = owner.uid;
+    statx.stx_gid = owner.gid;
+    tracee.write(buf, &statx)?;
+
+    Ok(0)
+}
+
+/* respond() carries out the syscall of NOTIF on behalf of the process. */
+fn respond(listener: c_int, notif: &seccomp_notif) -> seccomp_notif_resp
+{
+    let args = notif.data.args;
+    let (fd, int) = (|arg: u64| arg as c_int, |arg: u64| arg as u32);
+
+    let res = Tracee::open(listener, notif).map(|tracee| match notif.data.nr as c_long {
+        #[cfg(target_arch = "x86_64")]
+        nr if nr == SYS_chown => Some(chown(
+            &tracee,
+            AT_FDCWD,
+            args[0],
+            int(args[1]),
+            int(args[2]),
+            0,
+        )),
+        #[cfg(target_arch = "x86_64")]
+        nr if nr == SYS_lchown => Some(chown(
+            &tracee,
+            AT_FDCWD,
+            args[0],
+            int(args[1]),
+            int(args[2]),
+            AT_SYMLINK_NOFOLLOW,
+        )),
+        nr if nr == SYS_fchown => Some(chown(
+            &tracee,
+            fd(args[0]),
+            0,
+            int(args[1]),
+            int(args[2]),
+            AT_EMPTY_PATH,
+        )),
+        nr if nr == SYS_fchownat => Some(chown(
+            &tracee,
+            fd(args[0]),
+            args[1],
+            int(args[2]),
+            int(args[3]),
+            fd(args[4]),
+        )),
+        #[cfg(target_arch = "x86_64")]
+        nr if nr == SYS_stat => Some(stat_file(&tracee, AT_FDCWD, args[0], args[1], 0)),
+        #[cfg(target_arch = "x86_64")]
+        nr if nr == SYS_lstat => Some(stat_file(
+            &tracee,
+            AT_FDCWD,
+            args[0],
+            args[1],
+            AT_SYMLINK_NOFOLLOW,
+        )),
+        nr if nr == SYS_fstat => Some(stat_file(&tracee, fd(args[0]), 0, args[1], AT_EMPTY_PATH)),
+        nr if nr == SYS_newfstatat => Some(stat_file(
+            &tracee,
+            fd(args[0]),
+            args[1],
+            args[2],
+            fd(args[3]),
+        )),
+        nr if nr == SYS_statx => Some(statx_file(
+            &tracee,
+            fd(args[0]),
+            args[1],
+            fd(args[2]),
+            int(args[3]),
+            args[4],
+        )),
+        _ => None,
+    });
+
+    let mut resp = seccomp_notif_resp {
+        id: notif.id,
+        val: 0,
+        error: 0,
+        flags: 0,
+    };
+    match res {
+        Err(errno) | Ok(Some(Err(errno))) => resp.error = -errno,
+        Ok(Some(Ok(val))) => resp.val = val,
+        Ok(None) => resp.flags = SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+    }
+
+    resp
+}
+
+/* supervise() answers the syscalls deferred to LISTENER until no process is
+left to defer them. */
+fn supervise(listener: c_int) -> !
+{
+    loop {
+        let mut pollfd = pollfd {
+            fd: listener,
+            events: POLLIN,
+            revents: 0,
+        };
+        if unsafe { poll(&mut pollfd, 1, -1) } < 0 {
+            if Libc::errno() == EINTR {
+                continue;
+            }
+            unsafe { _exit(1) };
+        }
+        if pollfd.revents & (POLLHUP | POLLERR) != 0 {
+            unsafe { _exit(0) };
+        }
+
+        // The process may be gone by the time it is received or answered
+        let mut notif: seccomp_notif = unsafe { mem::zeroed() };
+        if unsafe { ioctl(listener, SECCOMP_IOCTL_NOTIF_RECV as _, &mut notif) } < 0 {
+            continue;
+        }
+        let resp = respond(listener, &notif);
+        unsafe { ioctl(listener, SECCOMP_IOCTL_NOTIF_SEND as _, &resp) };
+    }
+}
+
+/* filter() builds the filter deferring SYSCALLS to the supervisor. */
+fn filter() -> Result<Vec<sock_filter>, io::Error>
+{
+    Seccomp::compile(&Seccomp::Profile {
+        default_action: Seccomp::Action::Allow,
+        default_errno_ret: None,
+        syscalls: vec![Seccomp::Rule {
+            names: SYSCALLS.iter().map(|name| name.to_string()).collect(),
+            action: Seccomp::Action::Notify,
+            errno_ret: None,
+            args: Vec::new(),
+        }],
+    })
+}
+
+/* spawn() forks the supervisor, which waits for the listener of the filter
+to be sent over the socket returned by `install`.  It is forked before the
+command is confined, so that it is not confined along with it, and leaves
+the session of the caller so that the signals of the terminal do not reach
+it. */
+pub fn spawn() -> SyscallResult
+{
+    let (socket, peer) = Libc::socketpair(AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC)?;
+
+    match Libc::fork()? {
+        0 => {
+            unsafe {
+                close(socket);
+                setsid();
+            }
+            let mut buf = [0u8; 1];
+            let listener = match Libc::recv_fds(peer, &mut buf, 1) {
+                Ok((_, fds)) if !fds.is_empty() => fds[0],
+                _ => unsafe { _exit(0) },
+            };
+            unsafe { close(peer) };
+
+            supervise(listener)
+        }
+        _ => {
+            unsafe { close(peer) };
+            new_syscall_result(socket as i64, None)
+        }
+    }
+}
+
+/* install() confines the calling process with the filter deferring SYSCALLS
+to the supervisor behind SOCKET, no_new_privs has to be set beforehand. */
+pub fn install(socket: c_int) -> SyscallResult
+{
+    let filter = filter().map_err(|error| error.raw_os_error().unwrap_or(EINVAL))?;
+    let listener = Seccomp::listen(&filter)? as c_int;
+
+    let res = Libc::send_fds(socket, b"o", &[listener]);
+    unsafe {
+        close(listener);
+        close(socket);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn round_trip()
+    {
+        for (uid, gid) in [(0, 0), (1000, 0), (0, 100), (1000, 100), (NOOP_ID, NOOP_ID)] {
+            let resource = Resource { uid, gid };
+            assert_eq!(Resource::decode(&resource.encode()), Some(resource));
+        }
+    }
+
+    #[test]
+    fn encode()
+    {
+        // Fields of proto3 that are 0 are left out
+        assert_eq!(Resource { uid: 0, gid: 0 }.encode(), Vec::<u8>::new());
+        assert_eq!(
+            Resource { uid: 1000, gid: 5 }.encode(),
+            vec![0x08, 0xe8, 0x07, 0x10, 0x05]
+        );
+    }
+
+    #[test]
+    fn decode()
+    {
+        assert_eq!(Resource::decode(&[]), Some(Resource { uid: 0, gid: 0 }));
+        // Unknown fields of every wire type are skipped
+        let buf = [
+            0x18, 0x01, // field 3, varint
+            0x21, 0, 0, 0, 0, 0, 0, 0, 0, // field 4, 64-bit
+            0x2a, 0x02, 0xaa, 0xbb, // field 5, length-delimited
+            0x35, 0, 0, 0, 0, // field 6, 32-bit
+            0x10, 0x05, // gid
+        ];
+        assert_eq!(Resource::decode(&buf), Some(Resource { uid: 0, gid: 5 }));
+        // Truncated messages and unsupported wire types are rejected
+        assert_eq!(Resource::decode(&[0x08, 0xe8]), None);
+        assert_eq!(Resource::decode(&[0x2a, 0x05, 0xaa]), None);
+        assert_eq!(Resource::decode(&[0x0b]), None);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::Distro;
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use strum_macros::{EnumString, EnumVariantNames};
+
+/* FAMILIES maps the IDs of os-release(5), along with those of ID_LIKE, to
+the package manager of their family. */
+const FAMILIES: [(&str, Manager); 14] = [
+    ("arch", Manager::Pacman),
+    ("archarm", Manager::Pacman),
+    ("fedora", Manager::Dnf),
+    ("rhel", Manager::Dnf),
+    ("centos", Manager::Dnf),
+    ("debian", Manager::Apt),
+    ("ubuntu", Manager::Apt),
+    ("alpine", Manager::Apk),
+    ("suse", Manager::Zypper),
+    ("opensuse", Manager::Zypper),
+    ("opensuse-leap", Manager::Zypper),
+    ("opensuse-tumbleweed", Manager::Zypper),
+    ("sles", Manager::Zypper),
+    ("sled", Manager::Zypper),
+];
+
+/* Manager is a package manager unidis knows to drive. */
+#[derive(Copy, Clone, Debug, PartialEq, EnumString, EnumVariantNames, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Manager
+{
+    // Arch Linux and derivatives
+    Pacman,
+    // Fedora, RHEL and derivatives
+    Dnf,
+    // Debian, Ubuntu and derivatives
+    Apt,
+    // Alpine Linux
+    Apk,
+    // openSUSE and SLES
+    Zypper,
+}
+
+/* Action is what is asked of the package manager. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action
+{
+    // Install the packages along with their dependencies
+    Install,
+    // Remove the packages
+    Remove,
+    // Search the repositories for the terms
+    Search,
+}
+
+/* Error is why the packages of a root directory cannot be managed. */
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "kebab-case")]
+pub enum Error
+{
+    // The os-release(5) of the root directory names no known family
+    UnknownDistro
+    {
+        root: PathBuf,
+        id: Option<String>,
+        id_like: Vec<String>,
+    },
+    // The package manager of the family is missing from the root directory
+    MissingManager
+    {
+        root: PathBuf,
+        manager: Manager,
+        command: String,
+    },
+}
+
+/* detect() detects the package manager of the root directory ROOT from its
+os-release(5), ID_LIKE is only looked at if the ID is unknown. */
+pub fn detect(root: &Path) -> Result<Manager, Error>
+{
+    let mut os_release = Distro::os_release(root);
+    let id = os_release.remove("ID").filter(|id| !id.is_empty());
+    let id_like: Vec<String> = os_release
+        .remove("ID_LIKE")
+        .map(|id_like| id_like.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default();
+
+    id.iter()
+        .chain(id_like.iter())
+        .find_map(|id| {
+            FAMILIES
+                .iter()
+                .find(|(family, _)| family == id)
+                .map(|(_, manager)| *manager)
+        })
+        .ok_or_else(|| Error::UnknownDistro {
+            root: root.to_path_buf(),
+            id,
+            id_like,
+        })
+}
+
+/* command() builds the command line carrying out ACTION on PACKAGES with
+MANAGER, which has to be found in the root directory ROOT.  YES answers
+the questions of the package manager, searches ask none. */
+pub fn command(
+    root: &Path,
+    manager: Manager,
+    action: Action,
+    packages: &[String],
+    yes: bool,
+) -> Result<Vec<String>, Error>
+{
+    let (command, args, yes_flag): (&str, &[&str], &str) = match (manager, action) {
+        (Manager::Pacman, Action::Install) => ("pacman", &["-S", "--needed"], "--noconfirm"),
+        (Manager::Pacman, Action::Remove) => ("pacman", &["-R"], "--noconfirm"),
+        (Manager::Pacman, Action::Search) => ("pacman", &["-Ss"], ""),
+        (Manager::Dnf, Action::Install) => ("dnf", &["install"], "-y"),
+        (Manager::Dnf, Action::Remove) => ("dnf", &["remove"], "-y"),
+        (Manager::Dnf, Action::Search) => ("dnf", &["search"], ""),
+        (Manager::Apt, Action::Install) => ("apt-get", &["install"], "-y"),
+        (Manager::Apt, Action::Remove) => ("apt-get", &["remove"], "-y"),
+        (Manager::Apt, Action::Search) => ("apt-cache", &["search"], ""),
+        (Manager::Apk, Action::Install) => ("apk", &["add"], ""),
+        (Manager::Apk, Action::Remove) => ("apk", &["del"], ""),
+        (Manager::Apk, Action::Search) => ("apk", &["search"], ""),
+        // The global options of zypper come before the command
+        (Manager::Zypper, Action::Install) => ("zypper", &["install"], "--non-interactive"),
+        (Manager::Zypper, Action::Remove) => ("zypper", &["remove"], "--non-interactive"),
+        (Manager::Zypper, Action::Search) => ("zypper", &["search"], ""),
+    };
+
+    // The PATH of the caller may well miss the directory it is in
+    let executable = match Distro::find_command(root, command) {
+        Some(executable) => executable,
+        None => {
+            return Err(Error::MissingManager {
+                root: root.to_path_buf(),
+                manager,
+                command: command.to_string(),
+            })
+        }
+    };
+    let executable = Path::new("/").join(executable.strip_prefix(root).unwrap());
+
+    let mut argv = vec![executable.to_string_lossy().into_owned()];
+    let yes_flag = Some(yes_flag).filter(|flag| yes && !flag.is_empty());
+    if let (Manager::Zypper, Some(flag)) = (manager, yes_flag) {
+        argv.push(flag.to_string());
+    }
+    argv.extend(args.iter().map(|arg| arg.to_string()));
+    if let (false, Some(flag)) = (manager == Manager::Zypper, yes_flag) {
+        argv.push(flag.to_string());
+    }
+    argv.extend(packages.iter().cloned());
+
+    Ok(argv)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    /* fixture() creates a root directory with the os-release(5) OS_RELEASE and
+    the executables COMMANDS in /usr/bin. */
+    fn fixture(os_release: &str, commands: &[&str]) -> TempDir
+    {
+        let root = TempDir::new("unidis-pkg").unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        fs::write(root.path().join("etc/os-release"), os_release).unwrap();
+        for command in commands {
+            fs::write(root.path().join("usr/bin").join(command), "").unwrap();
+        }
+
+        root
+    }
+
+    #[test]
+    fn detect_id()
+    {
+        let root = fixture("NAME=\"Arch Linux\"\nID=arch\n", &[]);
+        assert_eq!(detect(root.path()).unwrap(), Manager::Pacman);
+        let root = fixture("ID=\"opensuse-tumbleweed\"\nID_LIKE=\"opensuse suse\"\n", &[]);
+        assert_eq!(detect(root.path()).unwrap(), Manager::Zypper);
+    }
+
+    #[test]
+    fn detect_id_like()
+    {
+        let root = fixture("ID=linuxmint\nID_LIKE=\"ubuntu debian\"\n", &[]);
+        assert_eq!(detect(root.path()).unwrap(), Manager::Apt);
+        let root = fixture("ID=rocky\nID_LIKE=\"rhel centos fedora\"\n", &[]);
+        assert_eq!(detect(root.path()).unwrap(), Manager::Dnf);
+    }
+
+    #[test]
+    fn detect_unknown()
+    {
+        let root = fixture("ID=gentoo\n", &[]);
+        match detect(root.path()) {
+            Err(Error::UnknownDistro { id, id_like, .. }) => {
+                assert_eq!(id.as_deref(), Some("gentoo"));
+                assert!(id_like.is_empty());
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn commands()
+    {
+        let commands = ["pacman", "dnf", "apt-get", "apt-cache", "apk", "zypper"];
+        let root = fixture("", &commands);
+        let packages = ["vim".to_string(), "git".to_string()];
+        let cases: [(Manager, Action, bool, &[&str]); 10] = [
+            (
+                Manager::Pacman,
+                Action::Install,
+                true,
+                &["/usr/bin/pacman", "-S", "--needed", "--noconfirm"],
+            ),
+            (Manager::Pacman, Action::Remove, false, &["/usr/bin/pacman", "-R"]),
+            (Manager::Pacman, Action::Search, true, &["/usr/bin/pacman", "-Ss"]),
+            (Manager::Dnf, Action::Install, true, &["/usr/bin/dnf", "install", "-y"]),
+            (Manager::Dnf, Action::Remove, false, &["/usr/bin/dnf", "remove"]),
+            (Manager::Apt, Action::Install, true, &["/usr/bin/apt-get", "install", "-y"]),
+            (Manager::Apt, Action::Search, true, &["/usr/bin/apt-cache", "search"]),
+            (Manager::Apk, Action::Remove, true, &["/usr/bin/apk", "del"]),
+            // The global options of zypper come before the command
+            (
+                Manager::Zypper,
+                Action::Install,
+                true,
+                &["/usr/bin/zypper", "--non-interactive", "install"],
+            ),
+            (Manager::Zypper, Action::Search, true, &["/usr/bin/zypper", "search"]),
+        ];
+
+        for (manager, action, yes, expected) in cases.iter() {
+            let argv = command(root.path(), *manager, *action, &packages, *yes).unwrap();
+            let expected = expected
+                .iter()
+                .map(|arg| arg.to_string())
+                .chain(packages.iter().cloned())
+                .collect::<Vec<_>>();
+            assert_eq!(argv, expected, "{:?} {:?}", manager, action);
+        }
+    }
+
+    #[test]
+    fn missing_manager()
+    {
+        let root = fixture("", &["apt-get"]);
+        match command(root.path(), Manager::Apt, Action::Search, &[], false) {
+            Err(Error::MissingManager { command, .. }) => assert_eq!(command, "apt-cache"),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+}
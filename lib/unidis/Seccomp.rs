@@ -0,0 +1,823 @@
+use crate::Libc::{self, Seccomp::*, SyscallResult};
+
+use libc::{
+    c_int, c_long, CLONE_NEWCGROUP, CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID,
+    CLONE_NEWUSER, CLONE_NEWUTS, ENOSYS, EPERM,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+/* syscalls! builds a table mapping the names of syscalls to their numbers
+on the native architecture, which are taken from the SYS_ constants of libc
+as the numbers differ between architectures. */
+macro_rules! syscalls {
+    ($($name:ident = $sys:ident),* $(,)?) => {
+        &[$((stringify!($name), libc::$sys)),*]
+    };
+}
+
+/* SYSCALLS are the syscalls profiles can refer to by name, on the
+architectures filters are compiled for. */
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+const SYSCALLS: &[(&str, c_long)] = syscalls! {
+    read = SYS_read, write = SYS_write, close = SYS_close,
+    fstat = SYS_fstat, lseek = SYS_lseek, mmap = SYS_mmap,
+    mprotect = SYS_mprotect, munmap = SYS_munmap, brk = SYS_brk,
+    rt_sigaction = SYS_rt_sigaction, rt_sigprocmask = SYS_rt_sigprocmask,
+    rt_sigreturn = SYS_rt_sigreturn, ioctl = SYS_ioctl,
+    pread64 = SYS_pread64, pwrite64 = SYS_pwrite64, readv = SYS_readv,
+    writev = SYS_writev, sched_yield = SYS_sched_yield,
+    mremap = SYS_mremap, msync = SYS_msync, mincore = SYS_mincore,
+    madvise = SYS_madvise, shmget = SYS_shmget, shmat = SYS_shmat,
+    shmctl = SYS_shmctl, dup = SYS_dup, nanosleep = SYS_nanosleep,
+    getitimer = SYS_getitimer, setitimer = SYS_setitimer,
+    getpid = SYS_getpid, sendfile = SYS_sendfile, socket = SYS_socket,
+    connect = SYS_connect, accept = SYS_accept, sendto = SYS_sendto,
+    recvfrom = SYS_recvfrom, sendmsg = SYS_sendmsg,
+    recvmsg = SYS_recvmsg, shutdown = SYS_shutdown, bind = SYS_bind,
+    listen = SYS_listen, getsockname = SYS_getsockname,
+    getpeername = SYS_getpeername, socketpair = SYS_socketpair,
+    setsockopt = SYS_setsockopt, getsockopt = SYS_getsockopt,
+    clone = SYS_clone, execve = SYS_execve, exit = SYS_exit,
+    wait4 = SYS_wait4, kill = SYS_kill, uname = SYS_uname,
+    semget = SYS_semget, semop = SYS_semop, semctl = SYS_semctl,
+    shmdt = SYS_shmdt, msgget = SYS_msgget, msgsnd = SYS_msgsnd,
+    msgrcv = SYS_msgrcv, msgctl = SYS_msgctl, fcntl = SYS_fcntl,
+    flock = SYS_flock, fsync = SYS_fsync, fdatasync = SYS_fdatasync,
+    truncate = SYS_truncate, ftruncate = SYS_ftruncate,
+    getcwd = SYS_getcwd, chdir = SYS_chdir, fchdir = SYS_fchdir,
+    fchmod = SYS_fchmod, fchown = SYS_fchown, umask = SYS_umask,
+    gettimeofday = SYS_gettimeofday, getrlimit = SYS_getrlimit,
+    getrusage = SYS_getrusage, sysinfo = SYS_sysinfo, times = SYS_times,
+    ptrace = SYS_ptrace, getuid = SYS_getuid, syslog = SYS_syslog,
+    getgid = SYS_getgid, setuid = SYS_setuid, setgid = SYS_setgid,
+    geteuid = SYS_geteuid, getegid = SYS_getegid, setpgid = SYS_setpgid,
+    getppid = SYS_getppid, setsid = SYS_setsid, setreuid = SYS_setreuid,
+    setregid = SYS_setregid, getgroups = SYS_getgroups,
+    setgroups = SYS_setgroups, setresuid = SYS_setresuid,
+    getresuid = SYS_getresuid, setresgid = SYS_setresgid,
+    getresgid = SYS_getresgid, getpgid = SYS_getpgid,
+    setfsuid = SYS_setfsuid, setfsgid = SYS_setfsgid, getsid = SYS_getsid,
+    capget = SYS_capget, capset = SYS_capset,
+    rt_sigpending = SYS_rt_sigpending,
+    rt_sigtimedwait = SYS_rt_sigtimedwait,
+    rt_sigqueueinfo = SYS_rt_sigqueueinfo,
+    rt_sigsuspend = SYS_rt_sigsuspend, sigaltstack = SYS_sigaltstack,
+    personality = SYS_personality, statfs = SYS_statfs,
+    fstatfs = SYS_fstatfs, getpriority = SYS_getpriority,
+    setpriority = SYS_setpriority, sched_setparam = SYS_sched_setparam,
+    sched_getparam = SYS_sched_getparam,
+    sched_setscheduler = SYS_sched_setscheduler,
+    sched_getscheduler = SYS_sched_getscheduler,
+    sched_get_priority_max = SYS_sched_get_priority_max,
+    sched_get_priority_min = SYS_sched_get_priority_min,
+    sched_rr_get_interval = SYS_sched_rr_get_interval,
+    mlock = SYS_mlock, munlock = SYS_munlock, mlockall = SYS_mlockall,
+    munlockall = SYS_munlockall, vhangup = SYS_vhangup,
+    pivot_root = SYS_pivot_root, prctl = SYS_prctl,
+    adjtimex = SYS_adjtimex, setrlimit = SYS_setrlimit,
+    chroot = SYS_chroot, sync = SYS_sync, acct = SYS_acct,
+    settimeofday = SYS_settimeofday, mount = SYS_mount,
+    umount2 = SYS_umount2, swapon = SYS_swapon, swapoff = SYS_swapoff,
+    reboot = SYS_reboot, sethostname = SYS_sethostname,
+    setdomainname = SYS_setdomainname, init_module = SYS_init_module,
+    delete_module = SYS_delete_module, quotactl = SYS_quotactl,
+    gettid = SYS_gettid, readahead = SYS_readahead,
+    setxattr = SYS_setxattr, lsetxattr = SYS_lsetxattr,
+    fsetxattr = SYS_fsetxattr, getxattr = SYS_getxattr,
+    lgetxattr = SYS_lgetxattr, fgetxattr = SYS_fgetxattr,
+    listxattr = SYS_listxattr, llistxattr = SYS_llistxattr,
+    flistxattr = SYS_flistxattr, removexattr = SYS_removexattr,
+    lremovexattr = SYS_lremovexattr, fremovexattr = SYS_fremovexattr,
+    tkill = SYS_tkill, futex = SYS_futex,
+    sched_setaffinity = SYS_sched_setaffinity,
+    sched_getaffinity = SYS_sched_getaffinity, io_setup = SYS_io_setup,
+    io_destroy = SYS_io_destroy, io_getevents = SYS_io_getevents,
+    io_submit = SYS_io_submit, io_cancel = SYS_io_cancel,
+    lookup_dcookie = SYS_lookup_dcookie,
+    remap_file_pages = SYS_remap_file_pages, getdents64 = SYS_getdents64,
+    set_tid_address = SYS_set_tid_address,
+    restart_syscall = SYS_restart_syscall, semtimedop = SYS_semtimedop,
+    fadvise64 = SYS_fadvise64, timer_create = SYS_timer_create,
+    timer_settime = SYS_timer_settime, timer_gettime = SYS_timer_gettime,
+    timer_getoverrun = SYS_timer_getoverrun,
+    timer_delete = SYS_timer_delete, clock_settime = SYS_clock_settime,
+    clock_gettime = SYS_clock_gettime, clock_getres = SYS_clock_getres,
+    clock_nanosleep = SYS_clock_nanosleep, exit_group = SYS_exit_group,
+    epoll_ctl = SYS_epoll_ctl, tgkill = SYS_tgkill, mbind = SYS_mbind,
+    set_mempolicy = SYS_set_mempolicy, get_mempolicy = SYS_get_mempolicy,
+    mq_open = SYS_mq_open, mq_unlink = SYS_mq_unlink,
+    mq_timedsend = SYS_mq_timedsend,
+    mq_timedreceive = SYS_mq_timedreceive, mq_notify = SYS_mq_notify,
+    mq_getsetattr = SYS_mq_getsetattr, kexec_load = SYS_kexec_load,
+    waitid = SYS_waitid, add_key = SYS_add_key,
+    request_key = SYS_request_key, keyctl = SYS_keyctl,
+    ioprio_set = SYS_ioprio_set, ioprio_get = SYS_ioprio_get,
+    inotify_add_watch = SYS_inotify_add_watch,
+    inotify_rm_watch = SYS_inotify_rm_watch,
+    migrate_pages = SYS_migrate_pages, openat = SYS_openat,
+    mkdirat = SYS_mkdirat, mknodat = SYS_mknodat, fchownat = SYS_fchownat,
+    newfstatat = SYS_newfstatat, unlinkat = SYS_unlinkat,
+    renameat = SYS_renameat, linkat = SYS_linkat,
+    symlinkat = SYS_symlinkat, readlinkat = SYS_readlinkat,
+    fchmodat = SYS_fchmodat, faccessat = SYS_faccessat,
+    pselect6 = SYS_pselect6, ppoll = SYS_ppoll, unshare = SYS_unshare,
+    set_robust_list = SYS_set_robust_list,
+    get_robust_list = SYS_get_robust_list, splice = SYS_splice,
+    tee = SYS_tee, sync_file_range = SYS_sync_file_range,
+    vmsplice = SYS_vmsplice, move_pages = SYS_move_pages,
+    utimensat = SYS_utimensat, epoll_pwait = SYS_epoll_pwait,
+    timerfd_create = SYS_timerfd_create, fallocate = SYS_fallocate,
+    timerfd_settime = SYS_timerfd_settime,
+    timerfd_gettime = SYS_timerfd_gettime, accept4 = SYS_accept4,
+    signalfd4 = SYS_signalfd4, eventfd2 = SYS_eventfd2,
+    epoll_create1 = SYS_epoll_create1, dup3 = SYS_dup3, pipe2 = SYS_pipe2,
+    inotify_init1 = SYS_inotify_init1, preadv = SYS_preadv,
+    pwritev = SYS_pwritev, rt_tgsigqueueinfo = SYS_rt_tgsigqueueinfo,
+    perf_event_open = SYS_perf_event_open, recvmmsg = SYS_recvmmsg,
+    fanotify_init = SYS_fanotify_init, fanotify_mark = SYS_fanotify_mark,
+    prlimit64 = SYS_prlimit64, name_to_handle_at = SYS_name_to_handle_at,
+    open_by_handle_at = SYS_open_by_handle_at,
+    clock_adjtime = SYS_clock_adjtime, syncfs = SYS_syncfs,
+    sendmmsg = SYS_sendmmsg, setns = SYS_setns, getcpu = SYS_getcpu,
+    process_vm_readv = SYS_process_vm_readv,
+    process_vm_writev = SYS_process_vm_writev, kcmp = SYS_kcmp,
+    finit_module = SYS_finit_module, sched_setattr = SYS_sched_setattr,
+    sched_getattr = SYS_sched_getattr, renameat2 = SYS_renameat2,
+    seccomp = SYS_seccomp, getrandom = SYS_getrandom,
+    memfd_create = SYS_memfd_create, kexec_file_load = SYS_kexec_file_load,
+    bpf = SYS_bpf, execveat = SYS_execveat, userfaultfd = SYS_userfaultfd,
+    membarrier = SYS_membarrier, mlock2 = SYS_mlock2,
+    copy_file_range = SYS_copy_file_range, preadv2 = SYS_preadv2,
+    pwritev2 = SYS_pwritev2, pkey_mprotect = SYS_pkey_mprotect,
+    pkey_alloc = SYS_pkey_alloc, pkey_free = SYS_pkey_free,
+    statx = SYS_statx, rseq = SYS_rseq,
+    pidfd_send_signal = SYS_pidfd_send_signal,
+    io_uring_setup = SYS_io_uring_setup,
+    io_uring_enter = SYS_io_uring_enter,
+    io_uring_register = SYS_io_uring_register, open_tree = SYS_open_tree,
+    move_mount = SYS_move_mount, fsopen = SYS_fsopen,
+    fsconfig = SYS_fsconfig, fsmount = SYS_fsmount, fspick = SYS_fspick,
+    pidfd_open = SYS_pidfd_open, clone3 = SYS_clone3,
+    close_range = SYS_close_range, openat2 = SYS_openat2,
+    pidfd_getfd = SYS_pidfd_getfd, faccessat2 = SYS_faccessat2,
+    process_madvise = SYS_process_madvise,
+    epoll_pwait2 = SYS_epoll_pwait2, mount_setattr = SYS_mount_setattr,
+    landlock_create_ruleset = SYS_landlock_create_ruleset,
+    landlock_add_rule = SYS_landlock_add_rule,
+    landlock_restrict_self = SYS_landlock_restrict_self,
+};
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+)))]
+const SYSCALLS: &[(&str, c_long)] = &[];
+
+/* LEGACY_SYSCALLS are the syscalls newer architectures only provide
+through their *at() and generic counterparts. */
+#[cfg(target_arch = "x86_64")]
+const LEGACY_SYSCALLS: &[(&str, c_long)] = syscalls! {
+    open = SYS_open, stat = SYS_stat, lstat = SYS_lstat, poll = SYS_poll,
+    access = SYS_access, pipe = SYS_pipe, select = SYS_select,
+    dup2 = SYS_dup2, pause = SYS_pause, alarm = SYS_alarm,
+    fork = SYS_fork, vfork = SYS_vfork, getdents = SYS_getdents,
+    rename = SYS_rename, mkdir = SYS_mkdir, rmdir = SYS_rmdir,
+    creat = SYS_creat, link = SYS_link, unlink = SYS_unlink,
+    symlink = SYS_symlink, readlink = SYS_readlink, chmod = SYS_chmod,
+    chown = SYS_chown, lchown = SYS_lchown, uselib = SYS_uselib,
+    ustat = SYS_ustat, sysfs = SYS_sysfs, getpgrp = SYS_getpgrp,
+    mknod = SYS_mknod, modify_ldt = SYS_modify_ldt, _sysctl = SYS__sysctl,
+    arch_prctl = SYS_arch_prctl, iopl = SYS_iopl, ioperm = SYS_ioperm, nfsservctl = SYS_nfsservctl,
+    time = SYS_time, utime = SYS_utime, utimes = SYS_utimes,
+    futimesat = SYS_futimesat, epoll_create = SYS_epoll_create,
+    epoll_wait = SYS_epoll_wait, inotify_init = SYS_inotify_init,
+    eventfd = SYS_eventfd, signalfd = SYS_signalfd,
+    set_thread_area = SYS_set_thread_area,
+    get_thread_area = SYS_get_thread_area,
+};
+#[cfg(not(target_arch = "x86_64"))]
+const LEGACY_SYSCALLS: &[(&str, c_long)] = &[];
+
+/* NATIVE_ARCH is the audit architecture of the native syscall ABI, None
+where unidis does not know the syscalls of the architecture. */
+#[cfg(target_arch = "x86_64")]
+const NATIVE_ARCH: Option<u32> = Some(AUDIT_ARCH_X86_64);
+#[cfg(target_arch = "aarch64")]
+const NATIVE_ARCH: Option<u32> = Some(AUDIT_ARCH_AARCH64);
+#[cfg(target_arch = "riscv64")]
+const NATIVE_ARCH: Option<u32> = Some(AUDIT_ARCH_RISCV64);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+)))]
+const NATIVE_ARCH: Option<u32> = None;
+
+/* BPF_MAXINSNS is the maximum length of a filter, see linux/bpf_common.h. */
+const BPF_MAXINSNS: usize = 4096;
+
+/* Action is what to do with a syscall matching a rule, see the
+runtime-spec of OCI for their meaning. */
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum Action
+{
+    #[serde(rename = "SCMP_ACT_KILL", alias = "SCMP_ACT_KILL_THREAD")]
+    Kill,
+    #[serde(rename = "SCMP_ACT_KILL_PROCESS")]
+    KillProcess,
+    #[serde(rename = "SCMP_ACT_TRAP")]
+    Trap,
+    #[serde(rename = "SCMP_ACT_ERRNO")]
+    Errno,
+    #[serde(rename = "SCMP_ACT_TRACE")]
+    Trace,
+    // The syscall waits on the listener of the filter, see `listen`
+    #[serde(rename = "SCMP_ACT_NOTIFY")]
+    Notify,
+    #[serde(rename = "SCMP_ACT_ALLOW")]
+    Allow,
+    #[serde(rename = "SCMP_ACT_LOG")]
+    Log,
+}
+
+/* Op is the comparison of an argument against the value of a rule. */
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum Op
+{
+    #[serde(rename = "SCMP_CMP_NE")]
+    NotEqual,
+    #[serde(rename = "SCMP_CMP_LT")]
+    LessThan,
+    #[serde(rename = "SCMP_CMP_LE")]
+    LessEqual,
+    #[serde(rename = "SCMP_CMP_EQ")]
+    Equal,
+    #[serde(rename = "SCMP_CMP_GE")]
+    GreaterEqual,
+    #[serde(rename = "SCMP_CMP_GT")]
+    GreaterThan,
+    // The argument masked by VALUE equals VALUE_TWO
+    #[serde(rename = "SCMP_CMP_MASKED_EQ")]
+    MaskedEqual,
+}
+
+/* Arg is a condition on an argument of a syscall, compared unsigned. */
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Arg
+{
+    pub index: u32,
+    pub value: u64,
+    #[serde(default)]
+    pub value_two: u64,
+    pub op: Op,
+}
+
+/* Rule applies ACTION to the syscalls NAMES whose arguments satisfy every
+condition of ARGS. */
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule
+{
+    pub names: Vec<String>,
+    pub action: Action,
+    #[serde(default)]
+    pub errno_ret: Option<u32>,
+    #[serde(default)]
+    pub args: Vec<Arg>,
+}
+
+/* Profile is a seccomp profile in the format of the runtime-spec of OCI.
+Rules are matched in order, the first matching rule wins, so rules with
+conditions have to come before a rule for the same syscall without any.
+The architectures of a profile are ignored, only the native architecture is
+ever allowed through. */
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile
+{
+    pub default_action: Action,
+    #[serde(default)]
+    pub default_errno_ret: Option<u32>,
+    #[serde(default)]
+    pub syscalls: Vec<Rule>,
+}
+
+/* DEFAULT_DENIED are the syscalls denied by the `default` profile, those
+which reach outside of the session into the kernel or host as a whole. */
+const DEFAULT_DENIED: [&str; 32] = [
+    "acct",
+    "add_key",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "create_module",
+    "delete_module",
+    "finit_module",
+    "get_kernel_syms",
+    "init_module",
+    "ioperm",
+    "iopl",
+    "kcmp",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "nfsservctl",
+    "open_by_handle_at",
+    "perf_event_open",
+    "query_module",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "_sysctl",
+    "sysfs",
+    "uselib",
+    "userfaultfd",
+    "ustat",
+];
+
+/* STRICT_DENIED are the syscalls denied by the `strict` profile on top of
+DEFAULT_DENIED, those which allow escaping the view of the session or
+inspecting other processes. */
+const STRICT_DENIED: [&str; 21] = [
+    "chroot",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "io_uring_enter",
+    "io_uring_register",
+    "io_uring_setup",
+    "mount",
+    "mount_setattr",
+    "move_mount",
+    "name_to_handle_at",
+    "open_tree",
+    "personality",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "setns",
+    "umount2",
+    "unshare",
+];
+
+/* NAMESPACE_FLAGS are the flags of `clone` that create namespaces. */
+const NAMESPACE_FLAGS: c_int = CLONE_NEWNS
+    | CLONE_NEWUTS
+    | CLONE_NEWIPC
+    | CLONE_NEWUSER
+    | CLONE_NEWPID
+    | CLONE_NEWNET
+    | CLONE_NEWCGROUP;
+
+/* deny() is a rule denying NAMES with EPERM. */
+fn deny(names: &[&str]) -> Rule
+{
+    Rule {
+        names: names.iter().map(|name| name.to_string()).collect(),
+        action: Action::Errno,
+        errno_ret: Some(EPERM as u32),
+        args: Vec::new(),
+    }
+}
+
+/* default() is the `default` profile, every syscall is allowed bar those
+of DEFAULT_DENIED, and personality(2) is restricted to the personas in use
+on Linux. */
+fn default() -> Profile
+{
+    let personas = [0, 0x0008, 0x20000, 0x20008, 0xffffffff];
+    Profile {
+        default_action: Action::Allow,
+        default_errno_ret: None,
+        syscalls: vec![
+            deny(&DEFAULT_DENIED),
+            Rule {
+                names: vec!["personality".to_string()],
+                action: Action::Errno,
+                errno_ret: Some(EPERM as u32),
+                args: personas
+                    .iter()
+                    .map(|persona| Arg {
+                        index: 0,
+                        value: *persona,
+                        value_two: 0,
+                        op: Op::NotEqual,
+                    })
+                    .collect(),
+            },
+        ],
+    }
+}
+
+/* strict() is the `strict` profile, which further denies STRICT_DENIED
+and the creation of namespaces.  clone3 is reported as missing, as its
+flags cannot be inspected, so that the C library falls back to clone. */
+fn strict() -> Profile
+{
+    Profile {
+        default_action: Action::Allow,
+        default_errno_ret: None,
+        syscalls: vec![
+            deny(&DEFAULT_DENIED),
+            deny(&STRICT_DENIED),
+            // clone is allowed as long as none of NAMESPACE_FLAGS is set
+            Rule {
+                names: vec!["clone".to_string()],
+                action: Action::Allow,
+                errno_ret: None,
+                args: vec![Arg {
+                    index: 0,
+                    value: NAMESPACE_FLAGS as u64,
+                    value_two: 0,
+                    op: Op::MaskedEqual,
+                }],
+            },
+            deny(&["clone"]),
+            Rule {
+                names: vec!["clone3".to_string()],
+                action: Action::Errno,
+                errno_ret: Some(ENOSYS as u32),
+                args: Vec::new(),
+            },
+        ],
+    }
+}
+
+/* profile() loads the profile NAME, either one of the built-in profiles,
+`default`, `strict` and `none`, or the path to a JSON profile.  None is
+returned for `none`. */
+pub fn profile(name: &str) -> Result<Option<Profile>, io::Error>
+{
+    match name {
+        "none" => Ok(None),
+        "default" => Ok(Some(default())),
+        "strict" => Ok(Some(strict())),
+        path => {
+            let profile = fs::read(Path::new(path))?;
+            serde_json::from_slice(&profile)
+                .map(Some)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        }
+    }
+}
+
+/* syscall() looks up the number of the syscall NAME. */
+fn syscall(name: &str) -> Option<c_long>
+{
+    SYSCALLS
+        .iter()
+        .chain(LEGACY_SYSCALLS.iter())
+        .find(|(syscall, _)| *syscall == name)
+        .map(|(_, nr)| *nr)
+}
+
+/* Assembler builds a classic BPF program, jumps refer to labels which are
+resolved into relative offsets once the program is complete. */
+#[derive(Default)]
+struct Assembler
+{
+    insns: Vec<(u16, u32, Option<usize>, Option<usize>)>,
+    labels: Vec<Option<usize>>,
+}
+
+impl Assembler
+{
+    /* label() creates a label, to be bound later on. */
+    fn label(&mut self) -> usize
+    {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    /* bind() binds LABEL to the next instruction. */
+    fn bind(&mut self, label: usize)
+    {
+        self.labels[label] = Some(self.insns.len());
+    }
+
+    /* stmt() appends a statement, see BPF_STMT. */
+    fn stmt(&mut self, code: u16, k: u32)
+    {
+        self.insns.push((code, k, None, None));
+    }
+
+    /* jump() appends a conditional jump to JT or JF, see BPF_JUMP.  None
+    falls through to the next instruction. */
+    fn jump(&mut self, code: u16, k: u32, jt: Option<usize>, jf: Option<usize>)
+    {
+        self.insns.push((BPF_JMP | code | BPF_K, k, jt, jf));
+    }
+
+    /* assemble() resolves the labels into the program. */
+    fn assemble(self) -> Result<Vec<sock_filter>, io::Error>
+    {
+        let labels = self.labels;
+        let offset = |insn: usize, label: Option<usize>| -> Result<u8, io::Error> {
+            let target = match label {
+                None => return Ok(0),
+                Some(label) => labels[label].expect("unbound label"),
+            };
+            let offset = target - (insn + 1);
+            if offset > u8::MAX as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "seccomp rule is too long",
+                ));
+            }
+            Ok(offset as u8)
+        };
+
+        let mut filter = Vec::new();
+        for (i, (code, k, jt, jf)) in self.insns.iter().enumerate() {
+            filter.push(sock_filter {
+                code: *code,
+                jt: offset(i, *jt)?,
+                jf: offset(i, *jf)?,
+                k: *k,
+            });
+        }
+
+        if filter.len() > BPF_MAXINSNS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "seccomp profile is too long",
+            ));
+        }
+        Ok(filter)
+    }
+}
+
+/* ret() translates ACTION into the return value of the filter. */
+fn ret(action: Action, errno: Option<u32>) -> u32
+{
+    let errno = errno.unwrap_or(EPERM as u32) & SECCOMP_RET_DATA;
+    match action {
+        Action::Kill => SECCOMP_RET_KILL_THREAD,
+        Action::KillProcess => SECCOMP_RET_KILL_PROCESS,
+        Action::Trap => SECCOMP_RET_TRAP,
+        Action::Errno => SECCOMP_RET_ERRNO | errno,
+        Action::Trace => SECCOMP_RET_TRACE | errno,
+        Action::Notify => SECCOMP_RET_USER_NOTIF,
+        Action::Allow => SECCOMP_RET_ALLOW,
+        Action::Log => SECCOMP_RET_LOG,
+    }
+}
+
+/* compare() appends the 64-bit comparison of ARG, jumping to FAIL if it
+does not hold and falling through otherwise.  Classic BPF only operates
+on 32-bit words, so the upper half is compared first. */
+fn compare(asm: &mut Assembler, arg: &Arg, fail: usize)
+{
+    let lo = SECCOMP_DATA_ARGS + arg.index * 8;
+    let hi = lo + 4;
+    let (value_hi, value_lo) = ((arg.value >> 32) as u32, arg.value as u32);
+    let pass = asm.label();
+
+    match arg.op {
+        Op::Equal | Op::NotEqual => {
+            let (equal, differ) = match arg.op {
+                Op::Equal => (None, Some(fail)),
+                _ => (Some(fail), Some(pass)),
+            };
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, hi);
+            asm.jump(BPF_JEQ, value_hi, None, differ);
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, lo);
+            asm.jump(BPF_JEQ, value_lo, equal, differ);
+        }
+        Op::MaskedEqual => {
+            let (datum_hi, datum_lo) = ((arg.value_two >> 32) as u32, arg.value_two as u32);
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, hi);
+            asm.stmt(BPF_ALU | BPF_AND | BPF_K, value_hi);
+            asm.jump(BPF_JEQ, datum_hi, None, Some(fail));
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, lo);
+            asm.stmt(BPF_ALU | BPF_AND | BPF_K, value_lo);
+            asm.jump(BPF_JEQ, datum_lo, None, Some(fail));
+        }
+        // Ordering is decided by the upper half unless both are equal
+        Op::GreaterThan | Op::GreaterEqual => {
+            let jump = match arg.op {
+                Op::GreaterThan => BPF_JGT,
+                _ => BPF_JGE,
+            };
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, hi);
+            asm.jump(BPF_JGT, value_hi, Some(pass), None);
+            asm.jump(BPF_JEQ, value_hi, None, Some(fail));
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, lo);
+            asm.jump(jump, value_lo, None, Some(fail));
+        }
+        Op::LessThan | Op::LessEqual => {
+            let jump = match arg.op {
+                Op::LessThan => BPF_JGE,
+                _ => BPF_JGT,
+            };
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, hi);
+            asm.jump(BPF_JGT, value_hi, Some(fail), None);
+            asm.jump(BPF_JEQ, value_hi, None, Some(pass));
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, lo);
+            asm.jump(jump, value_lo, Some(fail), None);
+        }
+    }
+
+    asm.bind(pass);
+}
+
+/* compile() translates PROFILE into a seccomp filter.  Syscalls unknown to
+the native architecture are skipped, as profiles are usually written for
+several of them. */
+pub fn compile(profile: &Profile) -> Result<Vec<sock_filter>, io::Error>
+{
+    let native_arch = match NATIVE_ARCH {
+        Some(arch) => arch,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "seccomp profiles are not supported on this architecture",
+            ))
+        }
+    };
+    let mut asm = Assembler::default();
+    let kill = asm.label();
+    let rules = asm.label();
+
+    // Syscalls of foreign architectures have numbers of their own, so they
+    // could bypass the rules entirely.  The kill sits right after the checks,
+    // as jumps only reach 255 instructions ahead
+    asm.stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARCH);
+    #[cfg(target_arch = "x86_64")]
+    {
+        asm.jump(BPF_JEQ, native_arch, None, Some(kill));
+        asm.stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR);
+        asm.jump(BPF_JGE, X32_SYSCALL_BIT, Some(kill), Some(rules));
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    asm.jump(BPF_JEQ, native_arch, Some(rules), Some(kill));
+    asm.bind(kill);
+    asm.stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS);
+    asm.bind(rules);
+
+    for rule in &profile.syscalls {
+        let action = ret(rule.action, rule.errno_ret.or(profile.default_errno_ret));
+        for nr in rule.names.iter().filter_map(|name| syscall(name)) {
+            // Every block starts by reloading the number, as the argument
+            // comparisons of the previous block override it
+            let next = asm.label();
+            asm.stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR);
+            asm.jump(BPF_JEQ, nr as u32, None, Some(next));
+            for arg in &rule.args {
+                compare(&mut asm, arg, next);
+            }
+            asm.stmt(BPF_RET | BPF_K, action);
+            asm.bind(next);
+        }
+    }
+
+    asm.stmt(
+        BPF_RET | BPF_K,
+        ret(profile.default_action, profile.default_errno_ret),
+    );
+
+    asm.assemble()
+}
+
+/* install() applies FILTER to the calling thread, it is inherited across
+`fork` and `execve` and cannot be removed. */
+pub fn install(filter: &[sock_filter]) -> SyscallResult
+{
+    let prog = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr(),
+    };
+
+    Libc::seccomp(&prog)
+}
+
+/* listen() applies FILTER like install(), returning the listener its
+SCMP_ACT_NOTIFY rules defer to.  Without a listener, these syscalls fail
+with ENOSYS instead. */
+pub fn listen(filter: &[sock_filter]) -> SyscallResult
+{
+    let prog = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr(),
+    };
+
+    Libc::seccomp_listener(&prog)
+}
+
+#[cfg(all(
+    test,
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+mod tests
+{
+    use super::*;
+
+    /* run() evaluates FILTER against the syscall NR of ARCH with ARGS, as
+    the kernel would, returning what the filter returns. */
+    fn run(filter: &[sock_filter], arch: u32, nr: c_long, args: [u64; 6]) -> u32
+    {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(nr as u32).to_ne_bytes());
+        data.extend_from_slice(&arch.to_ne_bytes());
+        data.extend_from_slice(&0u64.to_ne_bytes());
+        for arg in args.iter() {
+            data.extend_from_slice(&arg.to_ne_bytes());
+        }
+
+        let (mut pc, mut a) = (0, 0u32);
+        loop {
+            let insn = filter[pc];
+            pc += 1;
+            match insn.code {
+                code if code == BPF_LD | BPF_W | BPF_ABS => {
+                    let k = insn.k as usize;
+                    a = u32::from_ne_bytes([data[k], data[k + 1], data[k + 2], data[k + 3]]);
+                }
+                code if code == BPF_ALU | BPF_AND | BPF_K => a &= insn.k,
+                code if code == BPF_RET | BPF_K => return insn.k,
+                code => {
+                    let taken = match code & !(BPF_JMP | BPF_K) {
+                        BPF_JEQ => a == insn.k,
+                        BPF_JGT => a > insn.k,
+                        BPF_JGE => a >= insn.k,
+                        _ => panic!("unexpected instruction {:?}", insn),
+                    };
+                    pc += match taken {
+                        true => insn.jt,
+                        false => insn.jf,
+                    } as usize;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_allowlist()
+    {
+        let names: Vec<String> = SYSCALLS
+            .iter()
+            .chain(LEGACY_SYSCALLS.iter())
+            .map(|(name, _)| name.to_string())
+            .collect();
+        let profile = Profile {
+            default_action: Action::Errno,
+            default_errno_ret: Some(ENOSYS as u32),
+            syscalls: vec![Rule {
+                names,
+                action: Action::Allow,
+                errno_ret: None,
+                args: Vec::new(),
+            }],
+        };
+        let filter = compile(&profile).unwrap();
+        assert!(filter.len() > u8::MAX as usize);
+
+        let arch = NATIVE_ARCH.unwrap();
+        for (name, nr) in SYSCALLS.iter().chain(LEGACY_SYSCALLS.iter()) {
+            let action = run(&filter, arch, *nr, [0; 6]);
+            assert_eq!(action, SECCOMP_RET_ALLOW, "{}", name);
+        }
+        assert_eq!(
+            run(&filter, arch, 0xffff, [0; 6]),
+            SECCOMP_RET_ERRNO | ENOSYS as u32
+        );
+        assert_eq!(
+            run(&filter, !arch, libc::SYS_read, [0; 6]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+    }
+
+    #[test]
+    fn strict_clone()
+    {
+        let filter = compile(&strict()).unwrap();
+        let arch = NATIVE_ARCH.unwrap();
+        let clone = |flags: c_int| {
+            let args = [flags as u64, 0, 0, 0, 0, 0];
+            run(&filter, arch, libc::SYS_clone, args)
+        };
+
+        assert_eq!(clone(libc::SIGCHLD), SECCOMP_RET_ALLOW);
+        assert_eq!(clone(CLONE_NEWUSER), SECCOMP_RET_ERRNO | EPERM as u32);
+        assert_eq!(
+            run(&filter, arch, libc::SYS_clone3, [0; 6]),
+            SECCOMP_RET_ERRNO | ENOSYS as u32
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use crate::{
+    Capabilities, Cgroup, Env, Identity, Landlock, Seccomp,
+    Libc::{self, *},
+    UnionFS::{SupportedUnionFS, UnionFS},
+};
+
+use libc::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    ffi::CString,
+    fs::{self, DirBuilder, File},
+    io, iter,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, MetadataExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+    process, ptr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/* STOP_TIMEOUT is the time in milliseconds a session is given to exit
+after SIGTERM, before it is sent SIGKILL. */
+const STOP_TIMEOUT: c_int = 10000;
+
+/* Namespaces are the paths to the namespaces a session lives in, in the
+order they have to be joined. */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Namespaces
+{
+    // USER is the namespace mapping the current user to root, it is held by
+    // the `unidis` process that owns the session
+    pub user: PathBuf,
+    pub mnt: PathBuf,
+    pub pid: PathBuf,
+    // TIME is absent from sessions recorded before time namespaces were
+    // supported
+    #[serde(default)]
+    pub time: Option<PathBuf>,
+    // ROOT is the unioned root directory of the session
+    pub root: PathBuf,
+    // REVUSER is the namespace created after `pivot_root` that reverses the
+    // mapping of USER
+    pub revuser: PathBuf,
+}
+
+/* Session is a running "container", recorded under the runtime directory
+so that it can be attached to by other processes. */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session
+{
+    pub id: String,
+    // PID is the init process of the session as seen from the host
+    pub pid: pid_t,
+    // OWNER is the `unidis` process waiting on PID
+    pub owner: pid_t,
+    // DISTRO is the support root directory (LEFT) of the union
+    pub distro: PathBuf,
+    // UNIONFS is the union filesystem backing the session
+    pub unionfs: SupportedUnionFS,
+    // MOUNTPOINT is where the union is mounted, within the session
+    pub mountpoint: PathBuf,
+    // WORKDIRS are the directories on the host backing the union
+    pub workdirs: Vec<PathBuf>,
+    // STARTED is the time the session was created, in seconds since the
+    // UNIX epoch
+    pub started: u64,
+    // CGROUP is the cgroup the session was placed in, if any
+    #[serde(default)]
+    pub cgroup: Option<PathBuf>,
+    // CAPS are the capabilities left to the commands of the session
+    #[serde(default)]
+    pub caps: u64,
+    // SECCOMP is the seccomp profile the commands of the session are
+    // confined to, if any
+    #[serde(default)]
+    pub seccomp: Option<Seccomp::Profile>,
+    // LANDLOCK is the Landlock ruleset the commands of the session are
+    // confined to, if any, its paths are absolute
+    #[serde(default)]
+    pub landlock: Option<Landlock::Profile>,
+    // ENVIRONMENT is the environment the commands of the session are run
+    // with, if absent the environment of the caller is kept
+    #[serde(default)]
+    pub environment: Option<Vec<(String, String)>>,
+    pub namespaces: Namespaces,
+}
+
+/* valid_id() is whether ID names a single entry of the runtime directory,
+as the directory of a session is removed recursively once it exits. */
+pub fn valid_id(id: &str) -> bool
+{
+    crate::plain_name(id)
+}
+
+/* invalid_id() is the error of operating on the session ID. */
+fn invalid_id(id: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid session name {:?}", id))
+}
+
+/* runtime_dir() retrieves the directory sessions are recorded in. */
+pub fn runtime_dir() -> PathBuf
+{
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join(format!("unidis-{}", unsafe { geteuid() })));
+
+    runtime_dir.join("unidis").join("sessions")
+}
+
+impl Session
+{
+    /* new() describes the session with init process PID owned by the
+    calling process, which unions DISTRO through UNION, lives in CGROUP and
+    leaves CAPS to its commands. */
+    pub fn new(
+        id: &str,
+        pid: pid_t,
+        distro: &Path,
+        unionfs: SupportedUnionFS,
+        union: &dyn UnionFS,
+        cgroup: Option<&Path>,
+        caps: u64,
+    ) -> Self
+    {
+        let owner = process::id() as pid_t;
+        let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+        let owner_proc_dir = PathBuf::from(format!("/proc/{}", owner));
+
+        Session {
+            id: id.to_string(),
+            pid,
+            owner,
+            distro: distro.to_path_buf(),
+            unionfs,
+            mountpoint: union.mountpoint().to_path_buf(),
+            workdirs: union
+                .workdirs()
+                .into_iter()
+                .map(Path::to_path_buf)
+                .collect(),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            cgroup: cgroup.map(Path::to_path_buf),
+            caps,
+            seccomp: None,
+            landlock: None,
+            environment: None,
+            namespaces: Namespaces {
+                user: owner_proc_dir.join("ns/user"),
+                mnt: proc_dir.join("ns/mnt"),
+                pid: proc_dir.join("ns/pid"),
+                time: Some(proc_dir.join("ns/time")),
+                root: proc_dir.join("root"),
+                revuser: proc_dir.join("ns/user"),
+            },
+        }
+    }
+
+    /* dir() retrieves the directory the session is recorded in. */
+    pub fn dir(&self) -> PathBuf
+    {
+        runtime_dir().join(&self.id)
+    }
+
+    /* load() reads the session recorded as ID. */
+    pub fn load(id: &str) -> Result<Self, io::Error>
+    {
+        if !valid_id(id) {
+            return Err(invalid_id(id));
+        }
+
+        let session = fs::read(runtime_dir().join(id).join("session.json"))?;
+        serde_json::from_slice(&session)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /* save() records the session under the runtime directory. */
+    pub fn save(&self) -> Result<(), io::Error>
+    {
+        if !valid_id(&self.id) {
+            return Err(invalid_id(&self.id));
+        }
+
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(self.dir())?;
+
+        let session = serde_json::to_vec_pretty(self).unwrap();
+        fs::write(self.dir().join("session.json"), session)
+    }
+
+    /* remove() erases the record of the session. */
+    pub fn remove(&self) -> Result<(), io::Error>
+    {
+        if !valid_id(&self.id) {
+            return Err(invalid_id(&self.id));
+        }
+
+        fs::remove_dir_all(self.dir())
+    }
+
+    /* list() reads every recorded session. */
+    pub fn list() -> Result<Vec<Self>, io::Error>
+    {
+        let entries = match fs::read_dir(runtime_dir()) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+
+        let mut sessions = Vec::new();
+        for entry in entries {
+            let id = entry?.file_name();
+            match Session::load(&id.to_string_lossy()) {
+                Ok(session) => sessions.push(session),
+                Err(error) => println!("Failed to read session {:?}, got {:?}", id, error),
+            }
+        }
+        sessions.sort_by_key(|session| session.started);
+
+        Ok(sessions)
+    }
+
+    /* is_alive() checks whether the init process of the session still
+    exists. */
+    pub fn is_alive(&self) -> bool
+    {
+        is_alive(self.pid)
+    }
+
+    /* is_orphaned() checks whether the owner of the session died without
+    cleaning up after it. */
+    pub fn is_orphaned(&self) -> bool
+    {
+        !is_alive(self.owner)
+    }
+
+    /* teardown() removes the directories backing the union along with the
+    record of the session, the session must no longer be alive. */
+    pub fn teardown(&self) -> Result<(), io::Error>
+    {
+        for workdir in &self.workdirs {
+            remove_workdir(workdir)?;
+        }
+        if let Some(cgroup) = &self.cgroup {
+            Cgroup::remove(cgroup)?;
+        }
+
+        match self.remove() {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /* stop() terminates the session, first through SIGTERM then SIGKILL, and
+    tears down the union once it exits. */
+    pub fn stop(&self) -> SyscallResult
+    {
+        if let Ok(pidfd) = Libc::pidfd_open(self.pid, 0) {
+            let pidfd = pidfd as c_int;
+
+            // The init process of a PID namespace ignores signals it has no
+            // handler for, bar SIGKILL, so SIGTERM is merely a courtesy
+            let _ = Libc::pidfd_send_signal(pidfd, SIGTERM, 0);
+            if !pidfd_wait(pidfd, STOP_TIMEOUT) {
+                let _ = Libc::pidfd_send_signal(pidfd, SIGKILL, 0);
+                pidfd_wait(pidfd, -1);
+            }
+
+            unsafe { close(pidfd) };
+        }
+
+        // Give the owner the chance to clean up after the session, as it
+        // would have when the session exits normally
+        if let Ok(pidfd) = Libc::pidfd_open(self.owner, 0) {
+            pidfd_wait(pidfd as c_int, STOP_TIMEOUT);
+            unsafe { close(pidfd as c_int) };
+        }
+
+        if let Err(error) = self.teardown() {
+            println!("Failed to tear down session {}, got {:?}", self.id, error);
+            return Err(error.raw_os_error().unwrap_or(EINVAL));
+        }
+
+        new_syscall_result(0, None)
+    }
+
+    /* enter() moves the calling process into the namespaces and root
+    directory of the session, only its children end up in the PID namespace
+    of the session. */
+    pub fn enter(&self) -> SyscallResult
+    {
+        // Pin the init process, so that the namespaces opened below are
+        // guaranteed to belong to the session and not a recycled PID
+        let pidfd = Libc::pidfd_open(self.pid, 0)? as c_int;
+
+        let open = |path: &Path| {
+            File::open(path).map_err(|error| {
+                println!("Failed to open {:?}, got {:?}", path, error);
+                error.raw_os_error().unwrap_or(EINVAL)
+            })
+        };
+        let user = open(&self.namespaces.user)?;
+        let mnt = open(&self.namespaces.mnt)?;
+        let pid = open(&self.namespaces.pid)?;
+        let root = open(&self.namespaces.root)?;
+        let revuser = open(&self.namespaces.revuser)?;
+        let time = match &self.namespaces.time {
+            Some(time) => Some(open(time)?),
+            None => None,
+        };
+        let time_ino = fs::metadata("/proc/self/ns/time").map(|metadata| metadata.ino()).ok();
+
+        Libc::pidfd_send_signal(pidfd, 0, 0)?;
+        unsafe { close(pidfd) };
+
+        let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+
+        // Join the namespaces in the order they were created, the mount
+        // and PID namespace are owned by USER, so it is required to hold
+        // CAP_SYS_ADMIN in USER before joining either of them
+        Libc::setns(user.as_raw_fd(), CLONE_NEWUSER)?;
+        Libc::setns(mnt.as_raw_fd(), CLONE_NEWNS)?;
+        Libc::setns(pid.as_raw_fd(), CLONE_NEWPID)?;
+
+        // The time namespace of the session is only distinct from ours if
+        // it was given clock offsets
+        if let Some(time) = &time {
+            if time.metadata().map(|metadata| metadata.ino()).ok() != time_ino {
+                Libc::setns(time.as_raw_fd(), CLONE_NEWTIME)?;
+            }
+        }
+
+        // Joining a mount namespace resets the root directory to that of the
+        // namespace, so restore the chroot that `init` applied
+        Libc::fchdir(root.as_raw_fd())?;
+        Libc::chroot(Path::new("."))?;
+
+        // REVUSER is only distinct from USER after `init` has reversed the
+        // mapping, joining it last drops the capabilities held in USER
+        let user_ino = user.metadata().map(|metadata| metadata.ino()).ok();
+        let revuser_ino = revuser.metadata().map(|metadata| metadata.ino()).ok();
+        if user_ino != revuser_ino {
+            Libc::setns(revuser.as_raw_fd(), CLONE_NEWUSER)?;
+        }
+
+        // The namespaces have all been joined, so the descriptors can go
+        drop((user, mnt, pid, root, revuser, time));
+
+        if env::set_current_dir(&cwd).is_err() {
+            env::set_current_dir("/").unwrap();
+        }
+
+        new_syscall_result(0, None)
+    }
+
+    /* attach() runs ARGV within the namespaces of the session, returning the
+    wait status of the command.  An empty ARGV runs the login shell of the
+    user, as found within the root directory of the session. */
+    pub fn attach(&self, argv: &[CString]) -> SyscallResult
+    {
+        // The command is confined as `init` confines the session
+        let filter = match &self.seccomp {
+            Some(profile) => match Seccomp::compile(profile) {
+                Err(error) => {
+                    println!("Failed to compile seccomp profile, got {:?}", error);
+                    return Err(error.raw_os_error().unwrap_or(EINVAL));
+                }
+                Ok(filter) => Some(filter),
+            },
+            None => None,
+        };
+
+        self.enter()?;
+
+        // The shell is named after a dash as login(1) does, see `init`
+        let (executable, argv) = match argv.first() {
+            Some(executable) => (executable.clone(), argv.to_vec()),
+            None => {
+                let shell = Identity::login_shell(unsafe { getuid() });
+                let name = shell.file_name().unwrap_or_default().as_bytes();
+                let name = CString::new([b"-", name].concat()).unwrap();
+                (CString::new(shell.as_os_str().as_bytes()).unwrap(), vec![name])
+            }
+        };
+
+        // setns(CLONE_NEWPID) only applies to children
+        match Libc::fork()? {
+            0 => {
+                let argv = argv
+                    .iter()
+                    .map(|cstr| cstr.as_ptr())
+                    .chain(iter::once(ptr::null()))
+                    .collect::<Vec<_>>();
+                if let Some(environment) = &self.environment {
+                    Env::apply(environment);
+                }
+
+                let res = handle_syscall_result(
+                    Capabilities::restrict(self.caps)
+                        .and_then(|_| match &self.landlock {
+                            Some(landlock) => Landlock::apply(landlock),
+                            None => Ok(0),
+                        })
+                        .and_then(|_| match &filter {
+                            Some(filter) => Seccomp::install(filter),
+                            None => Ok(0),
+                        })
+                        .and_then(|_| Libc::execvp(executable.as_ptr(), argv.as_ptr())),
+                );
+                unsafe { _exit(res.err().unwrap_or(EINVAL)) }
+            }
+            pid => Libc::waitpid(pid as pid_t, 0),
+        }
+    }
+}
+
+/* is_alive() checks whether the process PID exists. */
+fn is_alive(pid: pid_t) -> bool
+{
+    match Libc::pidfd_open(pid, 0) {
+        Err(_) => false,
+        Ok(pidfd) => {
+            let alive = Libc::pidfd_send_signal(pidfd as c_int, 0, 0).is_ok();
+            unsafe { close(pidfd as c_int) };
+            alive
+        }
+    }
+}
+
+/* pidfd_wait() waits up to TIMEOUT milliseconds for the process referred to
+by PIDFD to exit, returning whether it did. */
+pub(crate) fn pidfd_wait(pidfd: c_int, timeout: c_int) -> bool
+{
+    let mut fds = [pollfd {
+        fd: pidfd,
+        events: POLLIN,
+        revents: 0,
+    }];
+
+    unsafe { poll(fds.as_mut_ptr(), 1, timeout) > 0 }
+}
+
+/* remove_workdir() removes the directory PATH, without ever crossing into
+another filesystem, as the host may still be mounted beneath it. */
+fn remove_workdir(path: &Path) -> Result<(), io::Error>
+{
+    let dev = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.dev(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.dev() != dev {
+            return Err(io::Error::other(format!("{:?} is still mounted", entry.path())));
+        }
+
+        if metadata.is_dir() {
+            remove_workdir(&entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    fs::remove_dir(path)
+}
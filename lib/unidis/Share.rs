@@ -0,0 +1,340 @@
+use crate::Libc::{self, *};
+
+use libc::{close, EINVAL, MS_BIND, MS_REC, O_CLOEXEC, O_PATH};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr,
+    str::FromStr,
+};
+use strum_macros::{EnumString, EnumVariantNames};
+
+/* Preset is a service of the desktop of the host shared with the session,
+through its sockets and the variables leading to them. */
+#[derive(Copy, Clone, Debug, PartialEq, EnumString, EnumVariantNames, Serialize, Deserialize)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset
+{
+    // The Wayland compositor, at $XDG_RUNTIME_DIR/$WAYLAND_DISPLAY
+    Wayland,
+    // The X server, at /tmp/.X11-unix along with the Xauthority cookies
+    X11,
+    // PipeWire and PulseAudio, at $XDG_RUNTIME_DIR
+    Audio,
+    // The session and system buses of D-Bus
+    Dbus,
+    // The SSH agent, at $SSH_AUTH_SOCK
+    SshAgent,
+}
+
+/* Share is what a preset found on the host: the paths bound into the
+session, at the same place, and the variables set for the command. */
+#[derive(Debug, Default)]
+pub struct Share
+{
+    pub paths: Vec<PathBuf>,
+    pub vars: Vec<(String, String)>,
+}
+
+/* var() looks up the variable KEY of VARS, empty ones are as good as
+unset. */
+fn var<'a>(vars: &'a [(String, String)], key: &str) -> Option<&'a str>
+{
+    vars.iter()
+        .find(|(name, value)| name == key && !value.is_empty())
+        .map(|(_, value)| value.as_str())
+}
+
+/* runtime_dir() is $XDG_RUNTIME_DIR of VARS, falling back on the usual
+location for the user. */
+fn runtime_dir(vars: &[(String, String)]) -> PathBuf
+{
+    var(vars, "XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() })))
+}
+
+impl Share
+{
+    /* path() shares PATH if it exists on the host. */
+    fn path(&mut self, path: PathBuf) -> bool
+    {
+        let exists = fs::symlink_metadata(&path).is_ok();
+        if exists && !self.paths.contains(&path) {
+            self.paths.push(path);
+        }
+        exists
+    }
+
+    /* var() sets the variable KEY to VALUE. */
+    fn var(&mut self, key: &str, value: &str)
+    {
+        self.vars.push((key.to_string(), value.to_string()));
+    }
+
+    /* discover() looks up what PRESET shares on the host, given the
+    environment VARS of the caller. */
+    fn discover(&mut self, preset: Preset, vars: &[(String, String)])
+    {
+        let runtime_dir = runtime_dir(vars);
+        match preset {
+            Preset::Wayland => {
+                let display = var(vars, "WAYLAND_DISPLAY").unwrap_or("wayland-0");
+                if self.path(runtime_dir.join(display)) {
+                    self.var("WAYLAND_DISPLAY", display);
+                    self.var("XDG_RUNTIME_DIR", &runtime_dir.to_string_lossy());
+                }
+            }
+            Preset::X11 => {
+                if self.path(PathBuf::from("/tmp/.X11-unix")) {
+                    self.var("DISPLAY", var(vars, "DISPLAY").unwrap_or(":0"));
+                }
+                let xauthority = var(vars, "XAUTHORITY")
+                    .map(PathBuf::from)
+                    .or_else(|| var(vars, "HOME").map(|home| Path::new(home).join(".Xauthority")));
+                if let Some(xauthority) = xauthority {
+                    if self.path(xauthority.clone()) {
+                        self.var("XAUTHORITY", &xauthority.to_string_lossy());
+                    }
+                }
+            }
+            Preset::Audio => {
+                let pulse = runtime_dir.join("pulse").join("native");
+                if self.path(pulse.clone()) {
+                    self.var("PULSE_SERVER", &format!("unix:{}", pulse.to_string_lossy()));
+                }
+                if self.path(runtime_dir.join("pipewire-0")) {
+                    self.var("XDG_RUNTIME_DIR", &runtime_dir.to_string_lossy());
+                }
+            }
+            Preset::Dbus => {
+                // Abstract sockets are reached through the network namespace
+                // rather than the filesystem
+                let address = var(vars, "DBUS_SESSION_BUS_ADDRESS")
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("unix:path={}", runtime_dir.join("bus").display()));
+                let path = address
+                    .split(';')
+                    .next()
+                    .unwrap_or("")
+                    .split(',')
+                    .find_map(|option| option.strip_prefix("unix:path="));
+                if path.is_none_or(|path| self.path(PathBuf::from(path))) {
+                    self.var("DBUS_SESSION_BUS_ADDRESS", &address);
+                }
+                self.path(PathBuf::from("/run/dbus/system_bus_socket"));
+            }
+            Preset::SshAgent => {
+                if let Some(sock) = var(vars, "SSH_AUTH_SOCK") {
+                    if self.path(PathBuf::from(sock)) {
+                        self.var("SSH_AUTH_SOCK", sock);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/* discover() looks up what PRESETS share on the host, given the environment
+VARS of the caller.  Services not running are reported and left out. */
+pub fn discover(presets: &[Preset], vars: &[(String, String)]) -> Share
+{
+    let mut share = Share::default();
+    for preset in presets {
+        let (paths, vars_) = (share.paths.len(), share.vars.len());
+        share.discover(*preset, vars);
+        if share.paths.len() == paths && share.vars.len() == vars_ {
+            println!("Nothing to share for {:?}, is it running?", preset);
+        }
+    }
+
+    share
+}
+
+/* presets() parses the preset NAMES. */
+pub fn presets<S: AsRef<str>>(names: &[S]) -> Result<Vec<Preset>, io::Error>
+{
+    names
+        .iter()
+        .map(|name| {
+            Preset::from_str(name.as_ref()).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown share preset {}", name.as_ref()),
+                )
+            })
+        })
+        .collect()
+}
+
+/* Opened is a path of a share opened on the host, to be bound into the
+session once the host is out of reach. */
+pub struct Opened
+{
+    path: PathBuf,
+    fd: i32,
+    dir: bool,
+}
+
+impl Drop for Opened
+{
+    fn drop(&mut self)
+    {
+        unsafe { close(self.fd) };
+    }
+}
+
+/* open() opens the paths of SHARE on the host, those gone in the meantime
+are skipped. */
+pub fn open(share: &Share) -> Vec<Opened>
+{
+    let mut opened = Vec::new();
+    for path in &share.paths {
+        let cpath = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let fd = unsafe { libc::open(cpath.as_ptr(), O_PATH | O_CLOEXEC) };
+        if fd < 0 {
+            continue;
+        }
+        opened.push(Opened {
+            path: path.clone(),
+            fd,
+            dir: Path::new(&format!("/proc/self/fd/{}", fd)).is_dir(),
+        });
+    }
+
+    opened
+}
+
+/* bind() binds the paths OPENED at the same place in the new root, which
+is the working directory, creating them if need be, see `placeholder`.
+This comes after /tmp is remounted so as not to be hidden by it. */
+pub fn bind(opened: &[Opened], created: &mut Vec<PathBuf>) -> SyscallResult
+{
+    for opened in opened {
+        let target = opened.path.strip_prefix("/").unwrap_or(&opened.path);
+        if let Err(error) = placeholder(Path::new("."), target, opened.dir, created) {
+            return Err(error.raw_os_error().unwrap_or(EINVAL));
+        }
+
+        Libc::mount(
+            &format!("/proc/self/fd/{}", opened.fd),
+            &target.to_string_lossy(),
+            "",
+            MS_BIND | MS_REC,
+            ptr::null(),
+        )?;
+    }
+
+    new_syscall_result(0, None)
+}
+
+/* placeholder() creates the mountpoint TARGET beneath ROOT along with its
+missing parents, a directory if DIR or else an empty file.  Whatever is
+created is appended to CREATED, relative to ROOT with symbolic links
+resolved, as it persists in the read-write side of the union otherwise. */
+pub fn placeholder(
+    root: &Path,
+    target: &Path,
+    dir: bool,
+    created: &mut Vec<PathBuf>,
+) -> Result<(), io::Error>
+{
+    let root = fs::canonicalize(root)?;
+    let target = target.strip_prefix("/").unwrap_or(target);
+
+    let mut path = root.clone();
+    let mut components = target.components().peekable();
+    while let Some(component) = components.next() {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok() {
+            continue;
+        }
+
+        match dir || components.peek().is_some() {
+            true => fs::create_dir(&path)?,
+            false => fs::write(&path, "")?,
+        }
+
+        // Paths leading out of ROOT through a symbolic link are not ours
+        // to remove
+        let parent = fs::canonicalize(path.parent().unwrap())?;
+        if let Ok(parent) = parent.strip_prefix(&root) {
+            created.push(parent.join(path.file_name().unwrap()));
+        }
+    }
+
+    Ok(())
+}
+
+/* remove_placeholders() removes the mountpoints CREATED beneath ROOT by
+`placeholder`, as long as they are still empty.  Those reached through a
+symbolic link are left alone, the link was not there when they were. */
+pub fn remove_placeholders(root: &Path, created: &[PathBuf])
+{
+    for path in created.iter().rev() {
+        let linked = path
+            .ancestors()
+            .skip(1)
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| {
+                fs::symlink_metadata(root.join(ancestor))
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink())
+            });
+        if linked {
+            continue;
+        }
+
+        let path = root.join(path);
+        let _ = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir(&path),
+            Ok(metadata) if metadata.is_file() && metadata.len() == 0 => fs::remove_file(&path),
+            _ => continue,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempdir::TempDir;
+
+    #[test]
+    fn placeholders()
+    {
+        let root = TempDir::new("unidis-share").unwrap();
+        let root = root.path();
+        fs::create_dir_all(root.join("usr/share")).unwrap();
+        symlink("usr/share", root.join("share")).unwrap();
+
+        let mut created = Vec::new();
+        placeholder(root, Path::new("/run/user/1000/bus"), false, &mut created).unwrap();
+        placeholder(root, Path::new("/share/fonts"), true, &mut created).unwrap();
+        placeholder(root, Path::new("/usr/share"), true, &mut created).unwrap();
+        assert!(root.join("run/user/1000/bus").is_file());
+        assert_eq!(
+            created,
+            ["run", "run/user", "run/user/1000", "run/user/1000/bus", "usr/share/fonts"]
+                .map(PathBuf::from)
+        );
+
+        // What is no longer empty is left, the rest goes
+        fs::write(root.join("usr/share/fonts/font.ttf"), "").unwrap();
+        remove_placeholders(root, &created);
+        assert!(!root.join("run").exists());
+        assert!(root.join("usr/share/fonts").is_dir());
+
+        // Links created in the meantime are not followed
+        let outside = TempDir::new("unidis-share").unwrap();
+        fs::create_dir(outside.path().join("fonts")).unwrap();
+        fs::remove_dir_all(root.join("usr/share")).unwrap();
+        symlink(outside.path(), root.join("usr/share")).unwrap();
+        remove_placeholders(root, &created);
+        assert!(outside.path().join("fonts").is_dir());
+    }
+}
@@ -1,7 +1,10 @@
 #![allow(dead_code)]
 
-use libc::{getegid, geteuid, gid_t, uid_t};
-use std::{clone::Clone, fmt::Display, iter::DoubleEndedIterator, marker::Copy, option::Option};
+use libc::{getegid, geteuid, getpwuid, gid_t, uid_t};
+use std::{
+    clone::Clone, ffi::CStr, fmt::Display, fs, iter::DoubleEndedIterator, marker::Copy,
+    option::Option,
+};
 
 /* IDMap is a simple mapping structure from SOURCE -> TARGET. */
 #[derive(Copy, Clone)]
@@ -125,3 +128,38 @@ pub fn revgidmap() -> String {
     let egid = unsafe { getegid() };
     revmap(idmap(GIDMAP.iter().copied(), egid))
 }
+
+/* subids() looks up the first range of subordinate IDs of the current user,
+known as NAME or ID, in FILE, see subuid(5). */
+fn subids(file: &str, name: Option<&str>, id: u32) -> Option<(u32, u32)> {
+    let subids = fs::read_to_string(file).ok()?;
+    subids.lines().find_map(|line| {
+        let fields = line.split(':').collect::<Vec<_>>();
+        if fields.len() != 3 || (Some(fields[0]) != name && fields[0] != id.to_string()) {
+            return None;
+        }
+        Some((fields[1].parse().ok()?, fields[2].parse().ok()?))
+    })
+}
+
+/* username() looks up the name of the current user, None if it has none. */
+fn username() -> Option<String> {
+    let passwd = unsafe { getpwuid(geteuid()).as_ref() }?;
+    Some(unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned())
+}
+
+/* subuidmap() returns the UID mapping of the current user to `root` along
+with its subordinate UIDs from 1 onwards, in the form taken by newuidmap(1),
+None if it has none. */
+pub fn subuidmap() -> Option<String> {
+    let euid = unsafe { geteuid() };
+    let (start, count) = subids("/etc/subuid", username().as_deref(), euid)?;
+    Some(format!("0 {} 1 1 {} {}", euid, start, count))
+}
+
+/* subgidmap() returns the GID mapping like subuidmap(), for newgidmap(1). */
+pub fn subgidmap() -> Option<String> {
+    let egid = unsafe { getegid() };
+    let (start, count) = subids("/etc/subgid", username().as_deref(), unsafe { geteuid() })?;
+    Some(format!("0 {} 1 1 {} {}", egid, start, count))
+}
@@ -32,3 +32,25 @@ pub const MOUNTPOINTS: [Mount; 2] = [
         data: ptr::null(),
     },
 ];
+
+/* DEVPTS is the mountings to perform for allocating terminals from an
+instance of devpts private to the namespace.  Paths are relative to the new
+"/". */
+pub const DEVPTS: [Mount; 2] = [
+    // Mount a new instance of devpts over the one bound from the host
+    Mount {
+        source: "devpts",
+        target: "dev/pts/",
+        fstype: "devpts",
+        mountflags: MS_NOSUID | MS_NOEXEC,
+        data: b"newinstance,ptmxmode=0666,mode=0620\0".as_ptr() as *const usize,
+    },
+    // Redirect /dev/ptmx to the multiplexer of the new instance
+    Mount {
+        source: "dev/pts/ptmx",
+        target: "dev/ptmx",
+        fstype: "",
+        mountflags: MS_BIND,
+        data: ptr::null(),
+    },
+];
@@ -0,0 +1,208 @@
+use crate::Libc::{self, *};
+
+use libc::*;
+use std::{mem, ptr};
+
+/* write_all() writes the whole of BUF to FD. */
+fn write_all(fd: c_int, mut buf: &[u8]) -> bool
+{
+    while !buf.is_empty() {
+        let len = unsafe { write(fd, buf.as_ptr() as *const c_void, buf.len()) };
+        if len < 0 && Libc::errno() == EINTR {
+            continue;
+        }
+        if len <= 0 {
+            return false;
+        }
+        buf = &buf[len as usize..];
+    }
+
+    true
+}
+
+/* forward_winsize() copies the window size of the terminal FROM to TO, the
+foreground process group of TO receives SIGWINCH if it changed. */
+fn forward_winsize(from: c_int, to: c_int)
+{
+    let mut winsize: winsize = unsafe { mem::zeroed() };
+    if unsafe { ioctl(from, TIOCGWINSZ, &mut winsize) } == 0 {
+        unsafe { ioctl(to, TIOCSWINSZ, &winsize) };
+    }
+}
+
+/* attach() allocates a pseudo-terminal from the devpts instance of the
+namespace and makes it the controlling terminal and standard file
+descriptors of a new session, the master side is sent over SOCKET to the
+parent. */
+pub fn attach(socket: c_int) -> SyscallResult
+{
+    let master =
+        new_syscall_result::<i32>(unsafe { posix_openpt(O_RDWR | O_NOCTTY | O_CLOEXEC) }, None)?
+            as c_int;
+    new_syscall_result::<i32>(unsafe { grantpt(master) }, None)?;
+    new_syscall_result::<i32>(unsafe { unlockpt(master) }, None)?;
+
+    let mut name = [0 as c_char; 64];
+    let res = unsafe { ptsname_r(master, name.as_mut_ptr(), name.len()) };
+    if res != 0 {
+        return Err(res);
+    }
+
+    // Start from the modes and size of the terminal of the parent, so the
+    // command sees the same terminal it would natively
+    let mut termios: termios = unsafe { mem::zeroed() };
+    let has_termios = unsafe { tcgetattr(STDIN_FILENO, &mut termios) } == 0;
+
+    // The new session has no controlling terminal, so the first terminal
+    // opened without O_NOCTTY becomes its controlling terminal
+    new_syscall_result::<i32>(unsafe { setsid() }, None)?;
+    let slave = new_syscall_result::<i32>(unsafe { open(name.as_ptr(), O_RDWR) }, None)? as c_int;
+    new_syscall_result::<i32>(unsafe { ioctl(slave, TIOCSCTTY, 0) }, None)?;
+
+    if has_termios {
+        unsafe { tcsetattr(slave, TCSANOW, &termios) };
+    }
+    forward_winsize(STDIN_FILENO, slave);
+
+    for fd in &[STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO] {
+        new_syscall_result::<i32>(unsafe { dup2(slave, *fd) }, None)?;
+    }
+    unsafe { close(slave) };
+
+    Libc::send_fds(socket, b"t", &[master])?;
+    unsafe {
+        close(master);
+        close(socket);
+    }
+
+    new_syscall_result(0, None)
+}
+
+/* receive() retrieves the master side of the pseudo-terminal sent by
+`attach` over SOCKET, if the child got that far. */
+pub fn receive(socket: c_int) -> Option<c_int>
+{
+    let mut buf = [0u8; 1];
+    let res = Libc::recv_fds(socket, &mut buf, 1);
+    unsafe { close(socket) };
+
+    match res {
+        Ok((_, fds)) => fds.first().copied(),
+        Err(_) => None,
+    }
+}
+
+/* relay() forwards the terminal of the calling process to MASTER until the
+process PID exits, along with any change to its window size.  The terminal
+is put in raw mode in the meantime, as line editing and signals are handled
+by the pseudo-terminal on the other end. */
+pub fn relay(master: c_int, pid: pid_t)
+{
+    let pidfd = match Libc::pidfd_open(pid, 0) {
+        Ok(pidfd) => pidfd as c_int,
+        Err(errno) => {
+            println!("Failed to open pidfd of {}, got {}", pid, errno);
+            unsafe { close(master) };
+            return;
+        }
+    };
+
+    let mut saved: termios = unsafe { mem::zeroed() };
+    let is_tty = unsafe { tcgetattr(STDIN_FILENO, &mut saved) } == 0;
+    if is_tty {
+        let mut raw = saved;
+        unsafe {
+            cfmakeraw(&mut raw);
+            tcsetattr(STDIN_FILENO, TCSANOW, &raw);
+        }
+    }
+
+    let mut mask: sigset_t = unsafe { mem::zeroed() };
+    let signalfd = unsafe {
+        sigemptyset(&mut mask);
+        sigaddset(&mut mask, SIGWINCH);
+        sigprocmask(SIG_BLOCK, &mask, ptr::null_mut());
+        signalfd(-1, &mask, SFD_CLOEXEC)
+    };
+
+    let mut buf = [0u8; 4096];
+    let mut pollfds = [
+        pollfd {
+            fd: STDIN_FILENO,
+            events: POLLIN,
+            revents: 0,
+        },
+        pollfd {
+            fd: master,
+            events: POLLIN,
+            revents: 0,
+        },
+        pollfd {
+            fd: signalfd,
+            events: POLLIN,
+            revents: 0,
+        },
+        pollfd {
+            fd: pidfd,
+            events: POLLIN,
+            revents: 0,
+        },
+    ];
+    let mut exited = false;
+    loop {
+        // Once the process exited, whatever output is left is drained
+        // without blocking, as background processes may hold the terminal
+        let res = unsafe { poll(pollfds.as_mut_ptr(), 4, if exited { 0 } else { -1 }) };
+        if res < 0 {
+            continue;
+        }
+        if res == 0 {
+            break;
+        }
+
+        if pollfds[0].revents != 0 {
+            let len = unsafe { read(STDIN_FILENO, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+            if len <= 0 {
+                pollfds[0].fd = -1;
+            } else if !write_all(master, &buf[..len as usize]) {
+                break;
+            }
+        }
+
+        if pollfds[1].revents != 0 {
+            // EIO is returned once every slave file descriptor is closed
+            let len = unsafe { read(master, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+            if len <= 0 || !write_all(STDOUT_FILENO, &buf[..len as usize]) {
+                break;
+            }
+        }
+
+        if pollfds[2].revents != 0 {
+            let mut siginfo: signalfd_siginfo = unsafe { mem::zeroed() };
+            unsafe {
+                read(
+                    signalfd,
+                    &mut siginfo as *mut signalfd_siginfo as *mut c_void,
+                    mem::size_of::<signalfd_siginfo>(),
+                )
+            };
+            forward_winsize(STDIN_FILENO, master);
+        }
+
+        if pollfds[3].revents != 0 {
+            exited = true;
+            pollfds[3].fd = -1;
+        }
+    }
+
+    if is_tty {
+        unsafe { tcsetattr(STDIN_FILENO, TCSANOW, &saved) };
+    }
+
+    unsafe {
+        sigprocmask(SIG_UNBLOCK, &mask, ptr::null_mut());
+        close(signalfd);
+        close(pidfd);
+        close(master);
+    }
+}
@@ -1,16 +1,51 @@
 #![allow(non_snake_case)]
 
 mod Libc;
-use Libc::{Clone::clone_args, *};
+use Libc::{
+    Clone::{clone_args, CLONE_INTO_CGROUP},
+    *,
+};
+
+mod Cgroup;
+mod HostFiles;
+mod Identity;
+mod Landlock;
+mod Masks;
+mod Ownership;
+mod Seccomp;
 
 mod Template;
+mod Tty;
 use Template::{IDMap, Mount};
 
 pub mod UnionFS;
 use UnionFS::*;
 
+pub mod Bootstrap;
+pub mod Capabilities;
+pub mod Conflicts;
+pub mod Daemon;
+pub mod Distro;
+pub mod Env;
+pub mod Export;
+pub mod Oci;
+pub mod Pkg;
+pub mod Session;
+pub mod Share;
+pub mod Vm;
+
 use libc::*;
-use std::{convert::TryInto, env, ffi::CStr, fs::write, marker, path::Path, ptr};
+use std::{
+    convert::TryInto,
+    env,
+    ffi::{CStr, CString, OsStr},
+    fs::{write, File},
+    io::{self, Read, Write},
+    iter, marker, mem,
+    os::unix::{ffi::OsStrExt, io::FromRawFd},
+    path::{Path, PathBuf},
+    process, ptr,
+};
 
 // REMOUNT_TMP means exactly what it says, whether to remount /tmp
 // More specifically, this will occur after the chroot but before
@@ -19,8 +54,54 @@ use std::{convert::TryInto, env, ffi::CStr, fs::write, marker, path::Path, ptr};
 pub static REMOUNT_TMP: __u64 = 0x01;
 // UPDATE_PATH triggers whether or not to update the PATH environment
 // variable after the new user environment has been created, specifically
-// appends /usr/bin and /usr/local/bin
+// appends the directories of executables found in LEFT
 pub static UPDATE_PATH: __u64 = 0x02;
+// HOLD replaces the command with an init process that keeps the session
+// alive, so that commands can be spawned into it later on, ARGV is ignored
+pub static HOLD: __u64 = 0x04;
+// TTY runs the command on a pseudo-terminal of its own, as the leader of a
+// new session, with the terminal of the caller relayed to it
+pub static TTY: __u64 = 0x08;
+// NET_NONE unshares the network namespace, leaving the session without any
+// usable network interface
+pub static NET_NONE: __u64 = 0x10;
+// NET_LOOPBACK unshares the network namespace like NET_NONE, but brings up
+// the loopback interface
+pub static NET_LOOPBACK: __u64 = 0x20;
+// PRIVATE_IPC unshares the IPC namespace, isolating System V IPC objects
+// and POSIX message queues from the host
+pub static PRIVATE_IPC: __u64 = 0x40;
+// PRIVATE_UTS unshares the UTS namespace so the session has a hostname of
+// its own, see HOSTNAME
+pub static PRIVATE_UTS: __u64 = 0x80;
+// PRIVATE_TIME unshares the time namespace, offsetting the monotonic and
+// boottime clocks of the session by TIME_OFFSET
+pub static PRIVATE_TIME: __u64 = 0x100;
+// CGROUP places the session in a cgroup of its own, created beneath the
+// delegated cgroup v2 subtree of the user, to enforce MEMORY_MAX, CPU_WEIGHT
+// and PIDS_MAX, the resources used are reported once the session exits
+pub static CGROUP: __u64 = 0x200;
+// CLEAR_ENV clears the environment of the caller but for the variables of
+// ENV_KEEP, or those of `Env::KEEP` if there are none
+pub static CLEAR_ENV: __u64 = 0x400;
+// PREPEND_PATH is UPDATE_PATH prepending the directories instead, so that
+// the commands of the distribution take precedence over those of the host
+pub static PREPEND_PATH: __u64 = 0x800;
+// SYNC_IDENTITY binds the `passwd` and `group` of the distribution, merged
+// with the invoking user and its groups as known to the host, over the
+// originals, so that the user mapped back has a name
+pub static SYNC_IDENTITY: __u64 = 0x1000;
+// AS_ROOT leaves the command as `root`, skipping the user mapping back to
+// the invoking user, the subordinate IDs of the user are mapped as well when
+// newuidmap(1) and newgidmap(1) allow for it, so that package managers can
+// hand files out to other users
+pub static AS_ROOT: __u64 = 0x2000;
+// FAKE_OWNERSHIP records the owners the command gives files through chown(2)
+// in their `user.rootlesscontainers` extended attribute rather than changing
+// them, and reports them through stat(2), for the IDs the session cannot map.
+// Modes need no recording, the files belong to the session as far as the
+// kernel is concerned so chmod(2) applies as is
+pub static FAKE_OWNERSHIP: __u64 = 0x4000;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -35,13 +116,78 @@ pub struct unidis_attrs<'a>
     // ARGC is the number of command line arguments
     pub argc: uintptr_t,
     // ARGV is the command to run (replacing the process), in accordance
-    // to `execvp`, ARGV has to NUL-terminated
+    // to `execvp`, ARGV has to NUL-terminated, an empty ARGV runs the login
+    // shell of the user within the distribution instead
     pub argv: *const *const c_char,
     // FLAGS describe some operations that can modify the behavior of
     // unidis
     pub flags: __u64,
     // UNIONFS describes the union filesystem to use
     pub unionfs: SupportedUnionFS,
+    // SESSION is the identifier to record the session under, if NULL the
+    // PID of the session is used instead
+    pub session: *const c_char,
+    // HOSTNAME is the hostname of the session if PRIVATE_UTS is set, if NULL
+    // the name of the distribution in LEFT is used instead
+    pub hostname: *const c_char,
+    // TIME_OFFSET is the offset in seconds of the monotonic and boottime
+    // clocks if PRIVATE_TIME is set, the boottime clock cannot go negative
+    pub time_offset: i64,
+    // MEMORY_MAX is the memory limit of the session in bytes if CGROUP is
+    // set, 0 means unlimited
+    pub memory_max: __u64,
+    // CPU_WEIGHT is the CPU weight of the session in [1, 10000] if CGROUP is
+    // set, 0 keeps the default weight of 100
+    pub cpu_weight: __u64,
+    // PIDS_MAX is the limit of processes in the session if CGROUP is set, 0
+    // means unlimited
+    pub pids_max: __u64,
+    // SECCOMP is the seccomp profile to confine the command with, either
+    // `default`, `strict`, `none` or the path to a profile in the format of
+    // the runtime-spec of OCI, if NULL no profile is applied
+    pub seccomp: *const c_char,
+    // CAPS is the set of capabilities left to the command, as a bitmask of
+    // their numbers, every other capability is dropped from the bounding
+    // set and no_new_privs is set regardless
+    pub caps: __u64,
+    // LANDLOCK is the Landlock profile to confine the command with, either
+    // `untrusted`, `none` or the path to a JSON profile, if NULL no profile
+    // is applied
+    pub landlock: *const c_char,
+    // LANDLOCK_READ and LANDLOCK_WRITE are NULL-terminated lists of paths
+    // the command may respectively read and modify on top of LANDLOCK, they
+    // may be NULL
+    pub landlock_read: *const *const c_char,
+    pub landlock_write: *const *const c_char,
+    // MASKS is the profile of the paths to mask and make read-only within
+    // the session, either `default`, `none` or the path to a profile in the
+    // format of the runtime-spec of OCI, if NULL nothing is masked
+    pub masks: *const c_char,
+    // ENV is the profile describing the environment of the command, either
+    // `host`, `clean` or the path to a JSON profile, if NULL the environment
+    // of the caller is passed through, the fields and flags below add to it
+    pub env: *const c_char,
+    // ENV_KEEP, ENV_FILES, ENV_UNSET and ENV_SET are NULL-terminated lists,
+    // which may be NULL, of the variables kept by CLEAR_ENV, files of
+    // KEY=VALUE lines to load, variables to unset and KEY=VALUE pairs to set,
+    // applied in that order
+    pub env_keep: *const *const c_char,
+    pub env_files: *const *const c_char,
+    pub env_unset: *const *const c_char,
+    pub env_set: *const *const c_char,
+    // NIX_ENV is the path to the output of `nix print-dev-env --json` whose
+    // exported variables are loaded after ENV_FILES, ahead of PATH, if not
+    // NULL
+    pub nix_env: *const c_char,
+    // HOST_FILES is the profile listing the files of the host bound over
+    // those of LEFT, either `default`, `none` or the path to a JSON profile,
+    // if NULL none are, HOST_FILES_SKIP is a NULL-terminated list, which may
+    // be NULL, of the files to leave out of it
+    pub host_files: *const c_char,
+    pub host_files_skip: *const *const c_char,
+    // SHARE is a NULL-terminated list, which may be NULL, of the services of
+    // the desktop shared with the session, see `Share::Preset`
+    pub share: *const *const c_char,
 }
 
 /* pivot_root() switches to the new root. */
@@ -117,16 +263,120 @@ fn setup_mounts(mounts: &[Mount::Mount]) -> SyscallResult
     new_syscall_result(0, None)
 }
 
-/* init(UNIDIS_ATTRS) sets up the "container" given the configuration
-outlined in UNIDIS_ATTRS. */
+/* subid_mapping() maps the current user to root along with its subordinate
+IDs through the setuid helpers of shadow, which have to run from outside the
+user namespace, so a helper is forked beforehand and released once it is
+unshared.  Whether the helpers succeeded is returned, the namespace is
+unshared regardless. */
+fn subid_mapping(uid_map: &str, gid_map: &str) -> Result<bool, c_int>
+{
+    let pid = process::id().to_string();
+    let (release_r, release_w) = Libc::pipe2(O_CLOEXEC)?;
+
+    match Libc::fork()? {
+        0 => {
+            unsafe { close(release_w) };
+            let mut released = [0u8; 1];
+            unsafe { libc::read(release_r, released.as_mut_ptr() as *mut c_void, 1) };
+
+            let map = |helper: &str, map: &str| {
+                process::Command::new(helper)
+                    .arg(&pid)
+                    .args(map.split_whitespace())
+                    .status()
+                    .is_ok_and(|status| status.success())
+            };
+            let mapped = map("newuidmap", uid_map) && map("newgidmap", gid_map);
+            unsafe { _exit(if mapped { 0 } else { 1 }) };
+        }
+        helper => {
+            unsafe { close(release_r) };
+            let res = Libc::unshare(CLONE_NEWUSER);
+            unsafe { libc::write(release_w, [1u8].as_ptr() as *const c_void, 1) };
+            unsafe { close(release_w) };
+
+            let status = Libc::waitpid(helper.try_into().unwrap(), 0)? as c_int;
+            res?;
+            Ok(WIFEXITED(status) && WEXITSTATUS(status) == 0)
+        }
+    }
+}
+
+/* hold() stands in as the init process of the session in place of a
+command, reaping the processes spawned into the session until it is
+terminated. */
+fn hold() -> !
+{
+    extern "C" fn terminate(_: c_int)
+    {
+        unsafe { _exit(0) };
+    }
+    extern "C" fn reap(_: c_int) {}
+
+    unsafe {
+        signal(SIGTERM, terminate as extern "C" fn(c_int) as sighandler_t);
+        signal(SIGINT, terminate as extern "C" fn(c_int) as sighandler_t);
+        signal(SIGCHLD, reap as extern "C" fn(c_int) as sighandler_t);
+
+        // SIGCHLD is only delivered within sigsuspend(), so that no child
+        // can exit unnoticed between reaping and suspending
+        let mut mask: sigset_t = mem::zeroed();
+        sigemptyset(&mut mask);
+        sigaddset(&mut mask, SIGCHLD);
+        sigprocmask(SIG_BLOCK, &mask, ptr::null_mut());
+
+        let mut unblocked: sigset_t = mem::zeroed();
+        sigemptyset(&mut unblocked);
+        loop {
+            while libc::waitpid(-1, ptr::null_mut(), WNOHANG) > 0 {}
+            sigsuspend(&unblocked);
+        }
+    }
+}
+
+/* Setup is what the parent prepares for `init` from UNIDIS_ATTRS before the
+namespaces are entered, while the host is still reachable. */
+struct Setup<'a>
+{
+    unionfs: &'a dyn UnionFS::UnionFS,
+    revuidmap: &'a str,
+    revgidmap: &'a str,
+    cwd: &'a Path,
+    environment: &'a [(String, String)],
+    identity: Option<&'a Identity::Identity>,
+    host_files: Option<&'a HostFiles::Profile>,
+    share: &'a Share::Share,
+    // READY is written to once the session is set up
+    ready: c_int,
+    tty: Option<c_int>,
+    masks: Option<&'a Masks::Profile>,
+    landlock: Option<&'a Landlock::Profile>,
+    filter: Option<&'a [Libc::Seccomp::sock_filter]>,
+    oci: Option<&'a Oci::Config>,
+}
+
+/* init(UNIDIS_ATTRS, SETUP) sets up the "container" given the configuration
+outlined in UNIDIS_ATTRS and prepared in SETUP. */
 #[cfg(unix)]
-fn init(
-    unidis_attrs: *const unidis_attrs,
-    revuidmap: &str,
-    revgidmap: &str,
-    cwd: &Path,
-) -> SyscallResult
+fn init(unidis_attrs: *const unidis_attrs, setup: Setup) -> SyscallResult
 {
+    let Setup {
+        unionfs,
+        revuidmap,
+        revgidmap,
+        cwd,
+        environment,
+        identity,
+        host_files,
+        share,
+        ready,
+        tty,
+        masks,
+        landlock,
+        filter,
+        oci,
+    } = setup;
+
     // Setup mount namespace by fixing the propagation and remounting
     // /proc in case CLONE_NEWPID was given
     Libc::mount("none", "/", "", MS_REC | MS_SLAVE, ptr::null())?;
@@ -139,22 +389,42 @@ fn init(
     )?;
 
     // Mount unioned filesystem
-    let unionfs = get_union_filesystem(unsafe { (*unidis_attrs).unionfs });
     let left = unsafe { CStr::from_ptr((*unidis_attrs).left) };
     let right = unsafe { CStr::from_ptr((*unidis_attrs).right) };
-    let res = (*unionfs).union(left.to_str().unwrap(), right.to_str().unwrap());
+    let res = unionfs.union(left.to_str().unwrap(), right.to_str().unwrap());
     if res.is_err() {
         println!("{}", res.err().unwrap());
         return Err(EINVAL);
     }
 
+    // LEFT is no longer reachable after `pivot_root`, so look up the name
+    // of the distribution beforehand
+    let hostname = match unsafe { (*unidis_attrs).hostname.as_ref() } {
+        Some(hostname) => unsafe { CStr::from_ptr(hostname) }.to_string_lossy().into_owned(),
+        None => Distro::name(Path::new(OsStr::from_bytes(left.to_bytes()))),
+    };
+
+    // PLACEHOLDERS are the mountpoints created within the union, they are
+    // handed to the parent to be removed once the session exits
+    let mut placeholders = Vec::new();
+
     // Setup mounts in new root
-    let mnt = (*unionfs).mountpoint();
+    let mnt = unionfs.mountpoint();
     println!("Setting up unioned mountpoint at {:?}", mnt);
     if env::set_current_dir(mnt).is_err() {
         return Err(EINVAL);
     }
     setup_mounts(&Mount::MOUNTPOINTS)?;
+    if let Some(identity) = identity {
+        Identity::apply(identity)?;
+    }
+    if let Some(host_files) = host_files {
+        HostFiles::apply(host_files)?;
+    }
+    if let Some(oci) = oci {
+        Oci::setup_mounts(oci, &mut placeholders)?;
+    }
+    let share = Share::open(share);
 
     // Change to "new" root directory
     println!("Attempting pivot_root to mountpoint");
@@ -164,25 +434,54 @@ fn init(
     // is standard to prevent escapes from the new root directory
     println!("Executing chroot");
     Libc::chroot(&Path::new("."))?;
+    if let Some(oci) = oci {
+        Oci::remount_root(oci)?;
+    }
 
     println!("Remounting TMPDIR [/tmp]");
     if unsafe { (*unidis_attrs).flags } & REMOUNT_TMP != 0 {
         // Remount tmp directory
         Libc::mount("none", "tmp/", "tmpfs", 0, ptr::null())?;
     }
+    Share::bind(&share, &mut placeholders)?;
+
+    // Terminals are allocated from a devpts instance of the namespace, it
+    // has to be mounted while the mount namespace is still owned by us
+    if tty.is_some() {
+        setup_mounts(&Mount::DEVPTS)?;
+    }
+
+    // Hide what the fresh /proc exposes of the host
+    if let Some(masks) = masks {
+        Masks::apply(masks)?;
+    }
+
+    // Likewise, the UTS and network namespaces are owned by the first user
+    // namespace, so they are set up before it is left
+    let flags = unsafe { (*unidis_attrs).flags };
+    if flags & PRIVATE_UTS != 0 {
+        Libc::sethostname(&hostname)?;
+    }
+    if flags & NET_LOOPBACK != 0 {
+        Libc::link_up("lo")?;
+    }
 
     // Effectively reverse applied user mapping for "normality" which
     // requires a new user namespace, a requirement of this step is
     // the success of `pivot_root` for the root directory of the mount
-    // namespace to match
-    Libc::unshare(CLONE_NEWUSER)?;
-    user_mapping("self", revuidmap, revgidmap)?;
+    // namespace to match, unless `root` is kept as with AS_ROOT
+    if flags & AS_ROOT == 0 {
+        Libc::unshare(CLONE_NEWUSER)?;
+        user_mapping("self", revuidmap, revgidmap)?;
+    }
 
-    // Update PATH if requested
-    let update_path = unsafe { (*unidis_attrs).flags } & UPDATE_PATH != 0;
-    if update_path {
-        let old_path = env::var("PATH").unwrap_or(String::new());
-        env::set_var("PATH", old_path + ":/usr/bin:/usr/local/bin");
+    // The environment is replaced as a whole, the process of a bundle
+    // describes its own
+    match oci {
+        Some(oci) => {
+            Oci::setup_process(oci)?;
+        }
+        None => Env::apply(environment),
     }
 
     // Synchronize current working directory
@@ -190,15 +489,157 @@ fn init(
         return Err(EINVAL);
     }
 
-    // Replace running process with EXECUTABLE[ ARGV]
-    let executable = unsafe { *((*unidis_attrs).argv) as *const c_char };
+    // Without a command, the shell is looked up while /etc/passwd and
+    // $SHELL are those the command would see, and still readable
     let argv = unsafe { (*unidis_attrs).argv };
+    let shell = match argv.is_null() || unsafe { *argv }.is_null() {
+        true => Some(Identity::login_shell(unsafe { getuid() })),
+        false => None,
+    };
+
+    // Signal the parent that the session is set up, handing it the
+    // placeholders as NUL-terminated paths, prefixed by their length
+    let placeholders = placeholders
+        .iter()
+        .flat_map(|path| path.as_os_str().as_bytes().iter().chain(iter::once(&0)))
+        .copied()
+        .collect::<Vec<_>>();
+    let message = iter::once(1u8)
+        .chain((placeholders.len() as u32).to_ne_bytes())
+        .chain(placeholders)
+        .collect::<Vec<_>>();
+    let mut ready = unsafe { File::from_raw_fd(ready) };
+    let _ = ready.write_all(&message);
+    drop(ready);
+    if unsafe { (*unidis_attrs).flags } & HOLD != 0 {
+        hold();
+    }
+
+    // The supervisor of the ownership is left out of the confinement below
+    let ownership = match flags & FAKE_OWNERSHIP != 0 {
+        true => Some(Ownership::spawn()? as c_int),
+        false => None,
+    };
+
+    if let Some(tty) = tty {
+        Tty::attach(tty)?;
+    }
+
+    // The user mapped back still holds every capability in its namespace,
+    // which an ordinary login does not
+    Capabilities::restrict(unsafe { (*unidis_attrs).caps })?;
+
+    // Relative paths of the profile resolve against the working directory
+    if let Some(landlock) = landlock {
+        Landlock::apply(landlock)?;
+    }
+
+    // The filter goes last, so that it does not apply to the setup above
+    if let Some(ownership) = ownership {
+        Ownership::install(ownership)?;
+    }
+    if let Some(filter) = filter {
+        Seccomp::install(filter)?;
+    }
+
+    // Replace running process with the login shell, named after a dash as
+    // login(1) does so that it reads the profile of the distribution
+    if let Some(shell) = shell {
+        let name = shell.file_name().unwrap_or_default().as_bytes();
+        let name = CString::new([b"-", name].concat()).unwrap();
+        let shell = CString::new(shell.as_os_str().as_bytes()).unwrap();
+        return Libc::execvp(shell.as_ptr(), [name.as_ptr(), ptr::null()].as_ptr());
+    }
+
+    // Replace running process with EXECUTABLE[ ARGV]
+    let executable = unsafe { *argv as *const c_char };
     Libc::execvp(executable, argv)
 }
 
+/* plain_name() is whether NAME is a single component of a path, fit to be
+joined onto a directory without escaping it. */
+fn plain_name(name: &str) -> bool
+{
+    !name.is_empty() && name != "." && name != ".." && !name.contains(&['/', '\0'][..])
+}
+
+/* paths() collects the NULL-terminated list of paths PATHS, which may be
+NULL. */
+fn paths(mut paths: *const *const c_char) -> Vec<&'static Path>
+{
+    let mut res = Vec::new();
+    while !paths.is_null() && !unsafe { *paths }.is_null() {
+        let path = unsafe { CStr::from_ptr(*paths) };
+        res.push(Path::new(OsStr::from_bytes(path.to_bytes())));
+        paths = unsafe { paths.add(1) };
+    }
+
+    res
+}
+
+/* strings() collects the NULL-terminated list of strings STRINGS, which
+may be NULL. */
+fn strings(strings: *const *const c_char) -> Vec<String>
+{
+    paths(strings)
+        .into_iter()
+        .map(|string| string.to_string_lossy().into_owned())
+        .collect()
+}
+
+/* env_profile() loads the environment profile of UNIDIS_ATTRS, extended
+with its flags and the variables it lists. */
+fn env_profile(unidis_attrs: *const unidis_attrs) -> Result<Env::Profile, io::Error>
+{
+    let mut profile = match unsafe { (*unidis_attrs).env.as_ref() } {
+        None => Env::Profile::default(),
+        Some(name) => Env::profile(&unsafe { CStr::from_ptr(name) }.to_string_lossy())?,
+    };
+
+    profile.apply_flags(unsafe { (*unidis_attrs).flags });
+    profile.keep.extend(strings(unsafe { (*unidis_attrs).env_keep }));
+    profile.files.extend(
+        paths(unsafe { (*unidis_attrs).env_files })
+            .into_iter()
+            .map(Path::to_path_buf),
+    );
+    if let Some(nix) = unsafe { (*unidis_attrs).nix_env.as_ref() } {
+        let nix = OsStr::from_bytes(unsafe { CStr::from_ptr(nix) }.to_bytes());
+        profile.nix = Some(Path::new(nix).to_path_buf());
+    }
+    profile.unset.extend(strings(unsafe { (*unidis_attrs).env_unset }));
+    profile.set.extend(strings(unsafe { (*unidis_attrs).env_set }));
+
+    Ok(profile)
+}
+
+/* landlock_profile() loads the Landlock profile of UNIDIS_ATTRS, extended
+with the paths it lists. */
+fn landlock_profile(
+    unidis_attrs: *const unidis_attrs,
+) -> Result<Option<Landlock::Profile>, io::Error>
+{
+    let name = match unsafe { (*unidis_attrs).landlock.as_ref() } {
+        None => return Ok(None),
+        Some(name) => unsafe { CStr::from_ptr(name) }.to_string_lossy(),
+    };
+
+    let mut profile = match Landlock::profile(&name)? {
+        None => return Ok(None),
+        Some(profile) => profile,
+    };
+    profile.extend(
+        &paths(unsafe { (*unidis_attrs).landlock_read }),
+        &paths(unsafe { (*unidis_attrs).landlock_write }),
+    );
+
+    Ok(Some(profile))
+}
+
 /* isolate_namespace() is the unwrapped routine for the library, allowing for
 a cleaner `Result` implementation. */
-fn isolate_namespace() -> SyscallResult
+fn isolate_namespace(unidis_attrs: *const unidis_attrs, cgroup: Option<&Cgroup::Cgroup>)
+    -> SyscallResult
 {
     let gid_map = IDMap::newgidmap();
     let uid_map = IDMap::newuidmap();
@@ -209,12 +650,62 @@ fn isolate_namespace() -> SyscallResult
     // This is a tradeoff for readability, since the other approach
     // would require the child needed to wait for the parent to write
     // to /proc/[pid]/{setgroups,uid_map,gid_map} for a proper mapping
-    Libc::unshare(CLONE_NEWUSER)?;
-    user_mapping("self", &uid_map, &gid_map)?;
+    let attrs_flags = unsafe { (*unidis_attrs).flags };
+    let subid_maps = match attrs_flags & AS_ROOT != 0 {
+        true => IDMap::subuidmap().zip(IDMap::subgidmap()),
+        false => None,
+    };
+    let mapped = match subid_maps {
+        Some((subuid_map, subgid_map)) => Some(subid_mapping(&subuid_map, &subgid_map)?),
+        None => None,
+    };
+    match mapped {
+        Some(true) => {}
+        // The namespace is unshared already, the helpers failed to map it
+        Some(false) => {
+            println!("Failed to map subordinate IDs, mapping the current user only");
+            user_mapping("self", &uid_map, &gid_map)?;
+        }
+        None => {
+            Libc::unshare(CLONE_NEWUSER)?;
+            user_mapping("self", &uid_map, &gid_map)?;
+        }
+    }
+
+    // Unlike the other namespaces, the offsets of a time namespace can only
+    // be set before any process enters it, so it is unshared here for the
+    // clone below to be its first member
+    if attrs_flags & PRIVATE_TIME != 0 {
+        let offset = unsafe { (*unidis_attrs).time_offset };
+        Libc::unshare(CLONE_NEWTIME)?;
+        if let Err(error) = write(
+            "/proc/self/timens_offsets",
+            format!("monotonic {} 0\nboottime {} 0\n", offset, offset),
+        ) {
+            println!("Failed to write to /proc/self/timens_offsets, got {:?}", error);
+            return Err(error.raw_os_error().unwrap_or(EINVAL));
+        }
+    }
 
-    let flags = CLONE_NEWNS | CLONE_NEWPID;
+    let mut flags = CLONE_NEWNS | CLONE_NEWPID;
+    if attrs_flags & (NET_NONE | NET_LOOPBACK) != 0 {
+        flags |= CLONE_NEWNET;
+    }
+    if attrs_flags & PRIVATE_IPC != 0 {
+        flags |= CLONE_NEWIPC;
+    }
+    if attrs_flags & PRIVATE_UTS != 0 {
+        flags |= CLONE_NEWUTS;
+    }
+
+    // The session is cloned straight into its cgroup, so that nothing it
+    // spawns escapes the limits
+    let mut flags = flags as __u64;
+    if cgroup.is_some() {
+        flags |= CLONE_INTO_CGROUP;
+    }
     let clone_args = clone_args {
-        flags: flags as __u64,                     // Unshared namespaces
+        flags,                                     // Unshared namespaces
         pidfd: 0,                                  // See CLONE_PIDFD
         child_tid: 0,                              // See CLONE_CHILD_SETTID
         parent_tid: 0,                             // See CLONE_PARENT_SETTID
@@ -224,7 +715,7 @@ fn isolate_namespace() -> SyscallResult
         tls: 0,                                    // See CLONE_SETTLS
         set_tid: [1].as_ptr() as __u64,            // Root "init" process = PID 1
         set_tid_size: 1,                           // ^ Length 1 array
-        cgroup: 0,                                 // See CLONE_INTO_CGROUP
+        cgroup: cgroup.map_or(0, |cgroup| cgroup.fd() as __u64), // See CLONE_INTO_CGROUP
     };
 
     // Spawn a process into a separate user namespace as desired
@@ -234,26 +725,314 @@ fn isolate_namespace() -> SyscallResult
 #[no_mangle]
 pub extern "C" fn unidis(unidis_attrs: *const unidis_attrs) -> i64
 {
-    let revuidmap = IDMap::revuidmap();
-    let revgidmap = IDMap::revgidmap();
+    launch(unidis_attrs, None)
+}
+
+/* launch() runs the session described by UNIDIS_ATTRS, along with the
+configuration of the OCI bundle it was translated from, if any.  It returns
+the exit status of the command, 128 plus the signal which killed it, or the
+errno of what failed. */
+pub(crate) fn launch(unidis_attrs: *const unidis_attrs, oci: Option<&Oci::Config>) -> i64
+{
+    // The process of a bundle runs as the user it asks for, which has to be
+    // the one mapped to us, see `Oci::load`
+    let (revuidmap, revgidmap) = match oci {
+        Some(oci) => (oci.revuidmap(), oci.revgidmap()),
+        None => (IDMap::revuidmap(), IDMap::revgidmap()),
+    };
+
+    let cwd = oci.map_or_else(|| env::current_dir().unwrap(), |oci| oci.process.cwd.clone());
+
+    // Named sessions have to be unique among the running sessions
+    let session_id = unsafe { (*unidis_attrs).session.as_ref() }
+        .map(|session| unsafe { CStr::from_ptr(session) }.to_string_lossy().into_owned());
+    if let Some(session_id) = &session_id {
+        if !Session::valid_id(session_id) {
+            println!("Invalid session name {:?}", session_id);
+            return EINVAL.into();
+        }
+        if let Ok(session) = Session::Session::load(session_id) {
+            if session.is_alive() {
+                println!("Session {} is already running", session_id);
+                return EEXIST.into();
+            }
+        }
+    }
+
+    // The union is prepared by the parent, which is responsible for tearing
+    // it down once the session exits
+    let unionfs = get_union_filesystem(unsafe { (*unidis_attrs).unionfs });
+
+    // The cgroup has to be created before the user namespace is unshared,
+    // as the delegated subtree is owned by the user on the host
+    let cgroup = if unsafe { (*unidis_attrs).flags } & CGROUP != 0 {
+        let nonzero = |value: __u64| if value == 0 { None } else { Some(value) };
+        let limits = Cgroup::Limits {
+            memory_max: nonzero(unsafe { (*unidis_attrs).memory_max }),
+            cpu_weight: nonzero(unsafe { (*unidis_attrs).cpu_weight }),
+            pids_max: nonzero(unsafe { (*unidis_attrs).pids_max }),
+        };
+        match Cgroup::Cgroup::create(&format!("unidis-{}", process::id()), &limits) {
+            Err(error) => {
+                println!("Failed to create cgroup, got {:?}", error);
+                return error.raw_os_error().unwrap_or(EINVAL).into();
+            }
+            Ok(cgroup) => Some(cgroup),
+        }
+    } else {
+        None
+    };
+
+    // The seccomp profile is compiled upfront, so that a broken profile is
+    // reported before anything is set up, the profile itself is recorded
+    // along with the session for the commands attached to it
+    let (seccomp, filter) = match unsafe { (*unidis_attrs).seccomp.as_ref() } {
+        None => (None, None),
+        Some(seccomp) => {
+            let seccomp = unsafe { CStr::from_ptr(seccomp) }.to_string_lossy();
+            let profile = Seccomp::profile(&seccomp).and_then(|profile| {
+                let filter = profile.as_ref().map(Seccomp::compile).transpose()?;
+                Ok((profile, filter))
+            });
+            match profile {
+                Err(error) => {
+                    println!("Failed to load seccomp profile {}, got {:?}", seccomp, error);
+                    return error.raw_os_error().unwrap_or(EINVAL).into();
+                }
+                Ok(profile) => profile,
+            }
+        }
+    };
+
+    let masks = match unsafe { (*unidis_attrs).masks.as_ref() } {
+        None => None,
+        Some(masks) => {
+            let masks = unsafe { CStr::from_ptr(masks) }.to_string_lossy();
+            match Masks::profile(&masks) {
+                Err(error) => {
+                    println!("Failed to load masks profile {}, got {:?}", masks, error);
+                    return error.raw_os_error().unwrap_or(EINVAL).into();
+                }
+                Ok(profile) => profile,
+            }
+        }
+    };
+
+    let host_files = match unsafe { (*unidis_attrs).host_files.as_ref() } {
+        None => None,
+        Some(host_files) => {
+            let host_files = unsafe { CStr::from_ptr(host_files) }.to_string_lossy();
+            match HostFiles::profile(&host_files) {
+                Err(error) => {
+                    println!(
+                        "Failed to load host files profile {}, got {:?}",
+                        host_files, error
+                    );
+                    return error.raw_os_error().unwrap_or(EINVAL).into();
+                }
+                Ok(profile) => profile.map(|mut profile| {
+                    profile.exclude(&paths(unsafe { (*unidis_attrs).host_files_skip }));
+                    profile
+                }),
+            }
+        }
+    };
 
-    let cwd = env::current_dir().unwrap();
+    let share = match Share::presets(&strings(unsafe { (*unidis_attrs).share })) {
+        Err(error) => {
+            println!("Failed to share with the session, got {:?}", error);
+            return EINVAL.into();
+        }
+        Ok(presets) => Share::discover(&presets, &env::vars().collect::<Vec<_>>()),
+    };
+
+    // Files are read from the host, and the directories of LEFT looked up,
+    // before either is out of reach
+    let environment = if oci.is_some() {
+        Ok(Vec::new())
+    } else {
+        let left = unsafe { CStr::from_ptr((*unidis_attrs).left) };
+        env_profile(unidis_attrs).and_then(|mut profile| {
+            profile.share(&share.vars);
+            Env::build(
+                env::vars(),
+                &profile,
+                Path::new(OsStr::from_bytes(left.to_bytes())),
+            )
+        })
+    };
+    let environment = match environment {
+        Err(error) => {
+            println!("Failed to set up the environment, got {:?}", error);
+            return error.raw_os_error().unwrap_or(EINVAL).into();
+        }
+        Ok(environment) => environment,
+    };
+
+    // The name services of the host are out of reach once in the session,
+    // a user unknown to them is left without a name, `root` has one already
+    let identity = match oci {
+        None if unsafe { (*unidis_attrs).flags } & (SYNC_IDENTITY | AS_ROOT) == SYNC_IDENTITY => {
+            Identity::host()
+        }
+        _ => None,
+    };
+
+    let landlock = match landlock_profile(unidis_attrs) {
+        Err(error) => {
+            println!("Failed to load Landlock profile, got {:?}", error);
+            return error.raw_os_error().unwrap_or(EINVAL).into();
+        }
+        Ok(landlock) => landlock,
+    };
+
+    // READY is written to by the child once the session is set up
+    let (ready_r, ready_w) = match Libc::pipe2(O_CLOEXEC) {
+        Err(errno) => {
+            println!("Failed to create pipe, got {}", errno);
+            return errno.into();
+        }
+        Ok(ready) => ready,
+    };
+
+    // TTY carries the master side of the pseudo-terminal back from the
+    // child
+    let tty = if unsafe { (*unidis_attrs).flags } & TTY != 0 {
+        match Libc::socketpair(AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC) {
+            Err(errno) => {
+                println!("Failed to create socket pair, got {}", errno);
+                return errno.into();
+            }
+            Ok(tty) => Some(tty),
+        }
+    } else {
+        None
+    };
 
     // The child takes over the main execution process
-    match handle_syscall_result(isolate_namespace()) {
+    match handle_syscall_result(isolate_namespace(unidis_attrs, cgroup.as_ref())) {
         Err(errno) => errno.into(),
         Ok(pid) => match pid {
             // Child process routine
-            0 => match handle_syscall_result(init(unidis_attrs, &revuidmap, &revgidmap, &cwd)) {
-                Err(errno) => errno.into(),
-                // execvp should've replaced the running process if it succeeded and
-                // returned the errno() if it did not.
-                Ok(_) => unreachable!(),
-            },
-            pid => match Libc::waitpid(pid.try_into().unwrap(), 0) {
-                Err(errno) => errno.into(),
-                Ok(_) => 0,
-            },
+            0 => {
+                unsafe { close(ready_r) };
+                if let Some((tty, _)) = tty {
+                    unsafe { close(tty) };
+                }
+                let setup = Setup {
+                    unionfs: &*unionfs,
+                    revuidmap: &revuidmap,
+                    revgidmap: &revgidmap,
+                    cwd: &cwd,
+                    environment: &environment,
+                    identity: identity.as_ref(),
+                    host_files: host_files.as_ref(),
+                    share: &share,
+                    ready: ready_w,
+                    tty: tty.map(|(_, tty)| tty),
+                    masks: oci.map(|oci| &oci.linux.masks).or(masks.as_ref()),
+                    landlock: landlock.as_ref(),
+                    filter: filter.as_deref(),
+                    oci,
+                };
+                let res = handle_syscall_result(init(unidis_attrs, setup));
+
+                // The child shares the TempDir's of the union with the parent,
+                // yet it sees the host mounted beneath them, so removing them
+                // from here would recurse into the host
+                mem::forget(unionfs);
+
+                match res {
+                    Err(errno) => errno.into(),
+                    // execvp should've replaced the running process if it succeeded and
+                    // returned the errno() if it did not.
+                    Ok(_) => unreachable!(),
+                }
+            }
+            pid => {
+                let pid: pid_t = pid.try_into().unwrap();
+
+                // Wait for the session to be set up, the child closes READY
+                // without writing to it if it fails
+                unsafe { close(ready_w) };
+                let mut ready_r = unsafe { File::from_raw_fd(ready_r) };
+                let mut ready = [0u8; 1];
+                let ready = ready_r.read_exact(&mut ready).is_ok();
+                let mut placeholders = Vec::new();
+                let mut len = [0u8; 4];
+                if ready && ready_r.read_exact(&mut len).is_ok() {
+                    let mut paths = vec![0u8; u32::from_ne_bytes(len) as usize];
+                    if ready_r.read_exact(&mut paths).is_ok() {
+                        placeholders = paths
+                            .split(|byte| *byte == 0)
+                            .filter(|path| !path.is_empty())
+                            .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+                            .collect();
+                    }
+                }
+                drop(ready_r);
+
+                // Record the session to allow attaching to it
+                let session_id = session_id.unwrap_or_else(|| pid.to_string());
+                let left = unsafe { CStr::from_ptr((*unidis_attrs).left) };
+                let mut session = Session::Session::new(
+                    &session_id,
+                    pid,
+                    Path::new(OsStr::from_bytes(left.to_bytes())),
+                    unsafe { (*unidis_attrs).unionfs },
+                    &*unionfs,
+                    cgroup.as_ref().map(|cgroup| cgroup.path()),
+                    unsafe { (*unidis_attrs).caps },
+                );
+                session.seccomp = seccomp;
+                session.landlock = landlock.map(|mut landlock| {
+                    // Relative paths resolve against the working directory
+                    // of the command, which those attaching do not share
+                    for rule in &mut landlock.rules {
+                        rule.path = cwd.join(&rule.path);
+                    }
+                    landlock
+                });
+                session.environment = Some(environment).filter(|_| oci.is_none());
+                if ready {
+                    if let Err(error) = session.save() {
+                        println!("Failed to record session {}, got {:?}", session_id, error);
+                    }
+                }
+
+                if let Some((tty, child_tty)) = tty {
+                    unsafe { close(child_tty) };
+                    if let Some(master) = Tty::receive(tty) {
+                        Tty::relay(master, pid);
+                    }
+                }
+
+                let res = Libc::waitpid(pid, 0);
+                let _ = session.remove();
+
+                // The mountpoints created within the union persist in its
+                // read-write side, RIGHT
+                let right = unsafe { CStr::from_ptr((*unidis_attrs).right) };
+                let right = Path::new(OsStr::from_bytes(right.to_bytes()));
+                Share::remove_placeholders(right, &placeholders);
+
+                // The namespace is gone along with its init process, so
+                // the cgroup is empty by now
+                if let Some(cgroup) = &cgroup {
+                    cgroup.report();
+                    if let Err(error) = cgroup.remove() {
+                        println!("Failed to remove cgroup {:?}, got {:?}", cgroup.path(), error);
+                    }
+                }
+
+                match res {
+                    Err(errno) => errno.into(),
+                    Ok(status) if WIFSIGNALED(status as c_int) => {
+                        128 + WTERMSIG(status as c_int) as i64
+                    }
+                    Ok(status) => WEXITSTATUS(status as c_int) as i64,
+                }
+            }
         },
     }
 }
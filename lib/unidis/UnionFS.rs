@@ -1,11 +1,13 @@
 mod FuseUnionFS;
 
+use serde::{Deserialize, Serialize};
 use std::{io, path::Path};
 use strum_macros::{EnumString, EnumVariantNames};
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, EnumString, EnumVariantNames)]
+#[derive(Copy, Clone, Debug, EnumString, EnumVariantNames, Serialize, Deserialize)]
 #[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum SupportedUnionFS
 {
     // Kernel
@@ -18,6 +20,9 @@ pub trait UnionFS
     /* mountpoint() retrieves the Path at which the unioned filesystem
     resides. */
     fn mountpoint(&self) -> &Path;
+    /* workdirs() retrieves the directories on the host backing the unioned
+    filesystem, these are removed once the union is torn down. */
+    fn workdirs(&self) -> Vec<&Path>;
     /* union() combines two directories specified by LEFT and RIGHT.
     LEFT will always be read-only while RIGHT is always read-write. */
     fn union(&self, left: &str, right: &str) -> Result<(), io::Error>;
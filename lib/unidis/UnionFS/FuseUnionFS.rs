@@ -33,6 +33,11 @@ impl UnionFS for FuseUnionFS
         self.union_root.path()
     }
 
+    fn workdirs(&self) -> Vec<&Path>
+    {
+        vec![self.chroot_root.path(), self.union_root.path()]
+    }
+
     #[cfg(unix)]
     fn union(&self, left: &str, right: &str) -> Result<(), io::Error>
     {
@@ -106,9 +111,8 @@ impl UnionFS for FuseUnionFS
 
         cmd.unwrap().wait().expect("Command failed to execute");
 
-        // Ideally, resources would be manually release via drop() and
-        // <impl TempDir>::close, but this works fine as well, although
-        // it relies on out-of-scope behavior
+        // Resources are released once the parent drops the TempDir's,
+        // which only happens after the session exits
         Ok(())
     }
 }
@@ -0,0 +1,738 @@
+use crate::{Distro, Export, IDMap, Libc, Session};
+
+use libc::{CLONE_NEWNS, CLONE_NEWUSER, MS_REC, MS_SLAVE};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    ptr, thread,
+    time::{Duration, Instant},
+};
+use strum_macros::{EnumString, EnumVariantNames};
+
+/* QEMU is the emulator the virtual machines are run with. */
+const QEMU: &str = "qemu-system-x86_64";
+
+/* ESP_SIZE is the size of the disk image holding the EFI system partition,
+the root directory itself being shared through virtiofs. */
+const ESP_SIZE: &str = "1G";
+
+/* FIRMWARE are the locations distributions install the code and variables
+of OVMF to, in the order they are looked up. */
+const FIRMWARE: [(&str, &str); 6] = [
+    (
+        "/usr/share/OVMF/OVMF_CODE_4M.fd",
+        "/usr/share/OVMF/OVMF_VARS_4M.fd",
+    ),
+    (
+        "/usr/share/OVMF/OVMF_CODE.fd",
+        "/usr/share/OVMF/OVMF_VARS.fd",
+    ),
+    (
+        "/usr/share/edk2/ovmf/OVMF_CODE.fd",
+        "/usr/share/edk2/ovmf/OVMF_VARS.fd",
+    ),
+    (
+        "/usr/share/edk2/x64/OVMF_CODE.4m.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.4m.fd",
+    ),
+    (
+        "/usr/share/edk2-ovmf/x64/OVMF_CODE.fd",
+        "/usr/share/edk2-ovmf/x64/OVMF_VARS.fd",
+    ),
+    (
+        "/usr/share/qemu/edk2-x86_64-code.fd",
+        "/usr/share/qemu/edk2-i386-vars.fd",
+    ),
+];
+
+/* VIRTIOFSD_DIRS are the directories virtiofsd is installed to outside of
+PATH, in the order they are looked up. */
+const VIRTIOFSD_DIRS: [&str; 3] = ["/usr/libexec", "/usr/lib/qemu", "/usr/lib"];
+
+/* SOCKET_TIMEOUT is how long virtiofsd is waited for to listen on its
+socket. */
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/* Display is how the screen of the virtual machine is shown. */
+#[derive(Copy, Clone, Debug, PartialEq, EnumString, EnumVariantNames, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Display
+{
+    // A GTK window, accelerated through OpenGL
+    Gtk,
+    // An SDL window, accelerated through OpenGL
+    Sdl,
+    // No window, the serial console is attached to the terminal instead
+    None,
+}
+
+/* Forward is a port of the host forwarded to the guest. */
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Forward
+{
+    // PROTOCOL is either tcp or udp
+    pub protocol: String,
+    pub host: u16,
+    pub guest: u16,
+}
+
+impl Forward
+{
+    /* parse() parses SPEC, written as `[tcp|udp:]HOST:GUEST`. */
+    pub fn parse(spec: &str) -> Option<Forward>
+    {
+        let parts = spec.split(':').collect::<Vec<_>>();
+        let (protocol, host, guest) = match parts[..] {
+            [host, guest] => ("tcp", host, guest),
+            [protocol, host, guest] if protocol == "tcp" || protocol == "udp" => {
+                (protocol, host, guest)
+            }
+            _ => return None,
+        };
+
+        Some(Forward {
+            protocol: protocol.to_string(),
+            host: host.parse().ok()?,
+            guest: guest.parse().ok()?,
+        })
+    }
+}
+
+/* Options is how the virtual machine of a managed root is run. */
+#[derive(Clone, Debug)]
+pub struct Options
+{
+    // Memory of the guest in MiB, shared with virtiofsd
+    pub memory: u64,
+    pub cpus: u32,
+    pub display: Display,
+    pub forwards: Vec<Forward>,
+    // Installation ISO, the first one in the `iso` directory by default
+    pub iso: Option<PathBuf>,
+    // Directory holding OVMF_CODE.fd and OVMF_VARS.fd, see FIRMWARE
+    pub ovmf: Option<PathBuf>,
+    pub virtiofsd: Option<PathBuf>,
+    pub kvm: bool,
+}
+
+/* Error is why the virtual machine of a managed root cannot be run. */
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "kebab-case")]
+pub enum Error
+{
+    // The name is not fit for a managed root, see `Distro::valid_name`
+    InvalidName
+    {
+        name: String
+    },
+    // OVMF is found in neither of the paths
+    MissingFirmware
+    {
+        paths: Vec<PathBuf>
+    },
+    // virtiofsd is found in neither of the paths
+    MissingVirtiofsd
+    {
+        paths: Vec<PathBuf>
+    },
+}
+
+/* Vm is the virtual machine of a managed root, kept in DIR along with the
+root directory, as `uefi/OVMF_{CODE,VARS}.fd`, `boot.qcow2` and `iso/`. */
+#[derive(Debug)]
+pub struct Vm
+{
+    pub name: String,
+    pub dir: PathBuf,
+    pub socket: PathBuf,
+    // FIRMWARE is copied to `uefi` unless it was already
+    firmware: Option<(PathBuf, PathBuf)>,
+    virtiofsd: PathBuf,
+    iso: Option<PathBuf>,
+    options: Options,
+}
+
+/* firmware() looks up the code and variables of OVMF, within the directory
+OVMF if given. */
+fn firmware(ovmf: Option<&Path>) -> Result<(PathBuf, PathBuf), Error>
+{
+    let candidates = match ovmf {
+        Some(ovmf) => vec![(ovmf.join("OVMF_CODE.fd"), ovmf.join("OVMF_VARS.fd"))],
+        None => FIRMWARE
+            .iter()
+            .map(|(code, vars)| (PathBuf::from(code), PathBuf::from(vars)))
+            .collect(),
+    };
+
+    candidates
+        .iter()
+        .find(|(code, vars)| code.is_file() && vars.is_file())
+        .cloned()
+        .ok_or_else(|| Error::MissingFirmware {
+            paths: candidates.into_iter().map(|(code, _)| code).collect(),
+        })
+}
+
+/* virtiofsd() looks up virtiofsd, which is rarely installed to PATH. */
+fn virtiofsd() -> Result<PathBuf, Error>
+{
+    let host = Path::new("/");
+    Distro::find_command(host, "virtiofsd")
+        .or_else(|| {
+            VIRTIOFSD_DIRS
+                .iter()
+                .map(|dir| Path::new(dir).join("virtiofsd"))
+                .find(|path| path.is_file())
+        })
+        .ok_or_else(|| Error::MissingVirtiofsd {
+            paths: VIRTIOFSD_DIRS
+                .iter()
+                .map(|dir| Path::new(dir).join("virtiofsd"))
+                .collect(),
+        })
+}
+
+/* mac() is the MAC address of the virtual machine NAME, derived from its
+name for the guest to keep seeing the same network interface. */
+fn mac(name: &str) -> String
+{
+    // FNV-1a, unlike the hashers of std it is stable across releases
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("52:54:BE:EF:{:02X}:{:02X}", (hash >> 8) as u8, hash as u8)
+}
+
+/* smp() is the topology of CPUS, two threads to a core like the host
+usually is. */
+fn smp(cpus: u32) -> String
+{
+    match cpus % 2 {
+        0 => format!("{},sockets=1,cores={},threads=2", cpus, cpus / 2),
+        _ => format!("{},sockets=1,cores={},threads=1", cpus, cpus),
+    }
+}
+
+/* shell_word() quotes ARG for sh(1) only if it has to be. */
+fn shell_word(arg: &str) -> String
+{
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    match !arg.is_empty() && arg.chars().all(plain) {
+        true => arg.to_string(),
+        false => Export::quote(arg),
+    }
+}
+
+impl Vm
+{
+    /* new() describes the virtual machine of the managed root NAME, looking
+    up what it needs from the host. */
+    pub fn new(name: &str, options: Options) -> Result<Vm, Error>
+    {
+        let dir = Distro::managed(name).ok_or_else(|| Error::InvalidName {
+            name: name.to_string(),
+        })?;
+        let uefi = dir.join("uefi");
+
+        let firmware = match uefi.join("OVMF_CODE.fd").is_file() {
+            true => None,
+            false => Some(firmware(options.ovmf.as_deref())?),
+        };
+        let virtiofsd = match &options.virtiofsd {
+            Some(virtiofsd) => virtiofsd.clone(),
+            None => virtiofsd()?,
+        };
+
+        // Any ISO dropped into `iso` is booted from, see `-boot order=dc`
+        let iso = options.iso.clone().or_else(|| {
+            let mut isos = fs::read_dir(dir.join("iso"))
+                .into_iter()
+                .flatten()
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "iso")
+                })
+                .collect::<Vec<_>>();
+            isos.sort();
+            isos.into_iter().next()
+        });
+
+        let socket = Session::runtime_dir()
+            .with_file_name("vms")
+            .join(format!("{}.sock", name));
+
+        Ok(Vm {
+            name: name.to_string(),
+            dir,
+            socket,
+            firmware,
+            virtiofsd,
+            iso,
+            options,
+        })
+    }
+
+    /* image() is the disk image holding the EFI system partition. */
+    fn image(&self) -> PathBuf
+    {
+        self.dir.join("boot.qcow2")
+    }
+
+    /* image_command() is the command line creating the disk image, unless it
+    exists already. */
+    pub fn image_command(&self) -> Option<Vec<String>>
+    {
+        if self.image().exists() {
+            return None;
+        }
+
+        let image = self.image().to_string_lossy().into_owned();
+        let args = ["qemu-img", "create", "-f", "qcow2", "-o", "cluster_size=2M"];
+        let mut argv = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        argv.extend(vec![image, ESP_SIZE.to_string()]);
+        Some(argv)
+    }
+
+    /* virtiofsd_command() is the command line of virtiofsd sharing the root
+    directory, which is run as root within a user namespace. */
+    pub fn virtiofsd_command(&self) -> Vec<String>
+    {
+        let root = self.dir.join("root");
+        vec![
+            self.virtiofsd.to_string_lossy().into_owned(),
+            format!("--socket-path={}", self.socket.display()),
+            "-o".to_string(),
+            format!("source={}", root.display()),
+            // Some commands, such as passwd, fail without those
+            "-o".to_string(),
+            "flock".to_string(),
+            "-o".to_string(),
+            "posix_lock".to_string(),
+            "-o".to_string(),
+            "xattr".to_string(),
+        ]
+    }
+
+    /* qemu_command() is the command line of qemu booting the root directory
+    through UEFI, with the ESP on the disk image. */
+    pub fn qemu_command(&self) -> Vec<String>
+    {
+        let uefi = self.dir.join("uefi");
+        let memory = format!("{}M", self.options.memory);
+        let hostfwd = self
+            .options
+            .forwards
+            .iter()
+            .map(|forward| {
+                format!(
+                    ",hostfwd={}::{}-:{}",
+                    forward.protocol, forward.host, forward.guest
+                )
+            })
+            .collect::<String>();
+
+        let mut args = vec![
+            // Communication sockets
+            "-chardev".to_string(),
+            format!("socket,id=guest-root,path={}", self.socket.display()),
+            "-device".to_string(),
+            "vhost-user-fs-pci,queue-size=1024,chardev=guest-root,tag=root".to_string(),
+            // The memory has to be shared with virtiofsd
+            "-m".to_string(),
+            memory.clone(),
+            "-object".to_string(),
+            format!("memory-backend-memfd,id=mem,size={},share=on", memory),
+            "-numa".to_string(),
+            "node,memdev=mem".to_string(),
+            // Input devices
+            "-device".to_string(),
+            "pci-bridge,chassis_nr=2,id=bridge.1".to_string(),
+            "-device".to_string(),
+            "virtio-keyboard-pci,bus=bridge.1,addr=03.0".to_string(),
+            "-device".to_string(),
+            "virtio-mouse-pci,bus=bridge.1,addr=04.0".to_string(),
+            "-device".to_string(),
+            "virtio-tablet-pci,bus=bridge.1,addr=05.0".to_string(),
+            // Network passthrough
+            "-netdev".to_string(),
+            format!("user,id=vmnic{}", hostfwd),
+            "-device".to_string(),
+            format!("virtio-net-pci,netdev=vmnic,mac={}", mac(&self.name)),
+            // RNG passthrough
+            "-object".to_string(),
+            "rng-random,id=rng0,filename=/dev/random".to_string(),
+            "-device".to_string(),
+            "virtio-rng-pci,rng=rng0".to_string(),
+        ];
+
+        match self.options.display {
+            Display::Gtk => args.extend(vec![
+                "-vga".to_string(),
+                "virtio".to_string(),
+                "-display".to_string(),
+                "gtk,gl=on".to_string(),
+            ]),
+            Display::Sdl => args.extend(vec![
+                "-vga".to_string(),
+                "virtio".to_string(),
+                "-display".to_string(),
+                "sdl,gl=on".to_string(),
+            ]),
+            Display::None => args.push("-nographic".to_string()),
+        }
+
+        // Resource allocation, the CPU of the host is only passed through
+        // along with KVM
+        match self.options.kvm {
+            true => args.extend(vec![
+                "-enable-kvm".to_string(),
+                "-cpu".to_string(),
+                "host".to_string(),
+            ]),
+            false => args.extend(vec!["-cpu".to_string(), "max".to_string()]),
+        }
+        args.extend(vec!["-smp".to_string(), smp(self.options.cpus)]);
+
+        args.extend(vec![
+            "-boot".to_string(),
+            "order=dc,menu=on".to_string(),
+            // UEFI support
+            "-drive".to_string(),
+            format!(
+                "if=pflash,format=raw,readonly=on,file={}",
+                uefi.join("OVMF_CODE.fd").display()
+            ),
+            "-drive".to_string(),
+            format!(
+                "if=pflash,format=raw,file={}",
+                uefi.join("OVMF_VARS.fd").display()
+            ),
+            // Hard disk
+            "-drive".to_string(),
+            format!(
+                "if=none,id=vda0,format=qcow2,file={},{}",
+                self.image().display(),
+                "cache=none,cache.direct=on,aio=native,discard=unmap"
+            ),
+            "-object".to_string(),
+            "iothread,id=io1".to_string(),
+            "-device".to_string(),
+            "virtio-scsi-pci,ioeventfd=on,iothread=io1,num_queues=8".to_string(),
+            "-device".to_string(),
+            "scsi-hd,drive=vda0".to_string(),
+        ]);
+
+        // Installation ISO
+        if let Some(iso) = &self.iso {
+            args.push("-drive".to_string());
+            args.push(format!("file={},readonly=on,media=cdrom", iso.display()));
+        }
+
+        let mut argv = vec![QEMU.to_string()];
+        argv.extend(args);
+        argv
+    }
+
+    /* dry_run() is the command lines run() would run, quoted for sh(1). */
+    pub fn dry_run(&self) -> Vec<String>
+    {
+        self.image_command()
+            .into_iter()
+            .chain(vec![self.virtiofsd_command(), self.qemu_command()])
+            .map(|argv| {
+                argv.iter()
+                    .map(|arg| shell_word(arg))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    /* setup() lays out the directory of the virtual machine, copying OVMF
+    and creating the disk image unless that was done already. */
+    pub fn setup(&self) -> Result<(), io::Error>
+    {
+        fs::create_dir_all(self.dir.join("root"))?;
+
+        // The variables are written to by the guest, so each virtual
+        // machine has a copy of its own
+        if let Some((code, vars)) = &self.firmware {
+            let uefi = self.dir.join("uefi");
+            fs::create_dir_all(&uefi)?;
+            fs::copy(code, uefi.join("OVMF_CODE.fd"))?;
+            fs::copy(vars, uefi.join("OVMF_VARS.fd"))?;
+        }
+
+        if let Some(argv) = self.image_command() {
+            let status = Command::new(&argv[0]).args(&argv[1..]).status()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "Failed to create {:?}, got {}",
+                    self.image(),
+                    status
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /* run() sets up the virtual machine and runs it until qemu exits,
+    supervising virtiofsd along the way. */
+    pub fn run(&self) -> Result<ExitStatus, io::Error>
+    {
+        self.setup()?;
+
+        fs::create_dir_all(self.socket.parent().unwrap())?;
+        let _ = fs::remove_file(&self.socket);
+
+        // virtiofsd runs as root within a user namespace to share files of
+        // any owner, with a tmpfs over /var to write its PID file to
+        let uid_map = IDMap::newuidmap();
+        let gid_map = IDMap::newgidmap();
+        let argv = self.virtiofsd_command();
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        unsafe {
+            command.pre_exec(move || {
+                let error = |errno| io::Error::from_raw_os_error(errno);
+                Libc::unshare(CLONE_NEWUSER | CLONE_NEWNS).map_err(error)?;
+                crate::user_mapping("self", &uid_map, &gid_map).map_err(error)?;
+                Libc::mount("none", "/", "", MS_REC | MS_SLAVE, ptr::null()).map_err(error)?;
+                Libc::mount("none", "/var", "tmpfs", 0, ptr::null()).map_err(error)?;
+                Ok(())
+            })
+        };
+        let mut virtiofsd = command.spawn()?;
+
+        // qemu gives up right away on a socket nobody listens on
+        let start = Instant::now();
+        while !self.socket.exists() {
+            if let Some(status) = virtiofsd.try_wait()? {
+                return Err(io::Error::other(format!("virtiofsd exited with {}", status)));
+            }
+            if start.elapsed() > SOCKET_TIMEOUT {
+                let _ = virtiofsd.kill();
+                let _ = virtiofsd.wait();
+                return Err(io::Error::from(io::ErrorKind::TimedOut));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let argv = self.qemu_command();
+        let status = Command::new(&argv[0]).args(&argv[1..]).status();
+
+        // virtiofsd usually exits along with its only client
+        let _ = virtiofsd.kill();
+        let _ = virtiofsd.wait();
+        let _ = fs::remove_file(&self.socket);
+
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::{
+        env,
+        sync::{Mutex, MutexGuard},
+    };
+    use tempdir::TempDir;
+
+    /* ENV serializes the tests, which point XDG_DATA_HOME at a directory of
+    their own, it is held for the whole of each test. */
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /* lock() takes ENV, regardless of a test having panicked with it. */
+    fn lock() -> MutexGuard<'static, ()>
+    {
+        ENV.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    /* options() are the options of a virtual machine using the OVMF and
+    virtiofsd stubs of DATA. */
+    fn options(data: &Path) -> Options
+    {
+        Options {
+            memory: 2048,
+            cpus: 6,
+            display: Display::None,
+            forwards: vec![
+                Forward::parse("2222:22").unwrap(),
+                Forward::parse("udp:5353:53").unwrap(),
+            ],
+            iso: None,
+            ovmf: Some(data.join("ovmf")),
+            virtiofsd: Some(data.join("virtiofsd")),
+            kvm: true,
+        }
+    }
+
+    /* fixture() creates a data directory holding the managed root `test`,
+    along with stubs of OVMF and virtiofsd, and describes its virtual
+    machine with OPTIONS adjusted by ADJUST.  The caller holds ENV. */
+    fn fixture(adjust: impl FnOnce(&Path, &mut Options)) -> (TempDir, Result<Vm, Error>)
+    {
+        let data = TempDir::new("unidis-vm").unwrap();
+        env::set_var("XDG_DATA_HOME", data.path());
+        env::set_var("XDG_RUNTIME_DIR", data.path().join("run"));
+
+        fs::create_dir_all(Distro::managed("test").unwrap().join("root")).unwrap();
+        fs::create_dir_all(data.path().join("ovmf")).unwrap();
+        fs::write(data.path().join("ovmf/OVMF_CODE.fd"), "").unwrap();
+        fs::write(data.path().join("ovmf/OVMF_VARS.fd"), "").unwrap();
+        fs::write(data.path().join("virtiofsd"), "").unwrap();
+
+        let mut options = options(data.path());
+        adjust(data.path(), &mut options);
+        let vm = Vm::new("test", options);
+        (data, vm)
+    }
+
+    /* values() are the arguments following each occurrence of FLAG. */
+    fn values<'a>(argv: &'a [String], flag: &str) -> Vec<&'a str>
+    {
+        argv.windows(2)
+            .filter(|pair| pair[0] == flag)
+            .map(|pair| pair[1].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn qemu_command()
+    {
+        let _env = lock();
+        let (data, vm) = fixture(|_, _| ());
+        let vm = vm.unwrap();
+        let dir = data.path().join("unidis/distros/test");
+        let argv = vm.qemu_command();
+
+        assert_eq!(argv[0], QEMU);
+        assert_eq!(values(&argv, "-m"), ["2048M"]);
+        let backend = "memory-backend-memfd,id=mem,size=2048M,share=on";
+        assert!(values(&argv, "-object").contains(&backend));
+        assert_eq!(values(&argv, "-smp"), ["6,sockets=1,cores=3,threads=2"]);
+        assert_eq!(
+            values(&argv, "-netdev"),
+            ["user,id=vmnic,hostfwd=tcp::2222-:22,hostfwd=udp::5353-:53"]
+        );
+        // The MAC address stays the same from one build to the next
+        let nic = "virtio-net-pci,netdev=vmnic,mac=52:54:BE:EF:2B:25";
+        assert!(values(&argv, "-device").contains(&nic));
+        assert!(argv.contains(&"-nographic".to_string()));
+        assert!(argv.contains(&"-enable-kvm".to_string()));
+        assert_eq!(values(&argv, "-cpu"), ["host"]);
+
+        let drives = values(&argv, "-drive");
+        let code = format!("file={}", dir.join("uefi/OVMF_CODE.fd").display());
+        assert!(drives[0].ends_with(&code));
+        assert!(!drives.iter().any(|drive| drive.contains("media=cdrom")));
+        assert_eq!(
+            values(&argv, "-chardev"),
+            [format!("socket,id=guest-root,path={}", vm.socket.display())]
+        );
+    }
+
+    #[test]
+    fn no_kvm()
+    {
+        let _env = lock();
+        let (_data, vm) = fixture(|_, options| {
+            options.kvm = false;
+            options.cpus = 3;
+        });
+        let argv = vm.unwrap().qemu_command();
+
+        assert!(!argv.contains(&"-enable-kvm".to_string()));
+        assert_eq!(values(&argv, "-cpu"), ["max"]);
+        assert_eq!(values(&argv, "-smp"), ["3,sockets=1,cores=3,threads=1"]);
+    }
+
+    #[test]
+    fn virtiofsd_command()
+    {
+        let _env = lock();
+        let (data, vm) = fixture(|_, _| ());
+        let vm = vm.unwrap();
+        let argv = vm.virtiofsd_command();
+
+        assert_eq!(argv[0], data.path().join("virtiofsd").to_string_lossy());
+        assert_eq!(argv[1], format!("--socket-path={}", vm.socket.display()));
+        let root = data.path().join("unidis/distros/test/root");
+        assert_eq!(
+            values(&argv, "-o"),
+            [format!("source={}", root.display()).as_str(), "flock", "posix_lock", "xattr"]
+        );
+    }
+
+    #[test]
+    fn iso()
+    {
+        let _env = lock();
+        // The first ISO of the `iso` directory is booted from by default
+        let (_data, vm) = fixture(|data, _| {
+            let iso = data.join("unidis/distros/test/iso");
+            fs::create_dir_all(&iso).unwrap();
+            for file in ["b.iso", "a.iso", "notes.txt"].iter() {
+                fs::write(iso.join(file), "").unwrap();
+            }
+        });
+        let cdrom = |iso: PathBuf| format!("file={},readonly=on,media=cdrom", iso.display());
+        let vm = vm.unwrap();
+        let argv = vm.qemu_command();
+        let expected = cdrom(vm.dir.join("iso/a.iso"));
+        assert_eq!(values(&argv, "-drive").last(), Some(&expected.as_str()));
+
+        let (data, vm) = fixture(|data, options| options.iso = Some(data.join("other.iso")));
+        let argv = vm.unwrap().qemu_command();
+        let expected = cdrom(data.path().join("other.iso"));
+        assert_eq!(values(&argv, "-drive").last(), Some(&expected.as_str()));
+    }
+
+    #[test]
+    fn ovmf()
+    {
+        let _env = lock();
+        // OVMF is copied from the directory given, until it has been
+        let (data, vm) = fixture(|_, _| ());
+        let ovmf = data.path().join("ovmf");
+        assert_eq!(
+            vm.unwrap().firmware,
+            Some((ovmf.join("OVMF_CODE.fd"), ovmf.join("OVMF_VARS.fd")))
+        );
+
+        let (_data, vm) = fixture(|data, options| {
+            let uefi = data.join("unidis/distros/test/uefi");
+            fs::create_dir_all(&uefi).unwrap();
+            fs::write(uefi.join("OVMF_CODE.fd"), "").unwrap();
+            options.ovmf = Some(data.join("missing"));
+        });
+        assert_eq!(vm.unwrap().firmware, None);
+
+        let (data, vm) = fixture(|data, options| options.ovmf = Some(data.join("missing")));
+        match vm {
+            Err(Error::MissingFirmware { paths }) => {
+                assert_eq!(paths, [data.path().join("missing/OVMF_CODE.fd")])
+            }
+            vm => panic!("unexpected {:?}", vm),
+        }
+    }
+
+    #[test]
+    fn invalid_name()
+    {
+        let _env = lock();
+        let (data, _) = fixture(|_, _| ());
+        let options = options(data.path());
+        assert!(matches!(Vm::new("..", options), Err(Error::InvalidName { .. })));
+    }
+}